@@ -0,0 +1,265 @@
+//! # Actor消息处理
+//! `create_parallel_pipeline`和`pass_data_between_two_threads`需要手工连接发送端和接收端。
+//! 这里基于`crossbeam-channel`实现一个轻量的Actor模型：
+//! 每个Actor在自己的线程中独占状态，通过有类型的邮箱接收消息；
+//! 请求/应答使用容量为1的一次性通道；Actor处理消息时发生panic会被监督者按退避时间重启；
+//! 注册表可以按名字查找Actor。
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
+use std::any::Any;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+error_chain! {
+    errors {
+        MailboxClosed(name: String) {
+            description("actor mailbox closed")
+            display("Actor '{}' 的邮箱已经关闭", name)
+        }
+        NoReply(name: String) {
+            description("actor dropped the reply channel")
+            display("Actor '{}' 没有回复请求", name)
+        }
+        ReplyTimeout(name: String, timeout: Duration) {
+            description("actor reply timed out")
+            display("等待Actor '{}' 回复超时（{:?}）", name, timeout)
+        }
+    }
+}
+
+/// Actor独占自己的状态，在单独的线程中按顺序处理邮箱中的消息
+pub trait Actor: Send + 'static {
+    type Msg: Send + 'static;
+
+    fn handle(&mut self, msg: Self::Msg);
+}
+
+/// 请求/应答消息中携带的一次性回复通道
+pub type ReplyTo<T> = Sender<T>;
+
+/// 指向一个Actor邮箱的引用，可以克隆并在线程间传递
+pub struct ActorRef<M> {
+    name: String,
+    sender: Sender<M>,
+}
+
+impl<M> Clone for ActorRef<M> {
+    fn clone(&self) -> Self {
+        ActorRef {
+            name: self.name.clone(),
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<M: Send + 'static> ActorRef<M> {
+    /// 发送消息，不等待处理结果
+    pub fn tell(&self, msg: M) -> Result<()> {
+        self.sender
+            .send(msg)
+            .map_err(|_| ErrorKind::MailboxClosed(self.name.clone()).into())
+    }
+
+    /// 发送请求并阻塞等待回复。`make_msg`用一次性回复通道构造消息。
+    pub fn ask<R>(&self, make_msg: impl FnOnce(ReplyTo<R>) -> M) -> Result<R> {
+        let (reply_to, reply) = bounded(1);
+        self.tell(make_msg(reply_to))?;
+        reply
+            .recv()
+            .map_err(|_| ErrorKind::NoReply(self.name.clone()).into())
+    }
+
+    /// 和`ask`相同，但最多等待`timeout`
+    pub fn ask_timeout<R>(
+        &self,
+        make_msg: impl FnOnce(ReplyTo<R>) -> M,
+        timeout: Duration,
+    ) -> Result<R> {
+        let (reply_to, reply) = bounded(1);
+        self.tell(make_msg(reply_to))?;
+        reply.recv_timeout(timeout).map_err(|err| {
+            if err.is_timeout() {
+                ErrorKind::ReplyTimeout(self.name.clone(), timeout).into()
+            } else {
+                ErrorKind::NoReply(self.name.clone()).into()
+            }
+        })
+    }
+}
+
+/// 监督策略：Actor处理消息时panic后，用`factory`重新创建状态并重启，
+/// 第n次重启前等待`base_backoff * 2^(n-1)`，最长`max_backoff`。
+/// 超过`max_restarts`次后Actor停止，邮箱随之关闭。
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    pub max_restarts: u32,
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy {
+            max_restarts: 3,
+            base_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// 在新线程中启动一个受监督的Actor。
+/// 所有`ActorRef`被丢弃后邮箱关闭，线程处理完剩余消息后退出。
+pub fn spawn<A, F>(
+    name: &str,
+    factory: F,
+    policy: RestartPolicy,
+) -> (ActorRef<A::Msg>, thread::JoinHandle<()>)
+where
+    A: Actor,
+    F: Fn() -> A + Send + 'static,
+{
+    let (sender, mailbox) = unbounded();
+    let actor_name = name.to_string();
+    let handle = thread::spawn(move || supervise(&actor_name, factory, policy, mailbox));
+
+    (
+        ActorRef {
+            name: name.to_string(),
+            sender,
+        },
+        handle,
+    )
+}
+
+fn supervise<A, F>(name: &str, factory: F, policy: RestartPolicy, mailbox: Receiver<A::Msg>)
+where
+    A: Actor,
+    F: Fn() -> A,
+{
+    let mut actor = factory();
+    let mut restarts = 0;
+
+    for msg in mailbox.iter() {
+        if panic::catch_unwind(AssertUnwindSafe(|| actor.handle(msg))).is_ok() {
+            continue;
+        }
+
+        if restarts >= policy.max_restarts {
+            println!(
+                "Actor '{}' 重启次数超过{}次，停止运行",
+                name, policy.max_restarts
+            );
+            return;
+        }
+
+        let backoff = policy
+            .base_backoff
+            .saturating_mul(1 << restarts.min(16))
+            .min(policy.max_backoff);
+        restarts += 1;
+        println!(
+            "Actor '{}' 发生panic，{:?}后第{}次重启",
+            name, backoff, restarts
+        );
+        thread::sleep(backoff);
+        actor = factory();
+    }
+}
+
+/// # Actor注册表
+/// 按名字保存`ActorRef`，查找时需要指定消息类型。
+#[derive(Default)]
+pub struct Registry {
+    actors: Mutex<HashMap<String, Box<dyn Any + Send>>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<M: Send + 'static>(&self, actor: ActorRef<M>) {
+        self.actors
+            .lock()
+            .unwrap()
+            .insert(actor.name.clone(), Box::new(actor));
+    }
+
+    /// 按名字查找Actor，名字不存在或者消息类型不匹配时返回`None`
+    pub fn lookup<M: Send + 'static>(&self, name: &str) -> Option<ActorRef<M>> {
+        self.actors
+            .lock()
+            .unwrap()
+            .get(name)
+            .and_then(|actor| actor.downcast_ref::<ActorRef<M>>())
+            .cloned()
+    }
+
+    /// 从注册表中移除Actor，返回是否存在
+    pub fn unregister(&self, name: &str) -> bool {
+        self.actors.lock().unwrap().remove(name).is_some()
+    }
+}
+
+pub enum FruitMsg {
+    Insert(String),
+    List(ReplyTo<Vec<String>>),
+}
+
+/// 用Actor代替`lazy_static`全局`FRUIT`存储，状态只由Actor线程访问，不再需要`Mutex`
+#[derive(Default)]
+struct FruitStore {
+    fruits: Vec<String>,
+}
+
+impl Actor for FruitStore {
+    type Msg = FruitMsg;
+
+    fn handle(&mut self, msg: FruitMsg) {
+        match msg {
+            FruitMsg::Insert(fruit) => {
+                assert!(!fruit.is_empty(), "水果名称不能为空");
+                self.fruits.push(fruit);
+            }
+            FruitMsg::List(reply_to) => {
+                let _ = reply_to.send(self.fruits.clone());
+            }
+        }
+    }
+}
+
+/// # 使用Actor保持可变状态
+/// 和`maintain_global_mutable_state`相同的例子，水果列表保存在Actor中，
+/// 其他代码通过注册表按名字找到Actor，用消息插入和读取数据。
+pub fn fruit_store_actor() -> Result<()> {
+    let registry = Registry::new();
+    let (fruit, handle) = spawn("fruit", FruitStore::default, RestartPolicy::default());
+    registry.register(fruit);
+
+    let store = registry
+        .lookup::<FruitMsg>("fruit")
+        .ok_or("注册表中没有找到fruit")?;
+    for name in &["apple", "orange", "peach"] {
+        store.tell(FruitMsg::Insert(name.to_string()))?;
+    }
+    store
+        .ask(FruitMsg::List)?
+        .iter()
+        .enumerate()
+        .for_each(|(i, item)| println!("数据 {}: {}", i, item));
+    store.tell(FruitMsg::Insert("grape".to_string()))?;
+    assert_eq!(store.ask(FruitMsg::List)?.len(), 4);
+
+    // 空名称会让Actor panic，监督者用新的空状态重启Actor，邮箱仍然可用
+    store.tell(FruitMsg::Insert(String::new()))?;
+    let fruits = store.ask_timeout(FruitMsg::List, Duration::from_secs(1))?;
+    println!("重启后的数据：{:?}", fruits);
+    assert!(fruits.is_empty());
+
+    drop(store);
+    registry.unregister("fruit");
+    handle.join().map_err(|_| "Actor线程异常退出")?;
+    Ok(())
+}
@@ -6,6 +6,8 @@ extern crate crossbeam_channel;
 use crossbeam_channel::{bounded, unbounded};
 // use image::{ImageBuffer, Pixel, Rgb};
 use lazy_static::lazy_static;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 // use num::complex::Complex;
 // use ring::digest::{Context, Digest, SHA256};
 // use std::fs::File;
@@ -13,8 +15,8 @@ use lazy_static::lazy_static;
 // use std::path::Path;
 // use std::sync::mpsc::{channel, RecvError};
 use std::sync::Mutex;
+use std::thread;
 use std::time::Duration;
-use std::{thread, time};
 // use threadpool::ThreadPool;
 // use walkdir::WalkDir;
 // use crate::parallel_tasks;
@@ -69,31 +71,38 @@ fn find_max(arr: &[i32]) -> Option<i32> {
 /// 由于通道在`crossbeam::scope`中创建，我们必须手工通过`drop`关闭它以防止整个程序被阻塞在工作者for循环等待中。
 /// 我们可以考虑在没有信息被送达时以信号的方式调用`drop`
 pub fn create_parallel_pipeline() {
+    run_pipeline(4, 2, &Schedule::Sleep(Duration::from_millis(500)));
+}
+
+/// 并行通道的实现，返回接收器按到达顺序收到的数据。
+/// `schedule`决定工作线程开始前的等待方式，测试工具用它在不同调度下重复运行。
+pub fn run_pipeline(n_msgs: i32, n_workers: usize, schedule: &Schedule) -> Vec<i32> {
     let (snd1, rcv1) = bounded(1);
     let (snd2, rcv2) = bounded(1);
-
-    let n_msgs = 4;
-    let n_workers = 2;
+    let mut results = vec![];
 
     crossbeam::scope(|s| {
         // 发送源
         s.spawn(|_| {
             for i in 0..n_msgs {
+                schedule.interleave(i as u64);
                 snd1.send(i).unwrap();
-                println!("源，发送{}", i);
+                schedule.trace(|| format!("源，发送{}", i));
             }
 
             drop(snd1);
         });
 
-        // 并行处理，2个工作线程
-        for _ in 0..n_workers {
+        // 并行处理的工作线程
+        for worker in 0..n_workers {
             let (sendr, recvr) = (snd2.clone(), rcv1.clone());
 
             s.spawn(move |_| {
-                thread::sleep(Duration::from_millis(500));
+                schedule.delay(1000 + worker as u64);
                 for msg in recvr.iter() {
-                    println!("工作者{:?} 接收信息 {}", thread::current().id(), msg);
+                    schedule
+                        .trace(|| format!("工作者{:?} 接收信息 {}", thread::current().id(), msg));
+                    schedule.interleave(2000 + (worker as u64) * 1000 + msg as u64);
                     sendr.send(msg * 2).unwrap();
                 }
             });
@@ -102,32 +111,82 @@ pub fn create_parallel_pipeline() {
         drop(snd2);
 
         for msg in rcv2.iter() {
-            println!("消耗接收数据 {}", msg);
+            schedule.trace(|| format!("消耗接收数据 {}", msg));
+            results.push(msg);
         }
     })
     .unwrap();
+
+    results
 }
 
 /// # 两个线程间传输数据
 /// 下面的例子验证了在一个创建者和一个消费者（SPSC）环境下使用`crossbeam-channel`。
 ///
 pub fn pass_data_between_two_threads() {
+    for msg in transfer(5, &Schedule::Sleep(Duration::from_millis(100))) {
+        println!("收到信息：{}", msg);
+    }
+}
+
+/// 单生产者单消费者传输的实现，返回消费者收到的数据
+pub fn transfer(n_msgs: i32, schedule: &Schedule) -> Vec<i32> {
     let (snd, rcv) = unbounded();
-    let n_msgs = 5;
 
     crossbeam::scope(|s| {
         s.spawn(|_| {
             for i in 0..n_msgs {
                 snd.send(i).unwrap();
-                thread::sleep(time::Duration::from_millis(100));
+                schedule.delay(i as u64);
             }
         });
     })
     .unwrap();
 
-    for _ in 0..n_msgs {
-        let msg = rcv.recv().unwrap();
-        println!("收到信息：{}", msg);
+    (0..n_msgs).map(|_| rcv.recv().unwrap()).collect()
+}
+
+/// 线程在调度点上的行为。
+/// 例子使用`Sleep`在原来调用`thread::sleep`的位置固定睡眠；
+/// 测试工具使用`Seeded`，在每个调度点根据种子和位置决定是否让出CPU或者短暂睡眠，
+/// 同一个种子每次产生相同的延迟序列，发现问题后可以用种子重现。
+/// 种子只是对调度的随机抽样，不能覆盖所有的交错顺序。
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    Sleep(Duration),
+    Seeded(u64),
+}
+
+impl Schedule {
+    /// 例子原本调用`thread::sleep`的位置
+    fn delay(&self, point: u64) {
+        match self {
+            Schedule::Sleep(duration) => thread::sleep(*duration),
+            Schedule::Seeded(seed) => perturb(*seed, point),
+        }
+    }
+
+    /// 例子打印每一步的输出，测试工具不打印
+    fn trace<F: FnOnce() -> String>(&self, line: F) {
+        if let Schedule::Sleep(_) = self {
+            println!("{}", line());
+        }
+    }
+
+    /// 额外的调度点，只在测试工具中生效
+    fn interleave(&self, point: u64) {
+        if let Schedule::Seeded(seed) = self {
+            perturb(*seed, point);
+        }
+    }
+}
+
+fn perturb(seed: u64, point: u64) {
+    let mut rng = StdRng::seed_from_u64(seed ^ point.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    match rng.gen_range(0..4) {
+        0 => {}
+        1 => thread::yield_now(),
+        _ => thread::sleep(Duration::from_micros(rng.gen_range(1..200))),
     }
 }
 
@@ -0,0 +1,384 @@
+//! # 线程例子的调度测试
+//! `explicit_threads`中的例子只在`main`中按一种调度运行一次，像忘记`drop(snd2)`这样的错误
+//! 只有在程序卡住时才会被发现。这里从两方面检查：
+//! - 模型检查：测试中的`model`模块用`loom`的`Mutex`和`Condvar`实现了和`crossbeam_channel::bounded`
+//!   语义相同的通道（满了阻塞发送，所有发送端关闭并且取空之后接收结束），按`run_pipeline`的步骤
+//!   搭建同样的流水线，由`loom`穷举两次抢占之内的所有线程交错。每种交错下都检查结果，所有线程都阻塞时`loom`报告死锁，
+//!   同一个模型每次检查的交错都相同，不依赖运气；
+//! - 实际运行：`loom`不能检查`crossbeam`本身，所以用不同的种子在调度点注入让出和短暂睡眠，
+//!   重复运行真正的例子，用看门狗在限定时间内发现死锁。出现问题时打印种子，可以用同一个种子重现。
+//!
+//! `cargo test`运行下面的测试，死锁或者结果错误都会让测试失败。
+use crate::explicit_threads::{run_pipeline, transfer, Schedule};
+use crossbeam_channel::{bounded, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+error_chain! {
+    errors {
+        Deadlock(case: String, timeout: Duration) {
+            description("concurrent example did not finish in time")
+            display("{}在{:?}内没有结束，可能发生了死锁", case, timeout)
+        }
+        Panicked(case: String) {
+            description("concurrent example panicked")
+            display("{}运行时发生panic", case)
+        }
+        Mismatch(case: String, found: Vec<i32>) {
+            description("concurrent example produced wrong results")
+            display("{}的结果不正确：{:?}", case, found)
+        }
+    }
+}
+
+const SEEDS: u64 = 200;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 在单独的线程中运行`f`，超过`timeout`没有返回就认为发生了死锁。
+/// 死锁的线程无法被终止，只能留在后台直到进程退出。
+fn with_watchdog<T, F>(case: String, timeout: Duration, f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let (done, finished) = bounded(1);
+    thread::spawn(move || {
+        let _ = done.send(f());
+    });
+
+    finished.recv_timeout(timeout).map_err(|err| match err {
+        RecvTimeoutError::Timeout => ErrorKind::Deadlock(case, timeout).into(),
+        RecvTimeoutError::Disconnected => ErrorKind::Panicked(case).into(),
+    })
+}
+
+/// 忘记`drop(snd2)`的流水线：接收循环永远等不到通道关闭
+fn pipeline_forgetting_drop(n_msgs: i32) -> Vec<i32> {
+    let (snd1, rcv1) = bounded(1);
+    let (snd2, rcv2) = bounded(1);
+
+    crossbeam::scope(|s| {
+        s.spawn(move |_| {
+            for i in 0..n_msgs {
+                snd1.send(i).unwrap();
+            }
+        });
+
+        let (sendr, recvr) = (snd2.clone(), rcv1);
+        s.spawn(move |_| {
+            for msg in recvr.iter() {
+                sendr.send(msg * 2).unwrap();
+            }
+        });
+
+        rcv2.iter().collect()
+    })
+    .unwrap()
+}
+
+/// 在种子`seed`的调度下运行流水线和数据传输，检查结果
+fn check_seed(seed: u64) -> Result<()> {
+    let n_workers = 1 + (seed % 4) as usize;
+    let case = format!("种子{}下的流水线", seed);
+    let mut results = with_watchdog(case.clone(), TIMEOUT, move || {
+        run_pipeline(8, n_workers, &Schedule::Seeded(seed))
+    })?;
+    results.sort_unstable();
+    if results != (0..8).map(|i| i * 2).collect::<Vec<_>>() {
+        bail!(ErrorKind::Mismatch(case, results));
+    }
+
+    let case = format!("种子{}下的数据传输", seed);
+    let received = with_watchdog(case.clone(), TIMEOUT, move || {
+        transfer(5, &Schedule::Seeded(seed))
+    })?;
+    if received != (0..5).collect::<Vec<_>>() {
+        bail!(ErrorKind::Mismatch(case, received));
+    }
+    Ok(())
+}
+
+/// 看门狗必须能够发现忘记关闭发送端造成的死锁
+fn detect_forgotten_drop() -> Result<()> {
+    let leaked = with_watchdog(
+        "忘记drop的流水线".to_string(),
+        Duration::from_millis(200),
+        || pipeline_forgetting_drop(4),
+    );
+    match leaked {
+        Err(err @ Error(ErrorKind::Deadlock(..), _)) => {
+            println!("看门狗发现死锁：{}", err);
+            Ok(())
+        }
+        _ => bail!("看门狗没有发现忘记drop造成的死锁"),
+    }
+}
+
+/// # 在不同调度下运行线程例子
+/// 对每个种子检查：
+/// - 流水线在工作线程结束后能够关闭，并且每条数据被恰好处理一次；
+/// - 单生产者单消费者通道保持发送顺序。
+///
+/// 最后确认看门狗能够发现忘记关闭发送端造成的死锁。
+pub fn explore_interleavings() -> Result<()> {
+    for seed in 0..SEEDS {
+        check_seed(seed)?;
+    }
+    println!("{}个调度下线程例子均正常结束", SEEDS);
+
+    detect_forgotten_drop()
+}
+
+/// `loom`下的通道和流水线模型，只在测试中使用：`loom`的同步原语只能在`loom::model`中运行
+#[cfg(test)]
+mod model {
+    use loom::sync::{Arc, Condvar, Mutex};
+    use loom::thread;
+    use std::collections::VecDeque;
+
+    struct State<T> {
+        queue: VecDeque<T>,
+        capacity: usize,
+        senders: usize,
+        receivers: usize,
+    }
+
+    struct Shared<T> {
+        state: Mutex<State<T>>,
+        changed: Condvar,
+    }
+
+    pub struct Sender<T>(Arc<Shared<T>>);
+    pub struct Receiver<T>(Arc<Shared<T>>);
+
+    /// 和`crossbeam_channel::bounded`相同：满了阻塞发送，所有接收端关闭后发送失败，
+    /// 所有发送端关闭并且取空之后接收返回`None`
+    pub fn bounded<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                capacity,
+                senders: 1,
+                receivers: 1,
+            }),
+            changed: Condvar::new(),
+        });
+        (Sender(shared.clone()), Receiver(shared))
+    }
+
+    impl<T> Sender<T> {
+        pub fn send(&self, value: T) -> Result<(), T> {
+            let mut state = self.0.state.lock().unwrap();
+            loop {
+                if state.receivers == 0 {
+                    return Err(value);
+                }
+                if state.queue.len() < state.capacity {
+                    state.queue.push_back(value);
+                    self.0.changed.notify_all();
+                    return Ok(());
+                }
+                state = self.0.changed.wait(state).unwrap();
+            }
+        }
+    }
+
+    impl<T> Clone for Sender<T> {
+        fn clone(&self) -> Self {
+            self.0.state.lock().unwrap().senders += 1;
+            Sender(self.0.clone())
+        }
+    }
+
+    impl<T> Drop for Sender<T> {
+        fn drop(&mut self) {
+            let mut state = self.0.state.lock().unwrap();
+            state.senders -= 1;
+            if state.senders == 0 {
+                self.0.changed.notify_all();
+            }
+        }
+    }
+
+    impl<T> Receiver<T> {
+        pub fn recv(&self) -> Option<T> {
+            let mut state = self.0.state.lock().unwrap();
+            loop {
+                if let Some(value) = state.queue.pop_front() {
+                    self.0.changed.notify_all();
+                    return Some(value);
+                }
+                if state.senders == 0 {
+                    return None;
+                }
+                state = self.0.changed.wait(state).unwrap();
+            }
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+            std::iter::from_fn(move || self.recv())
+        }
+    }
+
+    impl<T> Clone for Receiver<T> {
+        fn clone(&self) -> Self {
+            self.0.state.lock().unwrap().receivers += 1;
+            Receiver(self.0.clone())
+        }
+    }
+
+    impl<T> Drop for Receiver<T> {
+        fn drop(&mut self) {
+            let mut state = self.0.state.lock().unwrap();
+            state.receivers -= 1;
+            if state.receivers == 0 {
+                self.0.changed.notify_all();
+            }
+        }
+    }
+
+    /// 按`run_pipeline`的步骤搭建流水线；`forget_drop`为真时不关闭接收器之前的`snd2`
+    pub fn pipeline(n_msgs: i32, n_workers: usize, forget_drop: bool) -> Vec<i32> {
+        let (snd1, rcv1) = bounded(1);
+        let (snd2, rcv2) = bounded(1);
+
+        let source = thread::spawn(move || {
+            for i in 0..n_msgs {
+                snd1.send(i).unwrap();
+            }
+            drop(snd1);
+        });
+
+        let workers: Vec<_> = (0..n_workers)
+            .map(|_| {
+                let (sendr, recvr) = (snd2.clone(), rcv1.clone());
+                thread::spawn(move || {
+                    for msg in recvr.iter() {
+                        sendr.send(msg * 2).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        let kept = match forget_drop {
+            true => Some(snd2),
+            false => {
+                drop(snd2);
+                None
+            }
+        };
+
+        let results = rcv2.iter().collect();
+        drop(kept);
+        source.join().unwrap();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 抢占次数限制为2：`loom`的文档指出大部分并发错误在两次抢占之内就会出现，
+    /// 不限制时交错的数量随线程和消息数量爆炸，两个工作线程处理两条消息就要运行几十分钟。
+    /// 所以每个模型只用最少的线程和消息覆盖一种关闭情形
+    fn check(f: impl Fn() + Sync + Send + 'static) {
+        let mut builder = loom::model::Builder::new();
+        builder.preemption_bound = Some(2);
+        builder.check(f);
+    }
+
+    #[test]
+    fn model_pipeline_delivers_every_message_and_shuts_down() {
+        check(|| {
+            // 源线程在工作线程处理第一条消息时发送第二条并关闭通道
+            assert_eq!(model::pipeline(2, 1, false), [0, 2]);
+        });
+    }
+
+    #[test]
+    fn model_pipeline_with_idle_worker_shuts_down() {
+        // 两个工作线程竞争一条消息，没有拿到数据的那个也必须在通道关闭后退出
+        check(|| {
+            assert_eq!(model::pipeline(1, 2, false), [0]);
+        });
+        check(|| {
+            assert!(model::pipeline(0, 2, false).is_empty());
+        });
+    }
+
+    #[test]
+    #[ignore = "由model_detects_forgotten_drop在子进程中运行"]
+    fn forgotten_drop_model() {
+        check(|| {
+            model::pipeline(1, 1, true);
+        });
+    }
+
+    #[test]
+    fn model_detects_forgotten_drop() {
+        // loom报告死锁后，展开时的析构仍然会进入loom的运行时，进程直接abort，
+        // 所以在子进程中运行这个模型，检查它因为死锁失败
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["interleavings::tests::forgotten_drop_model", "--exact"])
+            .args(["--ignored", "--nocapture", "--test-threads=1"])
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("deadlock"));
+    }
+
+    #[test]
+    fn model_channel_keeps_order_between_two_threads() {
+        check(|| {
+            let (snd, rcv) = model::bounded(2);
+            let producer = loom::thread::spawn(move || {
+                for i in 0..3 {
+                    snd.send(i).unwrap();
+                }
+            });
+            assert_eq!(rcv.iter().collect::<Vec<_>>(), [0, 1, 2]);
+            producer.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn examples_finish_under_all_seeds() {
+        for seed in 0..SEEDS {
+            if let Err(err) = check_seed(seed) {
+                panic!("{}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn pipeline_shuts_down_with_idle_workers() {
+        // 工作线程比数据多，没有拿到数据的工作线程也必须在通道关闭后退出
+        for seed in 0..20 {
+            let results = with_watchdog(
+                format!("种子{}下的空闲工作线程", seed),
+                TIMEOUT,
+                move || run_pipeline(2, 4, &Schedule::Seeded(seed)),
+            )
+            .unwrap();
+            assert_eq!(results.len(), 2);
+        }
+    }
+
+    #[test]
+    fn pipeline_without_messages_shuts_down() {
+        let results = with_watchdog("没有数据的流水线".to_string(), TIMEOUT, || {
+            run_pipeline(0, 2, &Schedule::Seeded(0))
+        })
+        .unwrap();
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn watchdog_detects_forgotten_drop() {
+        detect_forgotten_drop().unwrap();
+    }
+}
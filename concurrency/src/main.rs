@@ -6,7 +6,9 @@
 #[macro_use]
 extern crate error_chain;
 
+mod actors;
 mod explicit_threads;
+mod interleavings;
 mod parallel_tasks;
 
 fn main() {
@@ -23,6 +25,14 @@ fn main() {
         }
     }
 
+    if let Err(ref e) = interleavings::explore_interleavings() {
+        println!("线程例子调度测试发生错误：{}", e);
+    }
+
+    if let Err(ref e) = actors::fruit_store_actor() {
+        println!("Actor保持可变状态发生错误：{}", e);
+    }
+
     parallel_tasks::mutate_elements_of_an_array_in_parallel();
     parallel_tasks::test_in_parallel();
     parallel_tasks::search_item_in_parallel();
@@ -0,0 +1,361 @@
+//! # 秒表和简单的基准测试
+//! 在`elapsed_time`只测一次`Instant::now`的基础上，提供带名字的分段计时（lap），
+//! 以及先预热再重复运行的基准测试，统计最小值、中位数、p95、平均值和标准偏差，
+//! 并用Tukey栅栏（四分位距的1.5倍）找出异常值。结果可以输出为表格或者JSON。
+//!
+//! 平均值和标准偏差复用`science::statistics`中的实现。
+//! 表格按显示宽度对齐，中文等全角字符占两列。
+use serde::Serialize;
+use std::fmt;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use unicode_width::UnicodeWidthStr;
+
+/// 用空格把`text`补齐到`width`列的显示宽度，`right`为真时右对齐。
+/// 格式化字符串的`{:<width$}`按字符数补齐，全角字符会让列错位。
+fn pad(text: &str, width: usize, right: bool) -> String {
+    let fill = " ".repeat(width.saturating_sub(text.width()));
+    if right {
+        fill + text
+    } else {
+        format!("{}{}", text, fill)
+    }
+}
+
+/// 一个分段：`split`是距上一个分段的时间，`total`是距秒表启动的时间
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lap {
+    pub name: String,
+    pub split: Duration,
+    pub total: Duration,
+}
+
+/// 记录带名字分段的秒表
+#[derive(Debug, Clone)]
+pub struct Stopwatch {
+    started: Instant,
+    last: Instant,
+    laps: Vec<Lap>,
+}
+
+impl Stopwatch {
+    pub fn start() -> Self {
+        let now = Instant::now();
+        Stopwatch {
+            started: now,
+            last: now,
+            laps: vec![],
+        }
+    }
+
+    /// 结束当前分段并返回它的耗时
+    pub fn lap(&mut self, name: &str) -> Duration {
+        let now = Instant::now();
+        let split = now - self.last;
+        self.last = now;
+        self.laps.push(Lap {
+            name: name.to_string(),
+            split,
+            total: now - self.started,
+        });
+        split
+    }
+
+    pub fn laps(&self) -> &[Lap] {
+        &self.laps
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+impl fmt::Display for Stopwatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .laps
+            .iter()
+            .map(|lap| lap.name.width())
+            .max()
+            .unwrap_or(0);
+        for lap in &self.laps {
+            writeln!(
+                f,
+                "{}  {:>12.3?}  {:>12.3?}",
+                pad(&lap.name, width, false),
+                lap.split,
+                lap.total
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// 一组运行时间的统计结果，时间单位都是纳秒
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Stats {
+    pub name: String,
+    pub runs: usize,
+    pub min: f64,
+    pub median: f64,
+    pub p95: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub std_dev: f64,
+    /// 异常值在样本中的下标
+    pub outliers: Vec<usize>,
+}
+
+/// 按最近秩法计算已排序样本的百分位数
+fn percentile(sorted: &[f64], percent: f64) -> f64 {
+    let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// 已排序样本的中位数，长度为偶数时取中间两个的平均值
+fn median(sorted: &[f64]) -> f64 {
+    let middle = sorted.len() / 2;
+    match sorted.len() % 2 {
+        0 => (sorted[middle - 1] + sorted[middle]) / 2.0,
+        _ => sorted[middle],
+    }
+}
+
+impl Stats {
+    /// 从样本计算统计值，样本为空时返回`None`
+    pub fn from_samples(name: &str, samples: &[Duration]) -> Option<Stats> {
+        let nanos: Vec<f64> = samples
+            .iter()
+            .map(|sample| sample.as_nanos() as f64)
+            .collect();
+        let mean = science::statistics::mean(&nanos)?;
+        let std_dev = science::statistics::std_deviation(&nanos)?;
+
+        let mut sorted = nanos.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let (q1, q3) = (percentile(&sorted, 25.0), percentile(&sorted, 75.0));
+        let fence = 1.5 * (q3 - q1);
+        let outliers = nanos
+            .iter()
+            .enumerate()
+            .filter(|(_, value)| **value < q1 - fence || **value > q3 + fence)
+            .map(|(index, _)| index)
+            .collect();
+
+        Some(Stats {
+            name: name.to_string(),
+            runs: samples.len(),
+            min: sorted[0],
+            median: median(&sorted),
+            p95: percentile(&sorted, 95.0),
+            max: sorted[sorted.len() - 1],
+            mean,
+            std_dev,
+            outliers,
+        })
+    }
+}
+
+/// 基准测试的配置，先运行`warmup`次丢弃结果，再记录`runs`次
+#[derive(Debug, Clone, Copy)]
+pub struct Bench {
+    warmup: usize,
+    runs: usize,
+}
+
+impl Default for Bench {
+    fn default() -> Self {
+        Bench {
+            warmup: 3,
+            runs: 30,
+        }
+    }
+}
+
+impl Bench {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn warmup(mut self, warmup: usize) -> Self {
+        self.warmup = warmup;
+        self
+    }
+
+    /// 至少运行一次
+    pub fn runs(mut self, runs: usize) -> Self {
+        self.runs = runs.max(1);
+        self
+    }
+
+    /// 测量`f`，返回值经过`black_box`以免被编译器优化掉
+    pub fn run<T, F: FnMut() -> T>(&self, name: &str, mut f: F) -> Stats {
+        for _ in 0..self.warmup {
+            black_box(f());
+        }
+        let samples: Vec<Duration> = (0..self.runs)
+            .map(|_| {
+                let start = Instant::now();
+                black_box(f());
+                start.elapsed()
+            })
+            .collect();
+        Stats::from_samples(name, &samples).expect("至少有一次运行")
+    }
+}
+
+/// 多个基准测试的结果
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Report {
+    pub results: Vec<Stats>,
+}
+
+/// 用合适的单位显示纳秒数
+fn human(nanos: f64) -> String {
+    format!("{:.3?}", Duration::from_nanos(nanos.round() as u64))
+}
+
+impl Report {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, stats: Stats) {
+        self.results.push(stats);
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// 对齐的文本表格
+    pub fn to_table(&self) -> String {
+        let header = [
+            "name", "runs", "min", "median", "p95", "max", "mean", "stddev", "outliers",
+        ];
+        let rows: Vec<Vec<String>> = self
+            .results
+            .iter()
+            .map(|stats| {
+                vec![
+                    stats.name.clone(),
+                    stats.runs.to_string(),
+                    human(stats.min),
+                    human(stats.median),
+                    human(stats.p95),
+                    human(stats.max),
+                    human(stats.mean),
+                    human(stats.std_dev),
+                    stats.outliers.len().to_string(),
+                ]
+            })
+            .collect();
+
+        let widths: Vec<usize> = (0..header.len())
+            .map(|column| {
+                rows.iter()
+                    .map(|row| row[column].width())
+                    .chain(Some(header[column].width()))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let line = |cells: Vec<&str>| {
+            cells
+                .iter()
+                .zip(&widths)
+                .enumerate()
+                .map(|(column, (cell, width))| pad(cell, *width, column > 0))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        };
+
+        let mut table = vec![line(header.to_vec())];
+        table.extend(
+            rows.iter()
+                .map(|row| line(row.iter().map(String::as_str).collect())),
+        );
+        table.join("\n")
+    }
+}
+
+/// # 秒表分段计时和重复运行的基准测试
+pub fn time_code_sections() -> serde_json::Result<()> {
+    let mut stopwatch = Stopwatch::start();
+    let squares: Vec<u64> = (0..100_000u64).map(|n| n * n).collect();
+    stopwatch.lap("生成平方数");
+    let sum: u64 = squares.iter().sum();
+    stopwatch.lap("求和");
+    println!("平方和：{}\n{}", sum, stopwatch);
+    assert_eq!(stopwatch.laps().len(), 2);
+    assert_eq!(
+        stopwatch.laps()[1].total,
+        stopwatch.laps()[0].total + stopwatch.laps()[1].split
+    );
+    assert!(stopwatch.elapsed() >= stopwatch.laps()[1].total);
+
+    // 统计值和异常值用固定的样本验证
+    let samples: Vec<Duration> = [10, 12, 11, 13, 12, 11, 10, 12, 11, 95]
+        .iter()
+        .map(|micros| Duration::from_micros(*micros))
+        .collect();
+    let stats = Stats::from_samples("固定样本", &samples).unwrap();
+    assert_eq!(stats.min, 10_000.0);
+    assert_eq!(stats.median, 11_500.0);
+    assert_eq!(stats.p95, 95_000.0);
+    assert_eq!(stats.mean, 19_700.0);
+    assert!((stats.std_dev - 25_116.0).abs() < 1.0);
+    assert_eq!(stats.outliers, [9]);
+    assert!(Stats::from_samples("空", &[]).is_none());
+
+    let bench = Bench::new().warmup(5).runs(50);
+    let mut report = Report::new();
+    report.push(bench.run("排序1000个数", || {
+        let mut data: Vec<u32> = (0..1000).rev().collect();
+        data.sort_unstable();
+        data
+    }));
+    report.push(bench.run("格式化100个字符串", || {
+        (0..100).map(|n| format!("{:05}", n)).collect::<Vec<_>>()
+    }));
+    report.push(stats);
+    println!("{}", report.to_table());
+    assert_eq!(report.to_table().lines().count(), 4);
+    assert!(report
+        .results
+        .iter()
+        .all(|stats| stats.min <= stats.median && stats.median <= stats.p95));
+
+    let json = report.to_json()?;
+    assert!(json.contains("\"name\": \"固定样本\""));
+    println!("{}", json.lines().take(12).collect::<Vec<_>>().join("\n"));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_aligns_by_display_width() {
+        let samples = [Duration::from_micros(10), Duration::from_micros(12)];
+        let mut report = Report::new();
+        report.push(Stats::from_samples("排序", &samples).unwrap());
+        report.push(Stats::from_samples("sort", &samples).unwrap());
+        report.push(Stats::from_samples("格式化字符串", &samples).unwrap());
+
+        let table = report.to_table();
+        let widths: Vec<usize> = table.lines().map(|line| line.width()).collect();
+        assert!(widths.iter().all(|width| *width == widths[0]), "{}", table);
+    }
+
+    #[test]
+    fn pad_uses_display_width() {
+        assert_eq!(pad("求和", 6, false), "求和  ");
+        assert_eq!(pad("求和", 6, true), "  求和");
+        assert_eq!(pad("too long", 3, false), "too long");
+    }
+}
@@ -0,0 +1,97 @@
+//! # 日历命令行工具
+//! ```text
+//! cal                          # 本月
+//! cal 2 2024 -w --sunday       # 2024年2月，显示周数，周日开始
+//! cal -3 --holidays us.toml    # 上个月、本月和下个月，高亮节假日
+//! cal -y 2024 --no-color
+//! ```
+//! 设置了`NO_COLOR`环境变量时也不使用颜色。
+use chrono::{Datelike, Local, Months, NaiveDate, Weekday};
+use clap::{App, Arg};
+use dateandtime::business_days::HolidayCalendar;
+use dateandtime::calendar::CalendarView;
+use std::env;
+use std::path::Path;
+use std::process;
+
+fn main() {
+    let matches = App::new("cal")
+        .about("在终端中显示日历")
+        .arg(Arg::new("month").help("月份，1-12"))
+        .arg(Arg::new("year").help("年份"))
+        .arg(
+            Arg::new("year-view")
+                .short('y')
+                .long("year")
+                .help("显示整年"),
+        )
+        .arg(
+            Arg::new("three")
+                .short('3')
+                .help("显示上个月、本月和下个月"),
+        )
+        .arg(
+            Arg::new("week-numbers")
+                .short('w')
+                .long("week-numbers")
+                .help("显示ISO周数"),
+        )
+        .arg(Arg::new("sunday").long("sunday").help("每周从周日开始"))
+        .arg(
+            Arg::new("holidays")
+                .long("holidays")
+                .takes_value(true)
+                .help("节假日文件（.toml或.ics），其中的日期会被高亮"),
+        )
+        .arg(Arg::new("no-color").long("no-color").help("不使用颜色"))
+        .get_matches();
+
+    let today = Local::now().date_naive();
+    let number = |name: &str| {
+        matches.value_of(name).map(|text| {
+            text.parse::<i32>().unwrap_or_else(|_| {
+                eprintln!("无效的数字：{}", text);
+                process::exit(2);
+            })
+        })
+    };
+    // 只有一个参数并且使用-y时，它是年份
+    let (month, year) = match (number("month"), number("year")) {
+        (Some(year), None) if matches.is_present("year-view") => (today.month() as i32, year),
+        (month, year) => (
+            month.unwrap_or(today.month() as i32),
+            year.unwrap_or(today.year()),
+        ),
+    };
+    let first = NaiveDate::from_ymd_opt(year, month as u32, 1).unwrap_or_else(|| {
+        eprintln!("无效的月份：{}", month);
+        process::exit(2);
+    });
+
+    let holidays = matches.value_of("holidays").map(|path| {
+        HolidayCalendar::load(Path::new(path)).unwrap_or_else(|err| {
+            eprintln!("无法加载节假日文件{}：{}", path, err);
+            process::exit(1);
+        })
+    });
+
+    let mut view = CalendarView::new()
+        .today(today)
+        .week_numbers(matches.is_present("week-numbers"))
+        .colour(!matches.is_present("no-color") && env::var_os("NO_COLOR").is_none());
+    if matches.is_present("sunday") {
+        view = view.first_weekday(Weekday::Sun);
+    }
+    if let Some(holidays) = &holidays {
+        view = view.holidays(holidays);
+    }
+
+    if matches.is_present("year-view") {
+        println!("{}", view.year(year));
+    } else if matches.is_present("three") {
+        let previous = first - Months::new(1);
+        println!("{}", view.months(previous.year(), previous.month(), 3));
+    } else {
+        println!("{}", view.month(year, month as u32));
+    }
+}
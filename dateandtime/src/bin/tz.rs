@@ -0,0 +1,89 @@
+//! # 时区转换命令行工具
+//! ```text
+//! tz convert "2022-03-13 02:30" --from America/New_York --to Asia/Shanghai --resolve earlier
+//! tz list america
+//! ```
+use chrono::NaiveDateTime;
+use clap::{App, Arg};
+use dateandtime::timezone::{self, Resolve};
+use std::process;
+
+fn parse_local(text: &str) -> Option<NaiveDateTime> {
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+}
+
+fn main() {
+    let matches = App::new("tz")
+        .about("按IANA时区名字转换时间")
+        .subcommand_required(true)
+        .subcommand(
+            App::new("convert")
+                .about("把一个时区的本地时间转换到另一个时区")
+                .arg(
+                    Arg::new("time")
+                        .required(true)
+                        .help("本地时间，例如\"2022-03-13 02:30\""),
+                )
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .takes_value(true)
+                        .required(true)
+                        .help("时间所在的时区"),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .takes_value(true)
+                        .required(true)
+                        .help("目标时区"),
+                )
+                .arg(
+                    Arg::new("resolve")
+                        .long("resolve")
+                        .takes_value(true)
+                        .possible_values(["reject", "earlier", "later"])
+                        .default_value("reject")
+                        .help("本地时间有歧义或者不存在时的处理方式"),
+                ),
+        )
+        .subcommand(
+            App::new("list")
+                .about("列出时区")
+                .arg(Arg::new("filter").help("只列出名字包含该文本的时区")),
+        )
+        .get_matches();
+
+    match matches.subcommand() {
+        Some(("convert", args)) => {
+            let text = args.value_of("time").unwrap();
+            let local = parse_local(text).unwrap_or_else(|| {
+                eprintln!("无法解析时间：{}", text);
+                process::exit(2);
+            });
+            let resolve = match args.value_of("resolve") {
+                Some("earlier") => Resolve::Earlier,
+                Some("later") => Resolve::Later,
+                _ => Resolve::Reject,
+            };
+
+            let from = args.value_of("from").unwrap();
+            let to = args.value_of("to").unwrap();
+            match timezone::convert(local, from, to, resolve) {
+                Ok(time) => println!("{}", time.format("%Y-%m-%d %H:%M:%S %Z (%:z)")),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    process::exit(1);
+                }
+            }
+        }
+        Some(("list", args)) => {
+            for name in timezone::list_zones(args.value_of("filter").unwrap_or("")) {
+                println!("{}", name);
+            }
+        }
+        _ => unreachable!(),
+    }
+}
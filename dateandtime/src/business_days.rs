@@ -0,0 +1,546 @@
+//! # 工作日和节假日
+//! `day_earlier`和`checked_add_signed(Duration::weeks(..))`只做日历上的加减。
+//! 计费等业务需要“10个工作日之后”、“下一个工作日”以及“两个日期之间有几个工作日”，
+//! 这里为`NaiveDate`增加这些方法，周末可以配置，节假日从TOML或ICS文件加载。
+//!
+//! 节假日落在周末时可以按规则调休（observed）：
+//! `nearest_weekday`把周六的节日挪到周五、周日的挪到周一；`next_weekday`挪到之后的第一个非周末日。
+//!
+//! TOML格式：
+//! ```toml
+//! weekend = ["Sat", "Sun"]
+//!
+//! [[holiday]]
+//! name = "Christmas Day"
+//! month = 12
+//! day = 25
+//! observed = "nearest_weekday"
+//!
+//! [[holiday]]
+//! name = "Thanksgiving"
+//! month = 11
+//! weekday = "Thu"
+//! nth = 4
+//!
+//! [[holiday]]
+//! name = "Company Day"
+//! date = "2022-09-09"
+//! ```
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum CalendarError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    /// 文件内容能够解析，但是节假日的定义不正确
+    Invalid(String),
+}
+
+impl fmt::Display for CalendarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalendarError::Io(err) => write!(f, "读取节假日文件失败：{}", err),
+            CalendarError::Toml(err) => write!(f, "解析TOML失败：{}", err),
+            CalendarError::Invalid(msg) => write!(f, "节假日定义错误：{}", msg),
+        }
+    }
+}
+
+impl Error for CalendarError {}
+
+impl From<std::io::Error> for CalendarError {
+    fn from(err: std::io::Error) -> Self {
+        CalendarError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for CalendarError {
+    fn from(err: toml::de::Error) -> Self {
+        CalendarError::Toml(err)
+    }
+}
+
+/// 节日落在周末时的调休规则
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Observed {
+    /// 不调休
+    #[default]
+    None,
+    /// 挪到最近的非周末日，距离相同时挪到之后
+    NearestWeekday,
+    /// 挪到之后的第一个非周末日
+    NextWeekday,
+}
+
+/// 节日的日期规则
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HolidayDate {
+    /// 只在某一天
+    Fixed(NaiveDate),
+    /// 每年的固定月日
+    Annual { month: u32, day: u32 },
+    /// 每年某月的第n个星期几，`nth`为-1表示最后一个
+    NthWeekday {
+        month: u32,
+        weekday: Weekday,
+        nth: i32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Holiday {
+    pub name: String,
+    pub date: HolidayDate,
+    pub observed: Observed,
+}
+
+#[derive(Deserialize)]
+struct RawCalendar {
+    #[serde(default)]
+    weekend: Option<Vec<String>>,
+    #[serde(default)]
+    holiday: Vec<RawHoliday>,
+}
+
+#[derive(Deserialize)]
+struct RawHoliday {
+    name: String,
+    date: Option<String>,
+    month: Option<u32>,
+    day: Option<u32>,
+    weekday: Option<String>,
+    nth: Option<i32>,
+    #[serde(default)]
+    observed: Observed,
+}
+
+impl RawHoliday {
+    fn into_holiday(self) -> Result<Holiday, CalendarError> {
+        let invalid = |msg: &str| CalendarError::Invalid(format!("{}：{}", self.name, msg));
+        let date = match (&self.date, self.month, self.day, &self.weekday, self.nth) {
+            (Some(date), None, None, None, None) => HolidayDate::Fixed(
+                NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| invalid("日期格式错误"))?,
+            ),
+            (None, Some(month), Some(day), None, None) => {
+                // 用闰年检查月日是否有效，2月29日是允许的
+                NaiveDate::from_ymd_opt(2000, month, day).ok_or_else(|| invalid("月日无效"))?;
+                HolidayDate::Annual { month, day }
+            }
+            (None, Some(month), None, Some(weekday), Some(nth))
+                if (1..=12).contains(&month) && (nth == -1 || (1..=5).contains(&nth)) =>
+            {
+                HolidayDate::NthWeekday {
+                    month,
+                    weekday: weekday.parse().map_err(|_| invalid("星期无效"))?,
+                    nth,
+                }
+            }
+            _ => return Err(invalid("需要date，或者month和day，或者month、weekday和nth")),
+        };
+
+        Ok(Holiday {
+            name: self.name,
+            date,
+            observed: self.observed,
+        })
+    }
+}
+
+/// # 节假日日历
+#[derive(Debug, Clone)]
+pub struct HolidayCalendar {
+    weekend: [bool; 7],
+    holidays: Vec<Holiday>,
+}
+
+impl Default for HolidayCalendar {
+    /// 周末为周六和周日，没有节假日
+    fn default() -> Self {
+        HolidayCalendar::new(&[Weekday::Sat, Weekday::Sun]).unwrap()
+    }
+}
+
+impl HolidayCalendar {
+    /// 一周七天都是周末的日历中没有工作日，工作日计算永远不会结束，返回错误
+    pub fn new(weekend: &[Weekday]) -> Result<Self, CalendarError> {
+        let mut days = [false; 7];
+        for day in weekend {
+            days[day.num_days_from_monday() as usize] = true;
+        }
+        if days.iter().all(|weekend| *weekend) {
+            return Err(CalendarError::Invalid("一周七天都是周末".to_string()));
+        }
+        Ok(HolidayCalendar {
+            weekend: days,
+            holidays: vec![],
+        })
+    }
+
+    pub fn add_holiday(&mut self, holiday: Holiday) -> &mut Self {
+        self.holidays.push(holiday);
+        self
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, CalendarError> {
+        let raw: RawCalendar = toml::from_str(text)?;
+        let mut calendar = match raw.weekend {
+            Some(days) => {
+                let days = days
+                    .iter()
+                    .map(|day| {
+                        day.parse::<Weekday>()
+                            .map_err(|_| CalendarError::Invalid(format!("无效的周末：{}", day)))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                HolidayCalendar::new(&days)?
+            }
+            None => HolidayCalendar::default(),
+        };
+        for holiday in raw.holiday {
+            calendar.add_holiday(holiday.into_holiday()?);
+        }
+        Ok(calendar)
+    }
+
+    /// 从ICS文件中读取全天的`VEVENT`作为节假日：`DTSTART`是日期，`SUMMARY`是名字，
+    /// `RRULE:FREQ=YEARLY`表示每年重复。ICS中没有调休的概念，所有节日使用`observed`规则。
+    pub fn add_ics(&mut self, text: &str, observed: Observed) -> Result<&mut Self, CalendarError> {
+        let mut name = None;
+        let mut start = None;
+        let mut yearly = false;
+
+        for line in text.lines().map(str::trim) {
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) => (key, value),
+                None => continue,
+            };
+            let property = key.split(';').next().unwrap_or(key);
+            match (property, value) {
+                ("BEGIN", "VEVENT") => {
+                    name = None;
+                    start = None;
+                    yearly = false;
+                }
+                ("SUMMARY", value) => name = Some(value.to_string()),
+                ("DTSTART", value) => {
+                    let date = value.get(..8).unwrap_or(value);
+                    start = Some(NaiveDate::parse_from_str(date, "%Y%m%d").map_err(|_| {
+                        CalendarError::Invalid(format!("无效的DTSTART：{}", value))
+                    })?);
+                }
+                ("RRULE", value) => yearly = value.split(';').any(|part| part == "FREQ=YEARLY"),
+                ("END", "VEVENT") => {
+                    let date = start
+                        .ok_or_else(|| CalendarError::Invalid("VEVENT缺少DTSTART".to_string()))?;
+                    self.add_holiday(Holiday {
+                        name: name.take().unwrap_or_default(),
+                        date: if yearly {
+                            HolidayDate::Annual {
+                                month: date.month(),
+                                day: date.day(),
+                            }
+                        } else {
+                            HolidayDate::Fixed(date)
+                        },
+                        observed,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// 根据扩展名加载`.toml`或`.ics`文件
+    pub fn load(path: &Path) -> Result<Self, CalendarError> {
+        let text = fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("ics") => {
+                let mut calendar = HolidayCalendar::default();
+                calendar.add_ics(&text, Observed::None)?;
+                Ok(calendar)
+            }
+            _ => HolidayCalendar::from_toml(&text),
+        }
+    }
+
+    pub fn is_weekend(&self, date: NaiveDate) -> bool {
+        self.weekend[date.weekday().num_days_from_monday() as usize]
+    }
+
+    /// `date`是节日或者调休日时返回节日的名字
+    pub fn holiday_name(&self, date: NaiveDate) -> Option<&str> {
+        // 1月1日的节日可能调休到前一年的12月31日，12月31日的节日也可能调休到下一年的1月
+        self.holidays
+            .iter()
+            .find(|holiday| {
+                [date.year() - 1, date.year(), date.year() + 1]
+                    .iter()
+                    .filter_map(|year| self.observed_date(holiday, *year))
+                    .any(|observed| observed == date)
+            })
+            .map(|holiday| holiday.name.as_str())
+    }
+
+    pub fn is_holiday(&self, date: NaiveDate) -> bool {
+        self.holiday_name(date).is_some()
+    }
+
+    pub fn is_business_day(&self, date: NaiveDate) -> bool {
+        !self.is_weekend(date) && !self.is_holiday(date)
+    }
+
+    /// 节日在`year`年的实际放假日期
+    fn observed_date(&self, holiday: &Holiday, year: i32) -> Option<NaiveDate> {
+        let date = match holiday.date {
+            HolidayDate::Fixed(date) if date.year() == year => date,
+            HolidayDate::Fixed(_) => return None,
+            HolidayDate::Annual { month, day } => NaiveDate::from_ymd_opt(year, month, day)?,
+            HolidayDate::NthWeekday {
+                month,
+                weekday,
+                nth,
+            } if nth > 0 => NaiveDate::from_weekday_of_month_opt(year, month, weekday, nth as u8)?,
+            HolidayDate::NthWeekday { month, weekday, .. } => {
+                let mut last = NaiveDate::from_ymd_opt(year, month, 1)?
+                    .checked_add_months(chrono::Months::new(1))?
+                    .pred_opt()?;
+                while last.weekday() != weekday {
+                    last = last.pred_opt()?;
+                }
+                last
+            }
+        };
+
+        if !self.is_weekend(date) || holiday.observed == Observed::None {
+            return Some(date);
+        }
+        for distance in 1..7 {
+            let before = date - Duration::days(distance);
+            let after = date + Duration::days(distance);
+            match holiday.observed {
+                Observed::NearestWeekday if !self.is_weekend(after) => return Some(after),
+                Observed::NearestWeekday if !self.is_weekend(before) => return Some(before),
+                Observed::NextWeekday if !self.is_weekend(after) => return Some(after),
+                _ => {}
+            }
+        }
+        Some(date)
+    }
+
+    /// `year`年的所有节假日（调休后的日期），按日期排序
+    pub fn holidays_in(&self, year: i32) -> Vec<(NaiveDate, &str)> {
+        let mut days: Vec<(NaiveDate, &str)> = self
+            .holidays
+            .iter()
+            .flat_map(|holiday| {
+                [year - 1, year, year + 1]
+                    .into_iter()
+                    .filter_map(|y| self.observed_date(holiday, y))
+                    .filter(|date| date.year() == year)
+                    .map(|date| (date, holiday.name.as_str()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        days.sort();
+        days
+    }
+}
+
+/// 为`NaiveDate`增加工作日计算
+pub trait BusinessDays: Sized {
+    /// 向后（`days`为负数时向前）数`days`个工作日，起始日期本身不计算在内
+    fn add_business_days(self, days: i64, calendar: &HolidayCalendar) -> Option<Self>;
+
+    /// 之后的第一个工作日
+    fn next_business_day(self, calendar: &HolidayCalendar) -> Option<Self>;
+
+    /// `[self, end)`之间的工作日数量，`end`早于`self`时返回负数
+    fn business_days_until(self, end: Self, calendar: &HolidayCalendar) -> i64;
+}
+
+impl BusinessDays for NaiveDate {
+    fn add_business_days(self, days: i64, calendar: &HolidayCalendar) -> Option<Self> {
+        let mut date = self;
+        let mut remaining = days.unsigned_abs();
+        while remaining > 0 {
+            date = if days > 0 {
+                date.succ_opt()?
+            } else {
+                date.pred_opt()?
+            };
+            if calendar.is_business_day(date) {
+                remaining -= 1;
+            }
+        }
+        Some(date)
+    }
+
+    fn next_business_day(self, calendar: &HolidayCalendar) -> Option<Self> {
+        self.add_business_days(1, calendar)
+    }
+
+    fn business_days_until(self, end: Self, calendar: &HolidayCalendar) -> i64 {
+        let (start, stop, sign) = if end >= self {
+            (self, end, 1)
+        } else {
+            (end, self, -1)
+        };
+        let count = start
+            .iter_days()
+            .take_while(|date| *date < stop)
+            .filter(|date| calendar.is_business_day(*date))
+            .count() as i64;
+        count * sign
+    }
+}
+
+const US_HOLIDAYS: &str = r#"
+weekend = ["Sat", "Sun"]
+
+[[holiday]]
+name = "New Year's Day"
+month = 1
+day = 1
+observed = "nearest_weekday"
+
+[[holiday]]
+name = "Thanksgiving"
+month = 11
+weekday = "Thu"
+nth = 4
+
+[[holiday]]
+name = "Christmas Day"
+month = 12
+day = 25
+observed = "nearest_weekday"
+"#;
+
+const US_HOLIDAYS_ICS: &str = "BEGIN:VCALENDAR
+VERSION:2.0
+BEGIN:VEVENT
+DTSTART;VALUE=DATE:20220704
+SUMMARY:Independence Day
+RRULE:FREQ=YEARLY
+END:VEVENT
+END:VCALENDAR";
+
+/// # 工作日计算
+pub fn calculate_business_days() -> Result<(), CalendarError> {
+    let mut calendar = HolidayCalendar::from_toml(US_HOLIDAYS)?;
+    calendar.add_ics(US_HOLIDAYS_ICS, Observed::NearestWeekday)?;
+    println!("2022年的节假日：{:?}", calendar.holidays_in(2022));
+
+    let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+
+    // 2022-01-01是周六，调休到2021-12-31
+    assert_eq!(
+        calendar.holiday_name(date(2021, 12, 31)),
+        Some("New Year's Day")
+    );
+
+    // 2022-12-25是周日，调休到周一12月26日
+    let after_christmas = date(2022, 12, 23).next_business_day(&calendar);
+    println!("2022-12-23之后的第一个工作日：{:?}", after_christmas);
+    assert_eq!(after_christmas, Some(date(2022, 12, 27)));
+
+    let due = date(2022, 6, 30).add_business_days(10, &calendar);
+    println!("2022-06-30之后10个工作日：{:?}", due);
+    assert_eq!(due, Some(date(2022, 7, 15)));
+    assert_eq!(
+        date(2022, 7, 15).add_business_days(-10, &calendar),
+        Some(date(2022, 6, 30))
+    );
+
+    let november = date(2022, 11, 1).business_days_until(date(2022, 12, 1), &calendar);
+    println!("2022年11月的工作日：{}天", november);
+    assert_eq!(november, 21);
+    assert_eq!(
+        date(2022, 12, 1).business_days_until(date(2022, 11, 1), &calendar),
+        -21
+    );
+
+    // 周末是周五和周六
+    let friday_saturday = HolidayCalendar::new(&[Weekday::Fri, Weekday::Sat])?;
+    assert_eq!(
+        date(2022, 7, 7).next_business_day(&friday_saturday),
+        Some(date(2022, 7, 10))
+    );
+
+    if let Err(err) = HolidayCalendar::from_toml("[[holiday]]\nname = \"Bad\"\nmonth = 2\nday = 30")
+    {
+        println!("{}", err);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn holiday_observed_in_next_year() {
+        // 2022-12-31是周六，调休到2023-01-02
+        let calendar = HolidayCalendar::from_toml(
+            "[[holiday]]\nname = \"Year End\"\nmonth = 12\nday = 31\nobserved = \"next_weekday\"",
+        )
+        .unwrap();
+        assert_eq!(calendar.holiday_name(date(2023, 1, 2)), Some("Year End"));
+        assert!(calendar
+            .holidays_in(2023)
+            .contains(&(date(2023, 1, 2), "Year End")));
+        assert!(!calendar
+            .holidays_in(2022)
+            .iter()
+            .any(|(day, _)| day.year() != 2022));
+        assert_eq!(
+            date(2022, 12, 30).next_business_day(&calendar),
+            Some(date(2023, 1, 3))
+        );
+    }
+
+    #[test]
+    fn adding_past_the_date_range_returns_none() {
+        let calendar = HolidayCalendar::new(&[Weekday::Sat, Weekday::Sun]).unwrap();
+        assert_eq!(NaiveDate::MIN.add_business_days(i64::MIN, &calendar), None);
+        assert_eq!(NaiveDate::MAX.add_business_days(i64::MAX, &calendar), None);
+        assert_eq!(
+            NaiveDate::MIN.add_business_days(0, &calendar),
+            Some(NaiveDate::MIN)
+        );
+    }
+
+    #[test]
+    fn calendar_without_business_days_is_rejected() {
+        let every_day = [
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+            Weekday::Sat,
+            Weekday::Sun,
+        ];
+        assert!(matches!(
+            HolidayCalendar::new(&every_day),
+            Err(CalendarError::Invalid(_))
+        ));
+        assert!(HolidayCalendar::from_toml(
+            r#"weekend = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"]"#
+        )
+        .is_err());
+    }
+}
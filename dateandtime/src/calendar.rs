@@ -0,0 +1,372 @@
+//! # 在终端中显示日历
+//! 和`cal`命令类似，按月或者按年显示日历，可以显示ISO周数、设置每周的第一天、
+//! 高亮今天以及节假日文件（参见`business_days::HolidayCalendar`）中的日期，多个月份按列排列。
+//!
+//! 关闭颜色后输出是纯文本，每个月份占固定的宽度和8行，方便直接比较渲染结果。
+use crate::business_days::HolidayCalendar;
+use ansi_term::{Colour, Style};
+use chrono::{Datelike, Duration, Local, Month, Months, NaiveDate, Weekday};
+
+/// 日期的高亮方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mark {
+    Plain,
+    Holiday,
+    Today,
+    /// 今天同时是节假日
+    TodayHoliday,
+}
+
+/// 日历的显示设置
+#[derive(Debug, Clone)]
+pub struct CalendarView<'a> {
+    first_weekday: Weekday,
+    week_numbers: bool,
+    colour: bool,
+    columns: usize,
+    today: Option<NaiveDate>,
+    holidays: Option<&'a HolidayCalendar>,
+}
+
+impl Default for CalendarView<'_> {
+    /// 周一开始，不显示周数，不使用颜色，每行3个月
+    fn default() -> Self {
+        CalendarView {
+            first_weekday: Weekday::Mon,
+            week_numbers: false,
+            colour: false,
+            columns: 3,
+            today: None,
+            holidays: None,
+        }
+    }
+}
+
+/// 一个月份占的行数：标题、星期和最多6周
+const MONTH_LINES: usize = 8;
+
+fn month_name(month: u32) -> &'static str {
+    Month::try_from(month as u8)
+        .map(|month| month.name())
+        .unwrap_or("")
+}
+
+fn centre(text: &str, width: usize) -> String {
+    let left = width.saturating_sub(text.len()) / 2;
+    format!(
+        "{:left$}{:<rest$}",
+        "",
+        text,
+        left = left,
+        rest = width - left
+    )
+}
+
+impl<'a> CalendarView<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn first_weekday(mut self, weekday: Weekday) -> Self {
+        self.first_weekday = weekday;
+        self
+    }
+
+    /// 每行前显示ISO周数。一行不从周一开始时跨两个ISO周，显示这一行的周四所在的周，
+    /// 也就是这一行中多数日期所在的周
+    pub fn week_numbers(mut self, show: bool) -> Self {
+        self.week_numbers = show;
+        self
+    }
+
+    pub fn colour(mut self, colour: bool) -> Self {
+        self.colour = colour;
+        self
+    }
+
+    /// 多个月份时每行显示的月份数，至少为1
+    pub fn columns(mut self, columns: usize) -> Self {
+        self.columns = columns.max(1);
+        self
+    }
+
+    pub fn today(mut self, today: NaiveDate) -> Self {
+        self.today = Some(today);
+        self
+    }
+
+    /// 使用本地时区的今天
+    pub fn today_local(self) -> Self {
+        self.today(Local::now().date_naive())
+    }
+
+    pub fn holidays(mut self, holidays: &'a HolidayCalendar) -> Self {
+        self.holidays = Some(holidays);
+        self
+    }
+
+    /// 一个月份的宽度，显示周数时多3列
+    pub fn width(&self) -> usize {
+        if self.week_numbers {
+            23
+        } else {
+            20
+        }
+    }
+
+    pub fn mark(&self, date: NaiveDate) -> Mark {
+        let holiday = self
+            .holidays
+            .is_some_and(|holidays| holidays.is_holiday(date));
+        match (self.today == Some(date), holiday) {
+            (true, true) => Mark::TodayHoliday,
+            (true, false) => Mark::Today,
+            (false, true) => Mark::Holiday,
+            (false, false) => Mark::Plain,
+        }
+    }
+
+    fn paint(&self, text: String, mark: Mark) -> String {
+        if !self.colour {
+            return text;
+        }
+        let style = match mark {
+            Mark::Plain => return text,
+            Mark::Holiday => Colour::Red.bold(),
+            Mark::Today => Style::new().reverse(),
+            Mark::TodayHoliday => Colour::Red.bold().reverse(),
+        };
+        style.paint(text).to_string()
+    }
+
+    /// 星期的标题，例如`Mo Tu We Th Fr Sa Su`
+    fn weekday_header(&self) -> String {
+        let days = (0..7)
+            .map(|offset| {
+                let weekday = (0..offset).fold(self.first_weekday, |day, _| day.succ());
+                format!("{:?}", weekday)[..2].to_string()
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        match self.week_numbers {
+            true => format!("   {}", days),
+            false => days,
+        }
+    }
+
+    /// 渲染一个月份，返回`MONTH_LINES`行，每行宽度都是`width()`
+    fn month_lines(&self, year: i32, month: u32, title: &str) -> Vec<String> {
+        let width = self.width();
+        let first = match NaiveDate::from_ymd_opt(year, month, 1) {
+            Some(first) => first,
+            None => return vec![" ".repeat(width); MONTH_LINES],
+        };
+        let mut lines = vec![centre(title, width), self.weekday_header()];
+
+        // 第一行前面空出的天数
+        let blank = first.weekday().days_since(self.first_weekday) as i64;
+        let mut row_start = first - Duration::days(blank);
+        while row_start.month() == month || row_start < first {
+            let days: Vec<NaiveDate> = row_start
+                .iter_days()
+                .take(7)
+                .filter(|date| date.month() == month && date.year() == year)
+                .collect();
+            let mut line = String::new();
+            if self.week_numbers {
+                let thursday =
+                    row_start + Duration::days(Weekday::Thu.days_since(self.first_weekday) as i64);
+                line.push_str(&format!("{:>2} ", thursday.iso_week().week()));
+            }
+            // 第n列从第3n个字符开始，不计颜色代码
+            let mut used = 0;
+            for date in &days {
+                let start = date.weekday().days_since(self.first_weekday) as usize * 3;
+                line.push_str(&" ".repeat(start - used));
+                line.push_str(&self.paint(format!("{:>2}", date.day()), self.mark(*date)));
+                used = start + 2;
+            }
+            line.push_str(&" ".repeat(20 - used));
+            lines.push(line);
+            row_start += Duration::days(7);
+        }
+
+        lines.resize(MONTH_LINES, " ".repeat(width));
+        lines
+    }
+
+    /// 单个月份，标题包含年份
+    pub fn month(&self, year: i32, month: u32) -> String {
+        let title = format!("{} {}", month_name(month), year);
+        join_rows(&[self.month_lines(year, month, &title)])
+    }
+
+    /// 从`year`年`month`月开始的`count`个月份，按`columns`排列
+    pub fn months(&self, year: i32, month: u32, count: usize) -> String {
+        let first = match NaiveDate::from_ymd_opt(year, month, 1) {
+            Some(first) => first,
+            None => return String::new(),
+        };
+        let blocks: Vec<Vec<String>> = (0..count as u32)
+            .filter_map(|offset| first.checked_add_months(Months::new(offset)))
+            .map(|date| {
+                let title = format!("{} {}", month_name(date.month()), date.year());
+                self.month_lines(date.year(), date.month(), &title)
+            })
+            .collect();
+        self.layout(&blocks)
+    }
+
+    /// 整年，年份显示在最上面，月份标题不再重复年份
+    pub fn year(&self, year: i32) -> String {
+        let blocks: Vec<Vec<String>> = (1..=12)
+            .map(|month| self.month_lines(year, month, month_name(month)))
+            .collect();
+        let total = self.columns * self.width() + (self.columns - 1) * 2;
+        format!(
+            "{}\n\n{}",
+            centre(&year.to_string(), total).trim_end(),
+            self.layout(&blocks)
+        )
+    }
+
+    fn layout(&self, blocks: &[Vec<String>]) -> String {
+        blocks
+            .chunks(self.columns)
+            .map(join_rows)
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
+/// 把几个月份并排放在一起，去掉每行末尾的空格和末尾的空行
+fn join_rows(blocks: &[Vec<String>]) -> String {
+    let mut lines: Vec<String> = (0..MONTH_LINES)
+        .map(|index| {
+            blocks
+                .iter()
+                .map(|block| block[index].as_str())
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect();
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+const HOLIDAYS: &str = r#"
+[[holiday]]
+name = "International Workers' Day"
+month = 5
+day = 1
+
+[[holiday]]
+name = "Mother's Day"
+month = 5
+weekday = "Sun"
+nth = 2
+"#;
+
+/// # 在终端中显示日历
+pub fn render_calendars() -> Result<(), crate::business_days::CalendarError> {
+    let holidays = HolidayCalendar::from_toml(HOLIDAYS)?;
+    let today = NaiveDate::from_ymd_opt(2022, 5, 18).unwrap();
+
+    let plain = CalendarView::new()
+        .week_numbers(true)
+        .today(today)
+        .holidays(&holidays);
+    let may = plain.month(2022, 5);
+    println!("{}", may);
+    assert_eq!(
+        may,
+        [
+            "       May 2022",
+            "   Mo Tu We Th Fr Sa Su",
+            "17                    1",
+            "18  2  3  4  5  6  7  8",
+            "19  9 10 11 12 13 14 15",
+            "20 16 17 18 19 20 21 22",
+            "21 23 24 25 26 27 28 29",
+            "22 30 31",
+        ]
+        .join("\n")
+    );
+    assert_eq!(plain.mark(today), Mark::Today);
+    assert_eq!(
+        plain.mark(NaiveDate::from_ymd_opt(2022, 5, 8).unwrap()),
+        Mark::Holiday
+    );
+
+    // 周日开始的三个月
+    let sunday = CalendarView::new().first_weekday(Weekday::Sun);
+    let quarter = sunday.months(2022, 1, 3);
+    println!("{}", quarter);
+    let lines: Vec<&str> = quarter.lines().collect();
+    assert_eq!(
+        lines[1],
+        "Su Mo Tu We Th Fr Sa  Su Mo Tu We Th Fr Sa  Su Mo Tu We Th Fr Sa"
+    );
+    assert_eq!(
+        lines[2],
+        "                   1         1  2  3  4  5         1  2  3  4  5"
+    );
+    assert_eq!(lines.len(), MONTH_LINES);
+
+    // 整年分4行，每行3个月
+    let year = sunday.year(2022);
+    assert_eq!(year.split("\n\n").count(), 5);
+    assert!(year.starts_with(&format!("{}2022\n", " ".repeat(30))));
+
+    // 打开颜色时只改变高亮的日期
+    let coloured = plain.clone().colour(true).month(2022, 5);
+    assert!(coloured.contains(&Style::new().reverse().paint("18").to_string()));
+    assert!(coloured.contains(&Colour::Red.bold().paint(" 1").to_string()));
+    println!("{}", coloured);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn week_numbers(first_weekday: Weekday, year: i32, month: u32) -> Vec<String> {
+        CalendarView::new()
+            .first_weekday(first_weekday)
+            .week_numbers(true)
+            .month(year, month)
+            .lines()
+            .skip(2)
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line[..2].trim().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn iso_week_numbers_for_sunday_first_rows() {
+        // 2022-05-01是周日：周日开始的第一行是5月1日到7日，多数日期在第18周
+        assert_eq!(
+            week_numbers(Weekday::Sun, 2022, 5),
+            ["18", "19", "20", "21", "22"]
+        );
+        assert_eq!(
+            week_numbers(Weekday::Mon, 2022, 5),
+            ["17", "18", "19", "20", "21", "22"]
+        );
+    }
+
+    #[test]
+    fn iso_week_numbers_across_year_boundary() {
+        // 2021-01-01是周五，属于2020年的第53周
+        assert_eq!(week_numbers(Weekday::Mon, 2021, 1)[0], "53");
+        assert_eq!(week_numbers(Weekday::Sun, 2021, 1)[0], "53");
+        // 2019-12-29是周日，周日开始的这一行（12月29日到1月4日）属于2020年的第1周
+        assert_eq!(week_numbers(Weekday::Sun, 2019, 12).last().unwrap(), "1");
+        assert_eq!(week_numbers(Weekday::Mon, 2019, 12).last().unwrap(), "1");
+    }
+}
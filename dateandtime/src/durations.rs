@@ -0,0 +1,356 @@
+//! # 解析和格式化时间间隔
+//! `elapsed_time`用`{:?}`打印`Duration`，`perform_checked_date_and_time_calculations`在代码中构造时间间隔。
+//! 这里提供：
+//!
+//! - `parse_duration`解析`1h30m15s`、`2 days 3 hours`、`1 hour, 30 minutes`这样的写法；
+//! - `parse_iso8601`和`to_iso8601`处理ISO 8601时间间隔，例如`P3DT4H`；
+//! - `DurationFormat`输出`1 hour, 30 minutes`或者紧凑的`1h30m`，可以限制显示的单位个数；
+//! - `to_std`和`from_std`在`chrono::Duration`和`std::time::Duration`之间转换。
+//!
+//! 和`checked_add_signed`一样，超出范围时返回错误而不是panic。
+use chrono::Duration;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DurationError {
+    Empty,
+    /// 无法解析的内容
+    Invalid(String),
+    UnknownUnit(String),
+    /// ISO 8601中的年和月不是固定长度，不能转换为时间间隔
+    CalendarUnit(char),
+    /// 超出`chrono::Duration`或者`std::time::Duration`的范围
+    Overflow,
+    /// `std::time::Duration`不能表示负的时间间隔
+    Negative,
+}
+
+impl fmt::Display for DurationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DurationError::Empty => write!(f, "时间间隔为空"),
+            DurationError::Invalid(text) => write!(f, "无法解析的时间间隔：{}", text),
+            DurationError::UnknownUnit(unit) => write!(f, "未知的时间单位：{}", unit),
+            DurationError::CalendarUnit(unit) => {
+                write!(f, "单位{}的长度不固定，不能转换为时间间隔", unit)
+            }
+            DurationError::Overflow => write!(f, "时间间隔超出范围"),
+            DurationError::Negative => write!(f, "std::time::Duration不能为负数"),
+        }
+    }
+}
+
+impl Error for DurationError {}
+
+fn unit_duration(amount: i64, unit: &str) -> Result<Duration, DurationError> {
+    let duration = match unit {
+        "w" | "week" | "weeks" => Duration::try_weeks(amount),
+        "d" | "day" | "days" => Duration::try_days(amount),
+        "h" | "hr" | "hour" | "hours" => Duration::try_hours(amount),
+        "m" | "min" | "mins" | "minute" | "minutes" => Duration::try_minutes(amount),
+        "s" | "sec" | "secs" | "second" | "seconds" => Duration::try_seconds(amount),
+        "ms" | "millisecond" | "milliseconds" => Duration::try_milliseconds(amount),
+        "us" | "µs" | "microsecond" | "microseconds" => Some(Duration::microseconds(amount)),
+        "ns" | "nanosecond" | "nanoseconds" => Some(Duration::nanoseconds(amount)),
+        _ => return Err(DurationError::UnknownUnit(unit.to_string())),
+    };
+    duration.ok_or(DurationError::Overflow)
+}
+
+/// 解析由数字和单位组成的时间间隔，数字和单位之间可以有空格，各部分之间可以用空格或逗号分隔。
+/// 以`-`开头表示负数。
+pub fn parse_duration(text: &str) -> Result<Duration, DurationError> {
+    let trimmed = text.trim();
+    let (negative, body) = match trimmed.strip_prefix('-') {
+        Some(body) => (true, body),
+        None => (false, trimmed),
+    };
+    if body.is_empty() {
+        return Err(DurationError::Empty);
+    }
+
+    let mut total = Duration::zero();
+    let mut rest = body;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        if rest.is_empty() {
+            break;
+        }
+
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits == 0 {
+            return Err(DurationError::Invalid(text.to_string()));
+        }
+        let amount: i64 = rest[..digits]
+            .parse()
+            .map_err(|_| DurationError::Overflow)?;
+        rest = rest[digits..].trim_start();
+
+        let letters = rest
+            .find(|c: char| !c.is_alphabetic())
+            .unwrap_or(rest.len());
+        if letters == 0 {
+            return Err(DurationError::Invalid(text.to_string()));
+        }
+        let unit = rest[..letters].to_lowercase();
+        rest = &rest[letters..];
+
+        total = total
+            .checked_add(&unit_duration(amount, &unit)?)
+            .ok_or(DurationError::Overflow)?;
+    }
+
+    Ok(if negative { -total } else { total })
+}
+
+/// 解析ISO 8601时间间隔，支持`PnWnDTnHnMnS`，秒可以带小数
+pub fn parse_iso8601(text: &str) -> Result<Duration, DurationError> {
+    let trimmed = text.trim();
+    let (negative, body) = match trimmed.strip_prefix('-') {
+        Some(body) => (true, body),
+        None => (false, trimmed),
+    };
+    let body = body
+        .strip_prefix('P')
+        .ok_or_else(|| DurationError::Invalid(text.to_string()))?;
+    if body.is_empty() || body == "T" {
+        return Err(DurationError::Empty);
+    }
+
+    let mut total = Duration::zero();
+    let mut in_time = false;
+    let mut number = String::new();
+    for c in body.chars() {
+        match c {
+            '0'..='9' | '.' | ',' => number.push(if c == ',' { '.' } else { c }),
+            'T' if !in_time && number.is_empty() => in_time = true,
+            'Y' | 'M' if !in_time => return Err(DurationError::CalendarUnit(c)),
+            'W' | 'D' | 'H' | 'M' | 'S' if !number.is_empty() => {
+                let part = match (c, in_time) {
+                    ('S', true) => fractional_seconds(&number, text)?,
+                    ('W', false) | ('D', false) | ('H', true) | ('M', true) => {
+                        let amount = number
+                            .parse()
+                            .map_err(|_| DurationError::Invalid(text.to_string()))?;
+                        let unit = match c {
+                            'W' => "w",
+                            'D' => "d",
+                            'H' => "h",
+                            _ => "m",
+                        };
+                        unit_duration(amount, unit)?
+                    }
+                    _ => return Err(DurationError::Invalid(text.to_string())),
+                };
+                total = total.checked_add(&part).ok_or(DurationError::Overflow)?;
+                number.clear();
+            }
+            _ => return Err(DurationError::Invalid(text.to_string())),
+        }
+    }
+    if !number.is_empty() {
+        return Err(DurationError::Invalid(text.to_string()));
+    }
+
+    Ok(if negative { -total } else { total })
+}
+
+fn fractional_seconds(number: &str, text: &str) -> Result<Duration, DurationError> {
+    let invalid = || DurationError::Invalid(text.to_string());
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if whole.is_empty() || fraction.len() > 9 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+
+    let seconds: i64 = whole.parse().map_err(|_| invalid())?;
+    let nanos: u32 = format!("{:0<9}", fraction).parse().map_err(|_| invalid())?;
+    Duration::new(seconds, nanos).ok_or(DurationError::Overflow)
+}
+
+/// 把时间间隔转换为ISO 8601格式，例如`P3DT4H`，零为`PT0S`
+pub fn to_iso8601(duration: Duration) -> String {
+    let sign = if duration < Duration::zero() { "-" } else { "" };
+    let duration = duration.abs();
+    let days = duration.num_days();
+    let hours = duration.num_hours() % 24;
+    let minutes = duration.num_minutes() % 60;
+    let seconds = duration.num_seconds() % 60;
+    let nanos = duration.subsec_nanos();
+
+    let mut text = format!("{}P", sign);
+    if days > 0 {
+        text.push_str(&format!("{}D", days));
+    }
+    if hours > 0 || minutes > 0 || seconds > 0 || nanos > 0 || days == 0 {
+        text.push('T');
+    }
+    if hours > 0 {
+        text.push_str(&format!("{}H", hours));
+    }
+    if minutes > 0 {
+        text.push_str(&format!("{}M", minutes));
+    }
+    if nanos > 0 {
+        let fraction = format!("{:09}", nanos);
+        text.push_str(&format!("{}.{}S", seconds, fraction.trim_end_matches('0')));
+    } else if seconds > 0 || text.ends_with('T') {
+        text.push_str(&format!("{}S", seconds));
+    }
+    text
+}
+
+/// `chrono::Duration`转换为`std::time::Duration`，负数时返回错误
+pub fn to_std(duration: Duration) -> Result<std::time::Duration, DurationError> {
+    if duration < Duration::zero() {
+        return Err(DurationError::Negative);
+    }
+    duration.to_std().map_err(|_| DurationError::Overflow)
+}
+
+/// `std::time::Duration`转换为`chrono::Duration`，超出范围时返回错误
+pub fn from_std(duration: std::time::Duration) -> Result<Duration, DurationError> {
+    Duration::from_std(duration).map_err(|_| DurationError::Overflow)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// `1 hour, 30 minutes`
+    Long,
+    /// `1h30m`
+    Compact,
+}
+
+/// 时间间隔的格式。`precision`是从最大的非零单位开始最多显示的单位个数，更小的单位被截去。
+#[derive(Debug, Clone, Copy)]
+pub struct DurationFormat {
+    style: Style,
+    precision: usize,
+}
+
+const UNITS: [(&str, &str, &str, i64); 5] = [
+    ("day", "days", "d", 86_400_000),
+    ("hour", "hours", "h", 3_600_000),
+    ("minute", "minutes", "m", 60_000),
+    ("second", "seconds", "s", 1_000),
+    ("millisecond", "milliseconds", "ms", 1),
+];
+
+impl DurationFormat {
+    pub fn long() -> Self {
+        DurationFormat {
+            style: Style::Long,
+            precision: UNITS.len(),
+        }
+    }
+
+    pub fn compact() -> Self {
+        DurationFormat {
+            style: Style::Compact,
+            precision: UNITS.len(),
+        }
+    }
+
+    pub fn precision(mut self, precision: usize) -> Self {
+        self.precision = precision.max(1);
+        self
+    }
+
+    pub fn format(&self, duration: Duration) -> String {
+        let sign = if duration < Duration::zero() { "-" } else { "" };
+        let mut millis = duration.abs().num_milliseconds();
+
+        let largest = UNITS
+            .iter()
+            .position(|unit| millis >= unit.3)
+            .unwrap_or(UNITS.len() - 1);
+        let mut parts = vec![];
+        for (singular, plural, short, size) in UNITS.iter().skip(largest).take(self.precision) {
+            let amount = millis / size;
+            millis %= size;
+            if amount == 0 && !parts.is_empty() {
+                continue;
+            }
+            parts.push(match self.style {
+                Style::Long if amount == 1 => format!("{} {}", amount, singular),
+                Style::Long => format!("{} {}", amount, plural),
+                Style::Compact => format!("{}{}", amount, short),
+            });
+        }
+
+        let separator = match self.style {
+            Style::Long => ", ",
+            Style::Compact => "",
+        };
+        format!("{}{}", sign, parts.join(separator))
+    }
+}
+
+/// # 解析和格式化时间间隔
+pub fn parse_and_format_durations() -> Result<(), DurationError> {
+    let duration = parse_duration("1h30m15s")?;
+    assert_eq!(duration, Duration::seconds(5415));
+    println!(
+        "1h30m15s => {} / {} / {}",
+        DurationFormat::long().format(duration),
+        DurationFormat::compact().precision(2).format(duration),
+        to_iso8601(duration)
+    );
+    assert_eq!(
+        DurationFormat::long().format(duration),
+        "1 hour, 30 minutes, 15 seconds"
+    );
+    assert_eq!(
+        DurationFormat::compact().precision(2).format(duration),
+        "1h30m"
+    );
+
+    // 两种格式都可以被重新解析
+    for format in [DurationFormat::long(), DurationFormat::compact()] {
+        assert_eq!(parse_duration(&format.format(duration))?, duration);
+    }
+
+    let iso = parse_iso8601("P3DT4H")?;
+    assert_eq!(iso, Duration::days(3) + Duration::hours(4));
+    assert_eq!(to_iso8601(iso), "P3DT4H");
+    let fraction = parse_iso8601("PT1M0.25S")?;
+    assert_eq!(fraction, Duration::milliseconds(60_250));
+    assert_eq!(to_iso8601(fraction), "PT1M0.25S");
+    assert_eq!(to_iso8601(Duration::zero()), "PT0S");
+
+    let std_duration = to_std(parse_duration("2 days, 3 hours")?)?;
+    assert_eq!(std_duration, std::time::Duration::from_secs(183_600));
+    assert_eq!(
+        from_std(std_duration)?,
+        Duration::days(2) + Duration::hours(3)
+    );
+
+    let failures = [
+        ("-5m", DurationError::Negative),
+        ("9999999999999999d", DurationError::Overflow),
+        (
+            "3 fortnights",
+            DurationError::UnknownUnit("fortnights".to_string()),
+        ),
+        ("P1Y2M", DurationError::CalendarUnit('Y')),
+        ("P1H", DurationError::Invalid("P1H".to_string())),
+    ];
+    for (text, expected) in failures {
+        let parsed = if text.starts_with('P') {
+            parse_iso8601(text)
+        } else {
+            parse_duration(text)
+        };
+        match parsed.and_then(to_std) {
+            Err(err) => {
+                println!("{} => {}", text, err);
+                assert_eq!(err, expected);
+            }
+            Ok(duration) => panic!("{}不应该被解析为{:?}", text, duration),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,379 @@
+//! # 解析格式不确定的时间字符串
+//! `parse_string_into_datetime_struct`要求调用者事先知道字符串的格式。
+//! 日志中的时间格式往往混杂，`FuzzyParser`按优先级依次尝试：
+//!
+//! 1. 相对时间：`now`、`yesterday 14:00`、`in 3 days`、`2 hours ago`、`last Friday`；
+//! 2. 纯数字的Unix时间戳，9-10位按秒（1973年之后），12-13位按毫秒；
+//!    8位数字留给`%Y%m%d`格式的日期，其他长度的数字不当作时间戳；
+//! 3. 一组已知格式：RFC 3339、RFC 2822、带时区的自定义格式、不带时区的日期时间和日期。
+//!
+//! 相对时间以及不带时区的时间都相对于调用者提供的`now`及其时区计算，结果中记录匹配的规则。
+use chrono::{
+    DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Weekday,
+};
+use std::error::Error;
+use std::fmt;
+
+/// 带时区偏移的格式
+const ZONED_FORMATS: [&str; 3] = [
+    "%d.%m.%Y %H:%M %P %z",
+    "%Y-%m-%d %H:%M:%S %z",
+    "%d/%b/%Y:%H:%M:%S %z",
+];
+
+/// 不带时区的日期时间格式，`{dm}`按日月顺序替换为`%d/%m`或`%m/%d`
+const LOCAL_FORMATS: [&str; 7] = [
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%d %H:%M",
+    "%Y/%m/%d %H:%M:%S",
+    "%b %d %Y %H:%M:%S",
+    "{dm}/%Y %H:%M:%S",
+    "{dm}/%Y %H:%M",
+];
+
+/// 只有日期的格式，时间取当天0点
+const DATE_FORMATS: [&str; 4] = ["%Y-%m-%d", "%Y/%m/%d", "%Y%m%d", "{dm}/%Y"];
+
+/// 数字日期中日和月的顺序，例如`05/08/2022`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DayOrder {
+    /// 5月8日之前先尝试8月5日
+    DayFirst,
+    /// 先尝试5月8日
+    MonthFirst,
+}
+
+/// 解析成功时匹配的规则
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    /// 固定格式，RFC 3339和RFC 2822分别记为`rfc3339`和`rfc2822`
+    Format(String),
+    EpochSeconds,
+    EpochMillis,
+    /// `now`、`today`、`yesterday`、`tomorrow`
+    RelativeDay,
+    /// `in 3 days`、`2 hours ago`
+    RelativeOffset,
+    /// `last Friday`、`next Monday`、`Friday`
+    Weekday,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FuzzyError {
+    /// 没有任何规则能够解析
+    Unrecognized(String),
+    /// 解析出的本地时间在时区中不存在
+    Nonexistent(NaiveDateTime),
+}
+
+impl fmt::Display for FuzzyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FuzzyError::Unrecognized(input) => write!(f, "无法识别的时间：{}", input),
+            FuzzyError::Nonexistent(local) => write!(f, "本地时间{}在时区中不存在", local),
+        }
+    }
+}
+
+impl Error for FuzzyError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parsed<Tz: TimeZone> {
+    pub time: DateTime<Tz>,
+    pub rule: Rule,
+}
+
+#[derive(Debug, Clone)]
+pub struct FuzzyParser {
+    day_order: DayOrder,
+}
+
+impl Default for FuzzyParser {
+    fn default() -> Self {
+        FuzzyParser::new(DayOrder::DayFirst)
+    }
+}
+
+impl FuzzyParser {
+    pub fn new(day_order: DayOrder) -> Self {
+        FuzzyParser { day_order }
+    }
+
+    /// 按优先级展开`{dm}`：首选的日月顺序在前，另一种顺序在后
+    fn expand(&self, formats: &[&str]) -> Vec<String> {
+        let (first, second) = match self.day_order {
+            DayOrder::DayFirst => ("%d/%m", "%m/%d"),
+            DayOrder::MonthFirst => ("%m/%d", "%d/%m"),
+        };
+
+        let mut expanded: Vec<String> = formats
+            .iter()
+            .map(|format| format.replace("{dm}", first))
+            .collect();
+        expanded.extend(
+            formats
+                .iter()
+                .filter(|format| format.contains("{dm}"))
+                .map(|format| format.replace("{dm}", second)),
+        );
+        expanded
+    }
+
+    pub fn parse<Tz: TimeZone>(
+        &self,
+        input: &str,
+        now: &DateTime<Tz>,
+    ) -> Result<Parsed<Tz>, FuzzyError> {
+        let input = input.trim();
+        let tz = now.timezone();
+
+        if let Some(parsed) = parse_relative(input, now)? {
+            return Ok(parsed);
+        }
+
+        if !input.is_empty() && input.len() <= 13 && input.bytes().all(|b| b.is_ascii_digit()) {
+            let value: i64 = input.parse().unwrap();
+            let (time, rule) = match input.len() {
+                9 | 10 => (tz.timestamp_opt(value, 0).single(), Rule::EpochSeconds),
+                12 | 13 => (tz.timestamp_millis_opt(value).single(), Rule::EpochMillis),
+                _ => (None, Rule::EpochSeconds),
+            };
+            if let Some(time) = time {
+                return Ok(Parsed { time, rule });
+            }
+        }
+
+        if let Ok(time) = DateTime::parse_from_rfc3339(input) {
+            return Ok(parsed_format(time.with_timezone(&tz), "rfc3339"));
+        }
+        if let Ok(time) = DateTime::parse_from_rfc2822(input) {
+            return Ok(parsed_format(time.with_timezone(&tz), "rfc2822"));
+        }
+        for format in ZONED_FORMATS {
+            if let Ok(time) = DateTime::parse_from_str(input, format) {
+                return Ok(parsed_format(time.with_timezone(&tz), format));
+            }
+        }
+        for format in self.expand(&LOCAL_FORMATS) {
+            if let Ok(local) = NaiveDateTime::parse_from_str(input, &format) {
+                return Ok(parsed_format(resolve(&tz, local)?, &format));
+            }
+        }
+        for format in self.expand(&DATE_FORMATS) {
+            if let Ok(date) = NaiveDate::parse_from_str(input, &format) {
+                return Ok(parsed_format(
+                    resolve(&tz, date.and_time(NaiveTime::MIN))?,
+                    &format,
+                ));
+            }
+        }
+
+        Err(FuzzyError::Unrecognized(input.to_string()))
+    }
+}
+
+fn parsed_format<Tz: TimeZone>(time: DateTime<Tz>, format: &str) -> Parsed<Tz> {
+    Parsed {
+        time,
+        rule: Rule::Format(format.to_string()),
+    }
+}
+
+/// 本地时间有歧义时取较早的一个
+fn resolve<Tz: TimeZone>(tz: &Tz, local: NaiveDateTime) -> Result<DateTime<Tz>, FuzzyError> {
+    tz.from_local_datetime(&local)
+        .earliest()
+        .ok_or(FuzzyError::Nonexistent(local))
+}
+
+fn parse_time_of_day(text: Option<&str>) -> Option<NaiveTime> {
+    match text {
+        None => Some(NaiveTime::MIN),
+        Some(text) => NaiveTime::parse_from_str(text, "%H:%M:%S")
+            .or_else(|_| NaiveTime::parse_from_str(text, "%H:%M"))
+            .ok(),
+    }
+}
+
+fn parse_unit(unit: &str) -> Option<Duration> {
+    let unit = unit.strip_suffix('s').unwrap_or(unit);
+    match unit {
+        "second" | "sec" => Some(Duration::seconds(1)),
+        "minute" | "min" => Some(Duration::minutes(1)),
+        "hour" => Some(Duration::hours(1)),
+        "day" => Some(Duration::days(1)),
+        "week" => Some(Duration::weeks(1)),
+        _ => None,
+    }
+}
+
+/// 解析相对时间，输入不是相对时间时返回`Ok(None)`
+fn parse_relative<Tz: TimeZone>(
+    input: &str,
+    now: &DateTime<Tz>,
+) -> Result<Option<Parsed<Tz>>, FuzzyError> {
+    let lower = input.to_lowercase();
+    let words: Vec<&str> = lower.split_whitespace().collect();
+    let today = now.naive_local().date();
+    let tz = now.timezone();
+
+    let at_day = |date: NaiveDate, time: Option<&str>, rule: Rule| -> Result<_, FuzzyError> {
+        match parse_time_of_day(time) {
+            Some(time) => Ok(Some(Parsed {
+                time: resolve(&tz, date.and_time(time))?,
+                rule,
+            })),
+            None => Ok(None),
+        }
+    };
+    let offset = |amount: &str, unit: &str, sign: i32| {
+        let amount: i32 = amount.parse().ok()?;
+        let unit = parse_unit(unit)?;
+        let delta = unit.checked_mul(amount.checked_mul(sign)?)?;
+        // 以天为单位时按本地日期计算，跨越夏令时后仍是同一个钟点
+        let time = if unit >= Duration::days(1) {
+            resolve(&tz, now.naive_local().checked_add_signed(delta)?).ok()?
+        } else {
+            now.clone().checked_add_signed(delta)?
+        };
+        Some(Parsed {
+            time,
+            rule: Rule::RelativeOffset,
+        })
+    };
+
+    match words.as_slice() {
+        ["now"] => Ok(Some(Parsed {
+            time: now.clone(),
+            rule: Rule::RelativeDay,
+        })),
+        [day @ ("today" | "yesterday" | "tomorrow"), time @ ..] if time.len() <= 1 => {
+            let date = match *day {
+                "yesterday" => today.pred_opt(),
+                "tomorrow" => today.succ_opt(),
+                _ => Some(today),
+            };
+            match date {
+                Some(date) => at_day(date, time.first().copied(), Rule::RelativeDay),
+                None => Ok(None),
+            }
+        }
+        ["in", amount, unit] => Ok(offset(amount, unit, 1)),
+        [amount, unit, "ago"] => Ok(offset(amount, unit, -1)),
+        [direction @ ("last" | "next" | "this"), weekday, time @ ..] if time.len() <= 1 => {
+            match weekday.parse::<Weekday>() {
+                Ok(weekday) => {
+                    let date = shift_to_weekday(today, weekday, direction);
+                    at_day(date, time.first().copied(), Rule::Weekday)
+                }
+                Err(_) => Ok(None),
+            }
+        }
+        [weekday, time @ ..] if time.len() <= 1 => match weekday.parse::<Weekday>() {
+            Ok(weekday) => {
+                let date = shift_to_weekday(today, weekday, "this");
+                at_day(date, time.first().copied(), Rule::Weekday)
+            }
+            Err(_) => Ok(None),
+        },
+        _ => Ok(None),
+    }
+}
+
+/// `last`是今天之前最近的一天，`next`是今天之后最近的一天，`this`是从今天开始一周内的一天
+fn shift_to_weekday(today: NaiveDate, weekday: Weekday, direction: &str) -> NaiveDate {
+    let ahead = (7 + weekday.num_days_from_monday() as i64
+        - today.weekday().num_days_from_monday() as i64)
+        % 7;
+    let days = match direction {
+        "last" => ahead - 7,
+        "next" if ahead == 0 => 7,
+        _ => ahead,
+    };
+    today + Duration::days(days)
+}
+
+/// # 解析格式混杂的时间字符串
+/// 以上海时间2022-03-09 10:00（星期三）作为当前时间。
+pub fn parse_mixed_formats() -> Result<(), FuzzyError> {
+    let now = chrono_tz::Asia::Shanghai
+        .with_ymd_and_hms(2022, 3, 9, 10, 0, 0)
+        .unwrap();
+    let parser = FuzzyParser::default();
+
+    let cases = [
+        (
+            "yesterday 14:00",
+            "2022-03-08T14:00:00+08:00",
+            Rule::RelativeDay,
+        ),
+        (
+            "in 3 days",
+            "2022-03-12T10:00:00+08:00",
+            Rule::RelativeOffset,
+        ),
+        (
+            "2 hours ago",
+            "2022-03-09T08:00:00+08:00",
+            Rule::RelativeOffset,
+        ),
+        ("last Friday", "2022-03-04T00:00:00+08:00", Rule::Weekday),
+        ("next wed 9:30", "2022-03-16T09:30:00+08:00", Rule::Weekday),
+        (
+            "1646791200",
+            "2022-03-09T10:00:00+08:00",
+            Rule::EpochSeconds,
+        ),
+        (
+            "1646791200500",
+            "2022-03-09T10:00:00.500+08:00",
+            Rule::EpochMillis,
+        ),
+        (
+            "Tue, 1 Jul 2003 10:52:37 +0200",
+            "2003-07-01T16:52:37+08:00",
+            Rule::Format("rfc2822".to_string()),
+        ),
+        (
+            "5.8.1994 8:00 am +0000",
+            "1994-08-05T16:00:00+08:00",
+            Rule::Format("%d.%m.%Y %H:%M %P %z".to_string()),
+        ),
+        (
+            "05/08/2022 12:30",
+            "2022-08-05T12:30:00+08:00",
+            Rule::Format("%d/%m/%Y %H:%M".to_string()),
+        ),
+        (
+            "25/12/2022",
+            "2022-12-25T00:00:00+08:00",
+            Rule::Format("%d/%m/%Y".to_string()),
+        ),
+    ];
+
+    for (input, expected, rule) in cases {
+        let parsed = parser.parse(input, &now)?;
+        println!(
+            "{:32} => {} {:?}",
+            input,
+            parsed.time.to_rfc3339(),
+            parsed.rule
+        );
+        assert_eq!(parsed.time.to_rfc3339(), expected);
+        assert_eq!(parsed.rule, rule);
+    }
+
+    // 月在前时，05/08是5月8日；12/25无法按日月顺序解析，退回到月日顺序
+    let month_first = FuzzyParser::new(DayOrder::MonthFirst);
+    let parsed = month_first.parse("05/08/2022", &now)?;
+    assert_eq!(parsed.time.to_rfc3339(), "2022-05-08T00:00:00+08:00");
+    let parsed = parser.parse("12/25/2022", &now)?;
+    assert_eq!(parsed.rule, Rule::Format("%m/%d/%Y".to_string()));
+
+    if let Err(err) = parser.parse("the day after never", &now) {
+        println!("{}", err);
+    }
+
+    Ok(())
+}
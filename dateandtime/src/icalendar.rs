@@ -0,0 +1,1287 @@
+//! # iCalendar（RFC 5545）读写和重复规则展开
+//! 读取和写入`.ics`文件中的`VEVENT`和`VTODO`，并把`RRULE`、`RDATE`、`EXDATE`展开为时间窗口内的具体发生时间，
+//! 这样不需要日历服务器也能离线计算会议室的重复预约。
+//!
+//! - `TZID`参数按IANA时区名字解析（`VTIMEZONE`中的定义被忽略），重复事件在本地时间上展开，
+//!   跨越夏令时后仍是同一个钟点，不存在的本地时间按跳变前的偏移量计算；
+//! - 支持的`RRULE`部分：`FREQ`（DAILY、WEEKLY、MONTHLY、YEARLY）、`INTERVAL`、`COUNT`、`UNTIL`、
+//!   `BYMONTH`、`BYMONTHDAY`、`BYDAY`（包括`1FR`、`-1SU`这样的序号）、`BYSETPOS`和`WKST`；
+//! - 不认识的属性原样保留，写出时按原样输出。
+use crate::durations::{parse_iso8601, to_iso8601};
+use chrono::{
+    DateTime, Datelike, Duration, Months, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeZone,
+    Utc, Weekday,
+};
+use chrono_tz::Tz;
+use std::error::Error;
+use std::fmt;
+
+/// 展开时最多检查的周期数，防止永远不会匹配的规则（如2月31日）陷入死循环
+const MAX_PERIODS: u32 = 100_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IcsError {
+    /// 第`line`行（展开折行之后）无法解析
+    Parse {
+        line: usize,
+        message: String,
+    },
+    UnknownTimeZone(String),
+    /// 合法但是没有实现的部分
+    Unsupported(String),
+}
+
+impl fmt::Display for IcsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IcsError::Parse { line, message } => write!(f, "第{}行解析错误：{}", line, message),
+            IcsError::UnknownTimeZone(tzid) => write!(f, "未知的TZID：{}", tzid),
+            IcsError::Unsupported(what) => write!(f, "不支持：{}", what),
+        }
+    }
+}
+
+impl Error for IcsError {}
+
+/// `DTSTART`等属性的值
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DateValue {
+    /// 全天，`VALUE=DATE`
+    Date(NaiveDate),
+    /// 不带时区的本地时间，由查看者的时区决定
+    Floating(NaiveDateTime),
+    /// 以`Z`结尾的UTC时间
+    Utc(NaiveDateTime),
+    /// 带`TZID`的本地时间
+    Zoned(NaiveDateTime, Tz),
+}
+
+impl DateValue {
+    fn local(&self) -> NaiveDateTime {
+        match self {
+            DateValue::Date(date) => date.and_time(NaiveTime::MIN),
+            DateValue::Floating(local) | DateValue::Utc(local) | DateValue::Zoned(local, _) => {
+                *local
+            }
+        }
+    }
+
+    /// 用同样的类型和时区包装另一个本地时间
+    fn with_local(&self, local: NaiveDateTime) -> DateValue {
+        match self {
+            DateValue::Date(_) => DateValue::Date(local.date()),
+            DateValue::Floating(_) => DateValue::Floating(local),
+            DateValue::Utc(_) => DateValue::Utc(local),
+            DateValue::Zoned(_, tz) => DateValue::Zoned(local, *tz),
+        }
+    }
+
+    /// 转换为绝对时间，全天和不带时区的时间使用`viewer`时区
+    pub fn instant<V: TimeZone>(&self, viewer: &V) -> DateTime<Utc> {
+        match self {
+            DateValue::Date(_) | DateValue::Floating(_) => local_instant(viewer, self.local()),
+            DateValue::Utc(local) => Utc.from_utc_datetime(local),
+            DateValue::Zoned(local, tz) => local_instant(tz, *local),
+        }
+    }
+
+    /// 把绝对时间转换为和本值同一个时区的本地时间
+    fn local_of<V: TimeZone>(&self, instant: &DateTime<Utc>, viewer: &V) -> NaiveDateTime {
+        match self {
+            DateValue::Date(_) | DateValue::Floating(_) => {
+                instant.with_timezone(viewer).naive_local()
+            }
+            DateValue::Utc(_) => instant.naive_utc(),
+            DateValue::Zoned(_, tz) => instant.with_timezone(tz).naive_local(),
+        }
+    }
+
+    fn parse(line: usize, params: &[(String, String)], value: &str) -> Result<DateValue, IcsError> {
+        let error = |message: String| IcsError::Parse { line, message };
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        if param("VALUE") == Some("DATE") || value.len() == 8 {
+            return NaiveDate::parse_from_str(value, "%Y%m%d")
+                .map(DateValue::Date)
+                .map_err(|_| error(format!("无效的日期：{}", value)));
+        }
+
+        let (local, utc) = match value.strip_suffix('Z') {
+            Some(local) => (local, true),
+            None => (value, false),
+        };
+        let local = NaiveDateTime::parse_from_str(local, "%Y%m%dT%H%M%S")
+            .map_err(|_| error(format!("无效的日期时间：{}", value)))?;
+        match (utc, param("TZID")) {
+            (true, _) => Ok(DateValue::Utc(local)),
+            (false, Some(tzid)) => tzid
+                .trim_start_matches('/')
+                .parse::<Tz>()
+                .map(|tz| DateValue::Zoned(local, tz))
+                .map_err(|_| IcsError::UnknownTimeZone(tzid.to_string())),
+            (false, None) => Ok(DateValue::Floating(local)),
+        }
+    }
+
+    /// 属性的参数部分（以`;`开头）
+    fn params(&self) -> String {
+        match self {
+            DateValue::Date(_) => ";VALUE=DATE".to_string(),
+            DateValue::Zoned(_, tz) => format!(";TZID={}", tz.name()),
+            _ => String::new(),
+        }
+    }
+}
+
+impl fmt::Display for DateValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DateValue::Date(date) => write!(f, "{}", date.format("%Y%m%d")),
+            DateValue::Utc(local) => write!(f, "{}Z", local.format("%Y%m%dT%H%M%S")),
+            DateValue::Floating(local) | DateValue::Zoned(local, _) => {
+                write!(f, "{}", local.format("%Y%m%dT%H%M%S"))
+            }
+        }
+    }
+}
+
+/// 本地时间转换为绝对时间：重复的时间取较早的一个，不存在的时间按跳变前的偏移量计算
+fn local_instant<V: TimeZone>(tz: &V, local: NaiveDateTime) -> DateTime<Utc> {
+    match tz.from_local_datetime(&local).earliest() {
+        Some(time) => time.with_timezone(&Utc),
+        None => {
+            let before = tz.offset_from_utc_datetime(&(local - Duration::days(1)));
+            Utc.from_utc_datetime(&(local - before.fix()))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// 解析后的`RRULE`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RRule {
+    pub freq: Frequency,
+    pub interval: u32,
+    pub count: Option<u32>,
+    pub until: Option<DateValue>,
+    pub by_month: Vec<u32>,
+    pub by_month_day: Vec<i32>,
+    /// 星期以及可选的序号，例如`1FR`是`(Some(1), Fri)`
+    pub by_day: Vec<(Option<i32>, Weekday)>,
+    pub by_set_pos: Vec<i32>,
+    pub week_start: Weekday,
+}
+
+const WEEKDAY_CODES: [(&str, Weekday); 7] = [
+    ("MO", Weekday::Mon),
+    ("TU", Weekday::Tue),
+    ("WE", Weekday::Wed),
+    ("TH", Weekday::Thu),
+    ("FR", Weekday::Fri),
+    ("SA", Weekday::Sat),
+    ("SU", Weekday::Sun),
+];
+
+fn parse_weekday(code: &str) -> Result<Weekday, String> {
+    WEEKDAY_CODES
+        .iter()
+        .find(|(name, _)| *name == code)
+        .map(|(_, weekday)| *weekday)
+        .ok_or_else(|| format!("无效的星期：{}", code))
+}
+
+fn weekday_code(weekday: Weekday) -> &'static str {
+    WEEKDAY_CODES[weekday.num_days_from_monday() as usize].0
+}
+
+fn parse_list<T: std::str::FromStr>(name: &str, value: &str) -> Result<Vec<T>, String> {
+    value
+        .split(',')
+        .map(|item| {
+            item.parse()
+                .map_err(|_| format!("{}的值无效：{}", name, item))
+        })
+        .collect()
+}
+
+impl RRule {
+    fn parse(line: usize, value: &str) -> Result<RRule, IcsError> {
+        let invalid = |message: String| IcsError::Unsupported(format!("RRULE {}", message));
+        let mut freq = None;
+        let mut rule = RRule {
+            freq: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_month: vec![],
+            by_month_day: vec![],
+            by_day: vec![],
+            by_set_pos: vec![],
+            week_start: Weekday::Mon,
+        };
+
+        for part in value.split(';') {
+            let (name, value) = part
+                .split_once('=')
+                .ok_or_else(|| invalid(part.to_string()))?;
+            match name {
+                "FREQ" => {
+                    freq = Some(match value {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        other => return Err(invalid(format!("FREQ={}", other))),
+                    })
+                }
+                "INTERVAL" => {
+                    rule.interval = value
+                        .parse()
+                        .ok()
+                        .filter(|interval| *interval > 0)
+                        .ok_or_else(|| invalid(part.to_string()))?
+                }
+                "COUNT" => rule.count = Some(value.parse().map_err(|_| invalid(part.to_string()))?),
+                "UNTIL" => rule.until = Some(DateValue::parse(line, &[], value)?),
+                "BYMONTH" => rule.by_month = parse_list(name, value).map_err(invalid)?,
+                "BYMONTHDAY" => rule.by_month_day = parse_list(name, value).map_err(invalid)?,
+                "BYSETPOS" => rule.by_set_pos = parse_list(name, value).map_err(invalid)?,
+                "WKST" => rule.week_start = parse_weekday(value).map_err(invalid)?,
+                "BYDAY" => {
+                    for item in value.split(',') {
+                        // 按字节切分星期代码，非ASCII的输入不合法，也不能在字符中间切开
+                        if !item.is_ascii() {
+                            return Err(invalid(item.to_string()));
+                        }
+                        let split = item.len().saturating_sub(2);
+                        let weekday = parse_weekday(&item[split..]).map_err(invalid)?;
+                        let ordinal = match &item[..split] {
+                            "" => None,
+                            ordinal => Some(
+                                ordinal
+                                    .trim_start_matches('+')
+                                    .parse()
+                                    .map_err(|_| invalid(item.to_string()))?,
+                            ),
+                        };
+                        rule.by_day.push((ordinal, weekday));
+                    }
+                }
+                other => return Err(invalid(other.to_string())),
+            }
+        }
+
+        rule.freq = freq.ok_or_else(|| invalid("缺少FREQ".to_string()))?;
+        Ok(rule)
+    }
+
+    fn matches_weekday(&self, date: NaiveDate) -> bool {
+        self.by_day.is_empty()
+            || self
+                .by_day
+                .iter()
+                .any(|(_, weekday)| *weekday == date.weekday())
+    }
+
+    /// 某个月中符合`BYMONTHDAY`和`BYDAY`的日期，两个都没有时使用`DTSTART`的日
+    fn days_in_month(&self, year: i32, month: u32, start: NaiveDate) -> Vec<NaiveDate> {
+        let first = match NaiveDate::from_ymd_opt(year, month, 1) {
+            Some(first) => first,
+            None => return vec![],
+        };
+        let days: Vec<NaiveDate> = first
+            .iter_days()
+            .take_while(|date| date.month() == month)
+            .collect();
+
+        let by_month_day: Vec<NaiveDate> = self
+            .by_month_day
+            .iter()
+            .filter_map(|day| match *day {
+                day if day > 0 => days.get(day as usize - 1).copied(),
+                day if day < 0 => days
+                    .len()
+                    .checked_sub(day.unsigned_abs() as usize)
+                    .map(|i| days[i]),
+                _ => None,
+            })
+            .collect();
+
+        match (self.by_month_day.is_empty(), self.by_day.is_empty()) {
+            (true, true) => days
+                .get(start.day() as usize - 1)
+                .copied()
+                .into_iter()
+                .collect(),
+            (false, true) => by_month_day,
+            (true, false) => select_weekdays(&days, &self.by_day),
+            (false, false) => by_month_day
+                .into_iter()
+                .filter(|date| self.matches_weekday(*date))
+                .collect(),
+        }
+    }
+
+    /// 第`period`个周期内的候选日期（未排序）
+    fn candidates(&self, start: NaiveDate, period: u32) -> Vec<NaiveDate> {
+        let step = period.saturating_mul(self.interval);
+        let in_months =
+            |date: &NaiveDate| self.by_month.is_empty() || self.by_month.contains(&date.month());
+
+        match self.freq {
+            Frequency::Daily => start
+                .checked_add_signed(Duration::days(step as i64))
+                .into_iter()
+                .filter(|date| in_months(date) && self.matches_weekday(*date))
+                .filter(|date| {
+                    self.by_month_day.is_empty()
+                        || self
+                            .days_in_month(date.year(), date.month(), *date)
+                            .contains(date)
+                })
+                .collect(),
+            Frequency::Weekly => {
+                let offset = (7 + start.weekday().num_days_from_monday()
+                    - self.week_start.num_days_from_monday())
+                    % 7;
+                let week = start - Duration::days(offset as i64) + Duration::weeks(step as i64);
+                week.iter_days()
+                    .take(7)
+                    .filter(|date| {
+                        if self.by_day.is_empty() {
+                            date.weekday() == start.weekday()
+                        } else {
+                            self.matches_weekday(*date)
+                        }
+                    })
+                    .filter(in_months)
+                    .collect()
+            }
+            Frequency::Monthly => {
+                let month = match NaiveDate::from_ymd_opt(start.year(), start.month(), 1)
+                    .and_then(|first| first.checked_add_months(Months::new(step)))
+                {
+                    Some(month) => month,
+                    None => return vec![],
+                };
+                if !in_months(&month) {
+                    return vec![];
+                }
+                self.days_in_month(month.year(), month.month(), start)
+            }
+            Frequency::Yearly => {
+                let year = start.year() + step as i32;
+                if !self.by_month.is_empty() {
+                    self.by_month
+                        .iter()
+                        .flat_map(|month| self.days_in_month(year, *month, start))
+                        .collect()
+                } else if !self.by_month_day.is_empty() {
+                    (1..=12)
+                        .flat_map(|month| self.days_in_month(year, month, start))
+                        .collect()
+                } else if !self.by_day.is_empty() {
+                    let days: Vec<NaiveDate> = NaiveDate::from_ymd_opt(year, 1, 1)
+                        .into_iter()
+                        .flat_map(|first| first.iter_days().take_while(|d| d.year() == year))
+                        .collect();
+                    select_weekdays(&days, &self.by_day)
+                } else {
+                    NaiveDate::from_ymd_opt(year, start.month(), start.day())
+                        .into_iter()
+                        .collect()
+                }
+            }
+        }
+    }
+
+    /// 从`start`开始展开，`limit`之后不再生成
+    fn expand(
+        &self,
+        start: NaiveDateTime,
+        until: Option<NaiveDateTime>,
+        limit: NaiveDateTime,
+    ) -> Vec<NaiveDateTime> {
+        let mut occurrences = vec![];
+
+        for period in 0..MAX_PERIODS {
+            let mut dates = self.candidates(start.date(), period);
+            dates.sort();
+            dates.dedup();
+            if !self.by_set_pos.is_empty() {
+                dates = select_positions(&dates, &self.by_set_pos);
+            }
+
+            let mut beyond = false;
+            for date in dates {
+                let occurrence = date.and_time(start.time());
+                if occurrence < start {
+                    continue;
+                }
+                if until.is_some_and(|until| occurrence > until) || occurrence > limit {
+                    beyond = true;
+                    break;
+                }
+                occurrences.push(occurrence);
+                if self
+                    .count
+                    .is_some_and(|count| occurrences.len() >= count as usize)
+                {
+                    return occurrences;
+                }
+            }
+            if beyond {
+                break;
+            }
+        }
+
+        occurrences
+    }
+}
+
+/// 按`BYDAY`在`days`（一个月或者一年）中选择日期，带序号时选择第n个或者倒数第n个
+fn select_weekdays(days: &[NaiveDate], by_day: &[(Option<i32>, Weekday)]) -> Vec<NaiveDate> {
+    by_day
+        .iter()
+        .flat_map(|(ordinal, weekday)| {
+            let matching: Vec<NaiveDate> = days
+                .iter()
+                .filter(|date| date.weekday() == *weekday)
+                .copied()
+                .collect();
+            match ordinal {
+                None => matching,
+                Some(ordinal) => select_positions(&matching, &[*ordinal]),
+            }
+        })
+        .collect()
+}
+
+/// 选择第n个（n为负数时倒数第n个）元素，位置从1开始
+fn select_positions<T: Copy + Ord>(items: &[T], positions: &[i32]) -> Vec<T> {
+    let mut selected: Vec<T> = positions
+        .iter()
+        .filter_map(|pos| match *pos {
+            pos if pos > 0 => items.get(pos as usize - 1).copied(),
+            pos if pos < 0 => items
+                .len()
+                .checked_sub(pos.unsigned_abs() as usize)
+                .map(|i| items[i]),
+            _ => None,
+        })
+        .collect();
+    selected.sort();
+    selected
+}
+
+impl fmt::Display for RRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let freq = match self.freq {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+            Frequency::Yearly => "YEARLY",
+        };
+        write!(f, "FREQ={}", freq)?;
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={}", count)?;
+        }
+        if let Some(until) = &self.until {
+            write!(f, ";UNTIL={}", until)?;
+        }
+        let join = |items: Vec<String>| items.join(",");
+        if !self.by_month.is_empty() {
+            write!(
+                f,
+                ";BYMONTH={}",
+                join(self.by_month.iter().map(|m| m.to_string()).collect())
+            )?;
+        }
+        if !self.by_month_day.is_empty() {
+            write!(
+                f,
+                ";BYMONTHDAY={}",
+                join(self.by_month_day.iter().map(|d| d.to_string()).collect())
+            )?;
+        }
+        if !self.by_day.is_empty() {
+            let days = self
+                .by_day
+                .iter()
+                .map(|(ordinal, weekday)| match ordinal {
+                    Some(ordinal) => format!("{}{}", ordinal, weekday_code(*weekday)),
+                    None => weekday_code(*weekday).to_string(),
+                })
+                .collect();
+            write!(f, ";BYDAY={}", join(days))?;
+        }
+        if !self.by_set_pos.is_empty() {
+            write!(
+                f,
+                ";BYSETPOS={}",
+                join(self.by_set_pos.iter().map(|p| p.to_string()).collect())
+            )?;
+        }
+        if self.week_start != Weekday::Mon {
+            write!(f, ";WKST={}", weekday_code(self.week_start))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComponentKind {
+    Event,
+    Todo,
+}
+
+/// 一个`VEVENT`或`VTODO`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Component {
+    pub kind: ComponentKind,
+    pub uid: Option<String>,
+    pub summary: Option<String>,
+    pub start: Option<DateValue>,
+    /// `VEVENT`的`DTEND`或者`VTODO`的`DUE`
+    pub end: Option<DateValue>,
+    pub duration: Option<Duration>,
+    pub rrule: Option<RRule>,
+    pub rdates: Vec<DateValue>,
+    pub exdates: Vec<DateValue>,
+    /// 其他属性：属性名（包括参数）和值
+    pub extra: Vec<(String, String)>,
+}
+
+/// 展开后的一次发生
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Occurrence {
+    pub start: DateValue,
+    pub end: Option<DateValue>,
+}
+
+impl Component {
+    fn new(kind: ComponentKind) -> Self {
+        Component {
+            kind,
+            uid: None,
+            summary: None,
+            start: None,
+            end: None,
+            duration: None,
+            rrule: None,
+            rdates: vec![],
+            exdates: vec![],
+            extra: vec![],
+        }
+    }
+
+    /// 展开`[from, to)`时间窗口内的发生时间，全天和不带时区的时间按`from`的时区计算
+    pub fn occurrences<V: TimeZone>(
+        &self,
+        from: &DateTime<V>,
+        to: &DateTime<V>,
+    ) -> Vec<Occurrence> {
+        let start = match &self.start {
+            Some(start) => start,
+            None => return vec![],
+        };
+        let viewer = from.timezone();
+        let (from, to) = (from.with_timezone(&Utc), to.with_timezone(&Utc));
+
+        let mut locals = match &self.rrule {
+            Some(rule) => {
+                let until = rule.until.as_ref().map(|until| match until {
+                    DateValue::Date(date) => {
+                        date.and_time(NaiveTime::MIN) + Duration::days(1) - Duration::seconds(1)
+                    }
+                    until => start.local_of(&until.instant(&viewer), &viewer),
+                });
+                // 多留一天，避免时区偏移导致窗口末尾的发生时间被漏掉
+                let limit = start.local_of(&to, &viewer) + Duration::days(1);
+                rule.expand(start.local(), until, limit)
+            }
+            None => vec![start.local()],
+        };
+        locals.extend(self.rdates.iter().map(|rdate| match rdate {
+            DateValue::Date(_) => rdate.local(),
+            rdate => start.local_of(&rdate.instant(&viewer), &viewer),
+        }));
+        let excluded: Vec<DateTime<Utc>> = self
+            .exdates
+            .iter()
+            .map(|exdate| exdate.instant(&viewer))
+            .collect();
+
+        locals.sort();
+        locals.dedup();
+        locals
+            .into_iter()
+            .map(|local| start.with_local(local))
+            .filter(|value| {
+                let instant = value.instant(&viewer);
+                instant >= from && instant < to && !excluded.contains(&instant)
+            })
+            .map(|value| Occurrence {
+                end: self.occurrence_end(start, &value),
+                start: value,
+            })
+            .collect()
+    }
+
+    fn occurrence_end(&self, start: &DateValue, occurrence: &DateValue) -> Option<DateValue> {
+        let length = match (&self.end, self.duration) {
+            (Some(end), _) => end.local() - start.local(),
+            (None, Some(duration)) => duration,
+            (None, None) => return None,
+        };
+        Some(occurrence.with_local(occurrence.local() + length))
+    }
+}
+
+/// 解析后的日历
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Calendar {
+    pub components: Vec<Component>,
+}
+
+fn unescape(value: &str) -> String {
+    let mut text = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (c, c == '\\') {
+            (_, true) => match chars.next() {
+                Some('n') | Some('N') => text.push('\n'),
+                Some(other) => text.push(other),
+                None => {}
+            },
+            (c, false) => text.push(c),
+        }
+    }
+    text
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+type Params = Vec<(String, String)>;
+
+/// 把内容行拆分为属性名、参数和值，参数值可以用双引号包含`:`和`;`
+fn split_content_line(line: &str) -> Option<(String, Params, &str)> {
+    let mut in_quotes = false;
+    let mut colon = None;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ':' if !in_quotes => {
+                colon = Some(i);
+                break;
+            }
+            _ => {}
+        }
+    }
+    let colon = colon?;
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+
+    let mut parts = vec![];
+    let mut current = String::new();
+    in_quotes = false;
+    for c in head.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ';' if !in_quotes => parts.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    parts.push(current);
+
+    let name = parts.remove(0).to_uppercase();
+    let params = parts
+        .into_iter()
+        .filter_map(|param| {
+            param
+                .split_once('=')
+                .map(|(key, value)| (key.to_uppercase(), value.to_string()))
+        })
+        .collect();
+    Some((name, params, value))
+}
+
+impl Calendar {
+    pub fn parse(text: &str) -> Result<Calendar, IcsError> {
+        // 以空格或制表符开头的行是上一行的延续
+        let mut lines: Vec<String> = vec![];
+        for raw in text.split('\n') {
+            let raw = raw.strip_suffix('\r').unwrap_or(raw);
+            match raw.strip_prefix(' ').or_else(|| raw.strip_prefix('\t')) {
+                Some(rest) if !lines.is_empty() => lines.last_mut().unwrap().push_str(rest),
+                _ if raw.is_empty() => {}
+                _ => lines.push(raw.to_string()),
+            }
+        }
+
+        let mut calendar = Calendar::default();
+        let mut current: Option<Component> = None;
+        let mut nested = 0;
+
+        for (index, line) in lines.iter().enumerate() {
+            let error = |message: String| IcsError::Parse {
+                line: index + 1,
+                message,
+            };
+            let (name, params, value) =
+                split_content_line(line).ok_or_else(|| error(format!("缺少冒号：{}", line)))?;
+
+            match (name.as_str(), value, current.as_mut()) {
+                ("BEGIN", "VEVENT", None) => current = Some(Component::new(ComponentKind::Event)),
+                ("BEGIN", "VTODO", None) => current = Some(Component::new(ComponentKind::Todo)),
+                ("BEGIN", _, Some(_)) => nested += 1,
+                ("END", "VEVENT" | "VTODO", Some(_)) if nested == 0 => {
+                    calendar.components.push(current.take().unwrap())
+                }
+                ("END", _, Some(_)) => nested -= 1,
+                // VALARM等嵌套组件的属性不属于事件本身
+                (_, _, Some(_)) if nested > 0 => {}
+                (_, _, None) => {}
+                ("UID", value, Some(component)) => component.uid = Some(unescape(value)),
+                ("SUMMARY", value, Some(component)) => component.summary = Some(unescape(value)),
+                ("DTSTART", value, Some(component)) => {
+                    component.start = Some(DateValue::parse(index + 1, &params, value)?)
+                }
+                ("DTEND" | "DUE", value, Some(component)) => {
+                    component.end = Some(DateValue::parse(index + 1, &params, value)?)
+                }
+                ("DURATION", value, Some(component)) => {
+                    component.duration =
+                        Some(parse_iso8601(value).map_err(|err| error(err.to_string()))?)
+                }
+                ("RRULE", value, Some(component)) => {
+                    component.rrule = Some(RRule::parse(index + 1, value)?)
+                }
+                ("EXDATE" | "RDATE", value, Some(component)) => {
+                    if params
+                        .iter()
+                        .any(|(key, value)| key == "VALUE" && value == "PERIOD")
+                    {
+                        return Err(IcsError::Unsupported("PERIOD类型的RDATE".to_string()));
+                    }
+                    let dates = value
+                        .split(',')
+                        .map(|date| DateValue::parse(index + 1, &params, date))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    if name == "EXDATE" {
+                        component.exdates.extend(dates);
+                    } else {
+                        component.rdates.extend(dates);
+                    }
+                }
+                (_, value, Some(component)) => {
+                    let head = line[..line.len() - value.len() - 1].to_string();
+                    component.extra.push((head, value.to_string()));
+                }
+            }
+        }
+
+        Ok(calendar)
+    }
+
+    /// 写出为`.ics`文本，行尾为CRLF，超过75字节的行按RFC 5545折行
+    pub fn to_ics(&self) -> String {
+        let mut lines = vec![
+            "BEGIN:VCALENDAR".to_string(),
+            "VERSION:2.0".to_string(),
+            "PRODID:-//rust-cookbook//dateandtime//EN".to_string(),
+        ];
+
+        for component in &self.components {
+            let (kind, end_name) = match component.kind {
+                ComponentKind::Event => ("VEVENT", "DTEND"),
+                ComponentKind::Todo => ("VTODO", "DUE"),
+            };
+            lines.push(format!("BEGIN:{}", kind));
+            if let Some(uid) = &component.uid {
+                lines.push(format!("UID:{}", escape(uid)));
+            }
+            if let Some(summary) = &component.summary {
+                lines.push(format!("SUMMARY:{}", escape(summary)));
+            }
+            if let Some(start) = &component.start {
+                lines.push(format!("DTSTART{}:{}", start.params(), start));
+            }
+            if let Some(end) = &component.end {
+                lines.push(format!("{}{}:{}", end_name, end.params(), end));
+            }
+            if let Some(duration) = component.duration {
+                lines.push(format!("DURATION:{}", to_iso8601(duration)));
+            }
+            if let Some(rule) = &component.rrule {
+                lines.push(format!("RRULE:{}", rule));
+            }
+            for (name, dates) in [("RDATE", &component.rdates), ("EXDATE", &component.exdates)] {
+                for date in dates {
+                    lines.push(format!("{}{}:{}", name, date.params(), date));
+                }
+            }
+            for (head, value) in &component.extra {
+                lines.push(format!("{}:{}", head, value));
+            }
+            lines.push(format!("END:{}", kind));
+        }
+        lines.push("END:VCALENDAR".to_string());
+
+        lines
+            .iter()
+            .map(|line| fold(line))
+            .collect::<Vec<_>>()
+            .join("")
+    }
+}
+
+/// 每行最多75字节，不在UTF-8字符中间断开
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 8);
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+fn starts(occurrences: &[Occurrence]) -> Vec<String> {
+    occurrences
+        .iter()
+        .map(|occurrence| occurrence.start.to_string())
+        .collect()
+}
+
+/// # 展开iCalendar中的重复事件
+/// 使用RFC 5545第3.8.5.3节中的例子，并验证写出后再读入得到相同的事件。
+pub fn expand_ics_recurrences() -> Result<(), IcsError> {
+    let ics = "BEGIN:VCALENDAR\r\n\
+VERSION:2.0\r\n\
+BEGIN:VEVENT\r\n\
+UID:daily-10@example.com\r\n\
+SUMMARY:Daily for 10 occurrences\r\n\
+DTSTART;TZID=America/New_York:19970902T090000\r\n\
+DTEND;TZID=America/New_York:19970902T100000\r\n\
+RRULE:FREQ=DAILY;COUNT=10\r\n\
+EXDATE;TZID=America/New_York:19970905T090000\r\n\
+END:VEVENT\r\n\
+BEGIN:VEVENT\r\n\
+UID:biweekly@example.com\r\n\
+SUMMARY:Every other week on Monday\\, Wednesday\\, and Friday\r\n\
+DTSTART;TZID=America/New_York:19970901T090000\r\n\
+RRULE:FREQ=WEEKLY;INTERVAL=2;UNTIL=19971224T000000Z;WKST=SU;BYDAY=MO,WE,FR\r\n\
+END:VEVENT\r\n\
+BEGIN:VEVENT\r\n\
+UID:first-friday@example.com\r\n\
+SUMMARY:Monthly on the first Friday for 10 occurrences\r\n\
+DTSTART;TZID=America/New_York:19970905T090000\r\n\
+RRULE:FREQ=MONTHLY;COUNT=10;BYDAY=1FR\r\n\
+END:VEVENT\r\n\
+BEGIN:VTODO\r\n\
+UID:last-workday@example.com\r\n\
+SUMMARY:The last work day of the month\r\n\
+DTSTART;TZID=America/New_York:19970929T090000\r\n\
+DUE;TZID=America/New_York:19970929T170000\r\n\
+RRULE:FREQ=MONTHLY;BYDAY=MO,TU,WE,TH,FR;BYSETPOS=-1\r\n\
+BEGIN:VALARM\r\n\
+ACTION:DISPLAY\r\n\
+TRIGGER:-PT15M\r\n\
+END:VALARM\r\n\
+END:VTODO\r\n\
+BEGIN:VEVENT\r\n\
+UID:last-sunday-october@example.com\r\n\
+SUMMARY:Every year on the last Sunday in October\r\n\
+DTSTART;TZID=America/New_York:19671029T020000\r\n\
+RRULE:FREQ=YEARLY;BYDAY=-1SU;BYMONTH=10\r\n\
+RDATE;VALUE=DATE:19711231\r\n\
+X-COMMENT;LANGUAGE=en:folded line that continues\r\n\
+\x20 on the next line\r\n\
+END:VEVENT\r\n\
+END:VCALENDAR\r\n";
+
+    let calendar = Calendar::parse(ics)?;
+    assert_eq!(calendar.components.len(), 5);
+    let new_york = chrono_tz::America::New_York;
+    let from = new_york.with_ymd_and_hms(1997, 1, 1, 0, 0, 0).unwrap();
+    let to = new_york.with_ymd_and_hms(1999, 1, 1, 0, 0, 0).unwrap();
+
+    // 每天一次共10次，去掉EXDATE指定的9月5日
+    let daily = calendar.components[0].occurrences(&from, &to);
+    println!(
+        "{}：{:?}",
+        calendar.components[0].summary.as_deref().unwrap_or(""),
+        starts(&daily)
+    );
+    assert_eq!(daily.len(), 9);
+    assert!(!starts(&daily).contains(&"19970905T090000".to_string()));
+    assert_eq!(
+        daily[0].end,
+        Some(DateValue::Zoned(
+            NaiveDate::from_ymd_opt(1997, 9, 2)
+                .unwrap()
+                .and_hms_opt(10, 0, 0)
+                .unwrap(),
+            new_york,
+        ))
+    );
+
+    // 隔周的周一、周三、周五，一直到12月24日，跨过夏令时结束后仍然是本地9点
+    let biweekly = calendar.components[1].occurrences(&from, &to);
+    assert_eq!(
+        calendar.components[1].summary.as_deref(),
+        Some("Every other week on Monday, Wednesday, and Friday")
+    );
+    assert_eq!(biweekly.len(), 25);
+    assert_eq!(
+        starts(&biweekly[..7]),
+        [
+            "19970901T090000",
+            "19970903T090000",
+            "19970905T090000",
+            "19970915T090000",
+            "19970917T090000",
+            "19970919T090000",
+            "19970929T090000"
+        ]
+    );
+    assert_eq!(biweekly[24].start.to_string(), "19971222T090000");
+    let november = biweekly[15].start.instant(&Utc);
+    assert_eq!(
+        november.with_timezone(&new_york).to_rfc3339(),
+        "1997-11-10T09:00:00-05:00"
+    );
+
+    let first_friday = calendar.components[2].occurrences(&from, &to);
+    println!("每月第一个周五：{:?}", starts(&first_friday));
+    assert_eq!(
+        starts(&first_friday),
+        [
+            "19970905T090000",
+            "19971003T090000",
+            "19971107T090000",
+            "19971205T090000",
+            "19980102T090000",
+            "19980206T090000",
+            "19980306T090000",
+            "19980403T090000",
+            "19980501T090000",
+            "19980605T090000"
+        ]
+    );
+
+    // 每月最后一个工作日，DTSTART本身（9月29日）不符合规则所以不算在内
+    let to_march = new_york.with_ymd_and_hms(1998, 4, 1, 0, 0, 0).unwrap();
+    let last_workday = calendar.components[3].occurrences(&from, &to_march);
+    assert_eq!(calendar.components[3].kind, ComponentKind::Todo);
+    assert_eq!(
+        starts(&last_workday),
+        [
+            "19970930T090000",
+            "19971031T090000",
+            "19971128T090000",
+            "19971231T090000",
+            "19980130T090000",
+            "19980227T090000",
+            "19980331T090000"
+        ]
+    );
+    assert_eq!(
+        last_workday[0].end.as_ref().map(|end| end.to_string()),
+        Some("19970930T170000".to_string())
+    );
+
+    // 年度规则加上一个全天的RDATE，RDATE在窗口时区的零点
+    let sixties = new_york.with_ymd_and_hms(1967, 1, 1, 0, 0, 0).unwrap();
+    let seventies = new_york.with_ymd_and_hms(1972, 1, 1, 0, 0, 0).unwrap();
+    let october = calendar.components[4].occurrences(&sixties, &seventies);
+    println!("十月最后一个周日：{:?}", starts(&october));
+    assert_eq!(
+        starts(&october),
+        [
+            "19671029T020000",
+            "19681027T020000",
+            "19691026T020000",
+            "19701025T020000",
+            "19711031T020000",
+            "19711231T000000"
+        ]
+    );
+    assert_eq!(
+        calendar.components[4].extra,
+        [(
+            "X-COMMENT;LANGUAGE=en".to_string(),
+            "folded line that continues on the next line".to_string()
+        )]
+    );
+
+    // 写出再读入应该得到同样的内容，长行被折行
+    let written = calendar.to_ics();
+    assert!(written
+        .lines()
+        .all(|line| line.trim_end_matches('\r').len() <= 75));
+    assert_eq!(Calendar::parse(&written)?, calendar);
+    println!(
+        "写出的第一个事件：\n{}",
+        written.split("END:VEVENT").next().unwrap_or("")
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 用RFC 5545第3.8.5.3节的例子：在纽约时区展开`[1996, until)`之间的发生时间
+    fn expand(dtstart: &str, rrule: &str, extra: &str, until: i32) -> Vec<String> {
+        let ics = format!(
+            "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\n\
+             DTSTART;TZID=America/New_York:{}\r\nRRULE:{}\r\n{}END:VEVENT\r\nEND:VCALENDAR\r\n",
+            dtstart, rrule, extra
+        );
+        let calendar = Calendar::parse(&ics).unwrap();
+        let new_york = chrono_tz::America::New_York;
+        let from = new_york.with_ymd_and_hms(1996, 1, 1, 0, 0, 0).unwrap();
+        let to = new_york.with_ymd_and_hms(until, 1, 1, 0, 0, 0).unwrap();
+        starts(&calendar.components[0].occurrences(&from, &to))
+    }
+
+    fn dates(list: &[&str]) -> Vec<String> {
+        list.iter().map(|date| format!("{}T090000", date)).collect()
+    }
+
+    #[test]
+    fn daily_rules() {
+        let until = expand(
+            "19970902T090000",
+            "FREQ=DAILY;UNTIL=19971224T000000Z",
+            "",
+            1999,
+        );
+        assert_eq!(until.len(), 113);
+        assert_eq!(until.last().unwrap(), "19971223T090000");
+
+        assert_eq!(
+            expand(
+                "19970902T090000",
+                "FREQ=DAILY;INTERVAL=10;COUNT=5",
+                "",
+                1999
+            ),
+            dates(&["19970902", "19970912", "19970922", "19971002", "19971012"])
+        );
+
+        // 没有结束条件的规则只展开到窗口末尾
+        let every_other_day = expand("19970902T090000", "FREQ=DAILY;INTERVAL=2", "", 1998);
+        assert_eq!(every_other_day.len(), 61);
+        assert_eq!(every_other_day[1], "19970904T090000");
+    }
+
+    #[test]
+    fn weekly_rules() {
+        assert_eq!(
+            expand("19970902T090000", "FREQ=WEEKLY;COUNT=10", "", 1999),
+            dates(&[
+                "19970902", "19970909", "19970916", "19970923", "19970930", "19971007", "19971014",
+                "19971021", "19971028", "19971104"
+            ])
+        );
+
+        // WKST改变隔周规则中每周的分组
+        assert_eq!(
+            expand(
+                "19970805T090000",
+                "FREQ=WEEKLY;INTERVAL=2;COUNT=4;BYDAY=TU,SU;WKST=MO",
+                "",
+                1999
+            ),
+            dates(&["19970805", "19970810", "19970819", "19970824"])
+        );
+        assert_eq!(
+            expand(
+                "19970805T090000",
+                "FREQ=WEEKLY;INTERVAL=2;COUNT=4;BYDAY=TU,SU;WKST=SU",
+                "",
+                1999
+            ),
+            dates(&["19970805", "19970817", "19970819", "19970831"])
+        );
+    }
+
+    #[test]
+    fn monthly_rules() {
+        assert_eq!(
+            expand(
+                "19970907T090000",
+                "FREQ=MONTHLY;INTERVAL=2;COUNT=10;BYDAY=1SU,-1SU",
+                "",
+                1999
+            ),
+            dates(&[
+                "19970907", "19970928", "19971102", "19971130", "19980104", "19980125", "19980301",
+                "19980329", "19980503", "19980531"
+            ])
+        );
+
+        assert_eq!(
+            expand("19970928T090000", "FREQ=MONTHLY;BYMONTHDAY=-3", "", 1998)
+                .into_iter()
+                .take(4)
+                .collect::<Vec<_>>(),
+            dates(&["19970928", "19971029", "19971128", "19971229"])
+        );
+
+        assert_eq!(
+            expand(
+                "19970902T090000",
+                "FREQ=MONTHLY;COUNT=10;BYMONTHDAY=2,15",
+                "",
+                1999
+            ),
+            dates(&[
+                "19970902", "19970915", "19971002", "19971015", "19971102", "19971115", "19971202",
+                "19971215", "19980102", "19980115"
+            ])
+        );
+
+        // 每个月第3个周二、周三或周四
+        assert_eq!(
+            expand(
+                "19970904T090000",
+                "FREQ=MONTHLY;COUNT=3;BYDAY=TU,WE,TH;BYSETPOS=3",
+                "",
+                1999
+            ),
+            dates(&["19970904", "19971007", "19971106"])
+        );
+    }
+
+    #[test]
+    fn friday_the_thirteenth_with_exdate() {
+        assert_eq!(
+            expand(
+                "19970902T090000",
+                "FREQ=MONTHLY;BYDAY=FR;BYMONTHDAY=13",
+                "EXDATE;TZID=America/New_York:19970902T090000\r\n",
+                2001
+            ),
+            dates(&["19980213", "19980313", "19981113", "19990813", "20001013"])
+        );
+    }
+
+    #[test]
+    fn yearly_rules() {
+        assert_eq!(
+            expand(
+                "19970610T090000",
+                "FREQ=YEARLY;COUNT=10;BYMONTH=6,7",
+                "",
+                2003
+            ),
+            dates(&[
+                "19970610", "19970710", "19980610", "19980710", "19990610", "19990710", "20000610",
+                "20000710", "20010610", "20010710"
+            ])
+        );
+
+        // 美国总统选举日：11月2日到8日之间的周二，每4年一次
+        assert_eq!(
+            expand(
+                "19961105T090000",
+                "FREQ=YEARLY;INTERVAL=4;BYMONTH=11;BYDAY=TU;BYMONTHDAY=2,3,4,5,6,7,8",
+                "",
+                2005
+            ),
+            dates(&["19961105", "20001107", "20041102"])
+        );
+    }
+
+    #[test]
+    fn rdate_and_window_in_other_timezone() {
+        let ics = "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\n\
+                   DTSTART;TZID=America/New_York:19971025T090000\r\n\
+                   DURATION:PT1H\r\n\
+                   RRULE:FREQ=DAILY;COUNT=3\r\n\
+                   RDATE;TZID=Europe/London:19971101T140000\r\n\
+                   END:VEVENT\r\nEND:VCALENDAR\r\n";
+        let event = &Calendar::parse(ics).unwrap().components[0];
+        let from = Utc.with_ymd_and_hms(1997, 10, 1, 0, 0, 0).unwrap();
+        let to = Utc.with_ymd_and_hms(1997, 12, 1, 0, 0, 0).unwrap();
+        let occurrences = event.occurrences(&from, &to);
+
+        // 10月26日纽约夏令时结束，本地时间仍然是9点，UTC时间晚一个小时
+        let utc: Vec<String> = occurrences
+            .iter()
+            .map(|occurrence| occurrence.start.instant(&Utc).to_rfc3339())
+            .collect();
+        assert_eq!(
+            utc,
+            [
+                "1997-10-25T13:00:00+00:00",
+                "1997-10-26T14:00:00+00:00",
+                "1997-10-27T14:00:00+00:00",
+                "1997-11-01T14:00:00+00:00"
+            ]
+        );
+        assert_eq!(
+            occurrences[3].end.as_ref().map(|end| end.to_string()),
+            Some("19971101T100000".to_string())
+        );
+
+        // 窗口是半开区间
+        let first = Utc.with_ymd_and_hms(1997, 10, 25, 13, 0, 0).unwrap();
+        assert_eq!(event.occurrences(&from, &first).len(), 0);
+        assert_eq!(event.occurrences(&first, &to).len(), 4);
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let ics = "BEGIN:VCALENDAR\r\nBEGIN:VTODO\r\n\
+                   UID:todo@example.com\r\n\
+                   SUMMARY:Semi\\, colon\\; and\\nnewline\r\n\
+                   DTSTART;VALUE=DATE:19970101\r\n\
+                   RRULE:FREQ=YEARLY;BYMONTH=1;BYDAY=-1FR\r\n\
+                   END:VTODO\r\nEND:VCALENDAR\r\n";
+        let calendar = Calendar::parse(ics).unwrap();
+        let todo = &calendar.components[0];
+        assert_eq!(todo.kind, ComponentKind::Todo);
+        assert_eq!(todo.summary.as_deref(), Some("Semi, colon; and\nnewline"));
+        assert_eq!(Calendar::parse(&calendar.to_ics()).unwrap(), calendar);
+    }
+
+    #[test]
+    fn invalid_input() {
+        assert!(Calendar::parse("BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nRRULE:FREQ=HOURLY\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n").is_err());
+        assert!(Calendar::parse("BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nDTSTART;TZID=Mars/Base:19970101T000000\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n").is_err());
+        for byday in ["中", "1É", "MO,-1SÜ", "+", ""] {
+            let ics = format!("BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nDTSTART:19970101T000000\r\nRRULE:FREQ=MONTHLY;BYDAY={}\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n", byday);
+            assert!(Calendar::parse(&ics).is_err(), "BYDAY={}", byday);
+        }
+    }
+}
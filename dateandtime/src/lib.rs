@@ -0,0 +1,11 @@
+//! # 日期和时间
+//! `src/main.rs`中的例子以及`src/bin`下的命令行工具共享的模块。
+
+pub mod benchmark;
+pub mod business_days;
+pub mod calendar;
+pub mod durations;
+pub mod fuzzy;
+pub mod icalendar;
+pub mod scheduler;
+pub mod timezone;
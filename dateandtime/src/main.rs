@@ -1,8 +1,10 @@
 use ansi_term::Colour::Red;
 use chrono::format::ParseError;
-use chrono::{DateTime, Duration, FixedOffset, Local, Utc};
+use chrono::{DateTime, Duration, Local, Utc};
 use chrono::{Datelike, Timelike};
 use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use dateandtime::durations::{self, DurationFormat};
+use dateandtime::{benchmark, business_days, calendar, fuzzy, icalendar, scheduler, timezone};
 use std::thread;
 use std::time::Instant;
 
@@ -13,15 +15,31 @@ fn main() {
 
     println!("{}", breakline);
     elapsed_time();
+    if let Err(err) = benchmark::time_code_sections() {
+        println!("输出基准测试结果发生错误：{}", err);
+    }
 
     println!("{}", breakline);
     perform_checked_date_and_time_calculations();
+    if let Err(err) = durations::parse_and_format_durations() {
+        println!("解析和格式化时间间隔发生错误：{}", err);
+    }
+    if let Err(err) = business_days::calculate_business_days() {
+        println!("计算工作日发生错误：{}", err);
+    }
 
     println!("{}", breakline);
     convert_local_time_to_another_timezone();
 
+    if let Err(err) = timezone::convert_with_iana_names() {
+        println!("按时区名字转换时间发生错误：{}", err);
+    }
+
     println!("{}", breakline);
     examine_date_and_time();
+    if let Err(err) = calendar::render_calendars() {
+        println!("显示日历发生错误：{}", err);
+    }
 
     println!("{}", breakline);
     convert_date_to_unix_timestamp();
@@ -33,6 +51,20 @@ fn main() {
     if let Err(err) = parse_string_into_datetime_struct() {
         println!("解析时间字符串发生错误：{}", err);
     }
+    if let Err(err) = fuzzy::parse_mixed_formats() {
+        println!("解析格式不确定的时间字符串发生错误：{}", err);
+    }
+
+    println!("{}", breakline);
+    if let Err(err) = scheduler::compute_cron_fire_times() {
+        println!("计算cron执行时间发生错误：{}", err);
+    }
+    if let Err(err) = scheduler::run_scheduled_job() {
+        println!("运行定时任务发生错误：{}", err);
+    }
+    if let Err(err) = icalendar::expand_ics_recurrences() {
+        println!("展开iCalendar重复事件发生错误：{}", err);
+    }
 
     println!("{}", breakline);
 }
@@ -48,7 +80,13 @@ fn elapsed_time() {
     expensive_function();
     let duration = start.elapsed();
 
-    println!("调用耗时方法使用的时间：{:?}", duration);
+    match durations::from_std(duration) {
+        Ok(duration) => println!(
+            "调用耗时方法使用的时间：{}",
+            DurationFormat::long().precision(2).format(duration)
+        ),
+        Err(err) => println!("无法转换耗时：{}", err),
+    }
 }
 
 fn day_earlier(date_time: DateTime<Utc>) -> Option<DateTime<Utc>> {
@@ -78,8 +116,8 @@ fn perform_checked_date_and_time_calculations() {
 fn convert_local_time_to_another_timezone() {
     let local_time = Local::now();
     let utc_time = DateTime::<Utc>::from_utc(local_time.naive_utc(), Utc);
-    let china_timezone = FixedOffset::east(8 * 3600);
-    let rio_timezone = FixedOffset::west(2 * 3600);
+    let china_timezone = chrono_tz::Asia::Hong_Kong;
+    let rio_timezone = chrono_tz::America::Sao_Paulo;
     println!("Local time now is {}", local_time.naive_utc());
     println!("UTC time now is {}", utc_time);
     println!(
@@ -0,0 +1,649 @@
+//! # 定时任务和cron表达式
+//! 解析5个字段（分 时 日 月 星期）或6个字段（秒 分 时 日 月 星期）的cron表达式，
+//! 支持`*`、列表`1,15`、范围`1-5`、步长`*/10`和`8-18/2`，以及`JAN`、`MON`这样的名称。
+//!
+//! 下一次执行时间在时区的本地时间上计算：
+//! 夏令时开始时被跳过的本地时间会在跳变后的第一个有效时间执行；
+//! 夏令时结束时重复出现的本地时间只执行一次（第一次出现时）。
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+
+const MONTH_NAMES: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+const WEEKDAY_NAMES: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+/// 最多向后搜索的年数，足够覆盖只在闰年2月29日执行的表达式
+const SEARCH_YEARS: i32 = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CronError {
+    /// 字段数量既不是5也不是6
+    FieldCount(usize),
+    /// 字段无法解析
+    Invalid { field: &'static str, spec: String },
+    /// 字段的值超出范围
+    OutOfRange {
+        field: &'static str,
+        value: u32,
+        min: u32,
+        max: u32,
+    },
+}
+
+impl fmt::Display for CronError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CronError::FieldCount(count) => {
+                write!(f, "cron表达式需要5个或6个字段，实际有{}个", count)
+            }
+            CronError::Invalid { field, spec } => write!(f, "无法解析{}字段：{}", field, spec),
+            CronError::OutOfRange {
+                field,
+                value,
+                min,
+                max,
+            } => write!(f, "{}字段的值{}超出范围{}-{}", field, value, min, max),
+        }
+    }
+}
+
+impl Error for CronError {}
+
+/// 解析后的cron表达式，每个字段用位集合表示允许的值
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    seconds: u64,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    /// 日和星期字段是否被限制。两个都被限制时满足其中一个即可，和传统cron一致
+    dom_restricted: bool,
+    dow_restricted: bool,
+}
+
+struct FieldSpec {
+    name: &'static str,
+    min: u32,
+    max: u32,
+    names: &'static [&'static str],
+    name_offset: u32,
+}
+
+const SECOND: FieldSpec = FieldSpec {
+    name: "秒",
+    min: 0,
+    max: 59,
+    names: &[],
+    name_offset: 0,
+};
+const MINUTE: FieldSpec = FieldSpec {
+    name: "分",
+    min: 0,
+    max: 59,
+    names: &[],
+    name_offset: 0,
+};
+const HOUR: FieldSpec = FieldSpec {
+    name: "时",
+    min: 0,
+    max: 23,
+    names: &[],
+    name_offset: 0,
+};
+const DAY_OF_MONTH: FieldSpec = FieldSpec {
+    name: "日",
+    min: 1,
+    max: 31,
+    names: &[],
+    name_offset: 0,
+};
+const MONTH: FieldSpec = FieldSpec {
+    name: "月",
+    min: 1,
+    max: 12,
+    names: &MONTH_NAMES,
+    name_offset: 1,
+};
+// 星期允许0-7，0和7都表示星期日
+const DAY_OF_WEEK: FieldSpec = FieldSpec {
+    name: "星期",
+    min: 0,
+    max: 7,
+    names: &WEEKDAY_NAMES,
+    name_offset: 0,
+};
+
+impl FieldSpec {
+    fn value(&self, text: &str, spec: &str) -> Result<u32, CronError> {
+        let upper = text.to_ascii_uppercase();
+        let value = match self.names.iter().position(|name| *name == upper) {
+            Some(index) => index as u32 + self.name_offset,
+            None => text.parse().map_err(|_| self.invalid(spec))?,
+        };
+
+        if value < self.min || value > self.max {
+            return Err(CronError::OutOfRange {
+                field: self.name,
+                value,
+                min: self.min,
+                max: self.max,
+            });
+        }
+        Ok(value)
+    }
+
+    fn invalid(&self, spec: &str) -> CronError {
+        CronError::Invalid {
+            field: self.name,
+            spec: spec.to_string(),
+        }
+    }
+
+    fn parse(&self, spec: &str) -> Result<u64, CronError> {
+        let mut bits = 0u64;
+
+        for part in spec.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => match step.parse::<u32>() {
+                    Ok(step) if step > 0 => (range, Some(step)),
+                    _ => return Err(self.invalid(spec)),
+                },
+                None => (part, None),
+            };
+
+            let (low, high) = if range == "*" {
+                (self.min, self.max)
+            } else if let Some((low, high)) = range.split_once('-') {
+                (self.value(low, spec)?, self.value(high, spec)?)
+            } else {
+                let value = self.value(range, spec)?;
+                // `5/15`表示从5开始每15个单位
+                (value, if step.is_some() { self.max } else { value })
+            };
+            if low > high {
+                return Err(self.invalid(spec));
+            }
+
+            for value in (low..=high).step_by(step.unwrap_or(1) as usize) {
+                bits |= 1 << value;
+            }
+        }
+
+        Ok(bits)
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = CronError;
+
+    fn from_str(expr: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let (second, rest) = match fields.len() {
+            5 => ("0", &fields[..]),
+            6 => (fields[0], &fields[1..]),
+            count => return Err(CronError::FieldCount(count)),
+        };
+
+        let mut days_of_week = DAY_OF_WEEK.parse(rest[4])?;
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week = (days_of_week & !(1 << 7)) | 1;
+        }
+
+        Ok(CronSchedule {
+            seconds: SECOND.parse(second)?,
+            minutes: MINUTE.parse(rest[0])?,
+            hours: HOUR.parse(rest[1])?,
+            days_of_month: DAY_OF_MONTH.parse(rest[2])?,
+            months: MONTH.parse(rest[3])?,
+            days_of_week,
+            dom_restricted: !rest[2].starts_with('*'),
+            dow_restricted: !rest[4].starts_with('*'),
+        })
+    }
+}
+
+fn contains(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+impl CronSchedule {
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let dom = contains(self.days_of_month, date.day());
+        let dow = contains(self.days_of_week, date.weekday().num_days_from_sunday());
+
+        match (self.dom_restricted, self.dow_restricted) {
+            (true, true) => dom || dow,
+            (true, false) => dom,
+            (false, true) => dow,
+            (false, false) => true,
+        }
+    }
+
+    /// 在本地时间上找到严格晚于`after`的第一个匹配时间
+    fn next_local(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let limit_year = after.year() + SEARCH_YEARS;
+        let mut t = after.with_nanosecond(0)? + Duration::seconds(1);
+
+        while t.year() <= limit_year {
+            let date = t.date();
+            if !contains(self.months, t.month()) {
+                let (year, month) = if t.month() == 12 {
+                    (t.year() + 1, 1)
+                } else {
+                    (t.year(), t.month() + 1)
+                };
+                t = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.matches_day(date) {
+                t = date.succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if !contains(self.hours, t.hour()) {
+                t = date.and_hms_opt(t.hour(), 0, 0)? + Duration::hours(1);
+            } else if !contains(self.minutes, t.minute()) {
+                t = date.and_hms_opt(t.hour(), t.minute(), 0)? + Duration::minutes(1);
+            } else if !contains(self.seconds, t.second()) {
+                t += Duration::seconds(1);
+            } else {
+                return Some(t);
+            }
+        }
+
+        None
+    }
+
+    /// 严格晚于`after`的下一次执行时间，表达式在搜索范围内不会匹配时返回`None`（例如`0 0 30 2 *`）
+    pub fn next_after<Tz: TimeZone>(&self, after: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let tz = after.timezone();
+        let mut local = after.naive_local();
+
+        loop {
+            local = self.next_local(local)?;
+            let fire = resolve_local(&tz, local)?;
+            // 重复的本地时间第一次出现时已经执行过，或者跳过的时间被映射到了已执行的时间
+            if fire > *after {
+                return Some(fire);
+            }
+        }
+    }
+
+    /// 从`after`开始的后续`n`次执行时间
+    pub fn upcoming<Tz: TimeZone>(&self, after: &DateTime<Tz>, n: usize) -> Vec<DateTime<Tz>> {
+        let mut times: Vec<DateTime<Tz>> = Vec::with_capacity(n);
+        let mut last = after.clone();
+
+        while times.len() < n {
+            match self.next_after(&last) {
+                Some(next) => {
+                    last = next.clone();
+                    times.push(next);
+                }
+                None => break,
+            }
+        }
+
+        times
+    }
+}
+
+/// 把本地时间转换为时区中的时间：重复的时间取较早的一个，
+/// 被夏令时跳过的时间顺延到跳变后的第一个有效时间
+fn resolve_local<Tz: TimeZone>(tz: &Tz, local: NaiveDateTime) -> Option<DateTime<Tz>> {
+    let mut candidate = local;
+    for _ in 0..=24 * 60 {
+        if let Some(time) = tz.from_local_datetime(&candidate).earliest() {
+            return Some(time);
+        }
+        candidate = candidate.with_second(0)? + Duration::minutes(1);
+    }
+    None
+}
+
+/// 错过执行时间（例如任务运行过久或者系统休眠）时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissedRunPolicy {
+    /// 只在准时的情况下执行，错过的执行全部丢弃
+    Skip,
+    /// 不管错过多少次，只补执行一次
+    RunOnce,
+    /// 补执行每一次错过的执行
+    RunAll,
+}
+
+impl MissedRunPolicy {
+    /// 从按时间排序的到期执行时间中选出实际需要执行的时间
+    pub fn select<T>(self, mut due: Vec<T>) -> Vec<T> {
+        match self {
+            MissedRunPolicy::Skip if due.len() > 1 => vec![],
+            MissedRunPolicy::Skip | MissedRunPolicy::RunOnce => due.pop().into_iter().collect(),
+            MissedRunPolicy::RunAll => due,
+        }
+    }
+}
+
+/// 后台定时任务的句柄，调用`stop`停止任务并等待线程退出
+pub struct JobHandle {
+    stop: Sender<()>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl JobHandle {
+    pub fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.thread.join();
+    }
+}
+
+/// 在后台线程中按`schedule`执行`job`，`job`的参数是计划的执行时间
+pub fn spawn_job<Tz, F>(
+    schedule: CronSchedule,
+    tz: Tz,
+    policy: MissedRunPolicy,
+    mut job: F,
+) -> JobHandle
+where
+    Tz: TimeZone + Send + 'static,
+    F: FnMut(DateTime<Tz>) + Send + 'static,
+{
+    let (stop, stopped) = mpsc::channel();
+
+    let thread = thread::spawn(move || {
+        let mut last = Utc::now().with_timezone(&tz);
+
+        while let Some(next) = schedule.next_after(&last) {
+            let wait = (next.clone().with_timezone(&Utc) - Utc::now())
+                .to_std()
+                .unwrap_or_default();
+            match stopped.recv_timeout(wait) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => return,
+            }
+
+            let now = Utc::now().with_timezone(&tz);
+            let mut due = vec![next];
+            while let Some(missed) = schedule.next_after(due.last().unwrap()) {
+                if missed > now {
+                    break;
+                }
+                due.push(missed);
+            }
+            last = due.last().unwrap().clone();
+
+            policy.select(due).into_iter().for_each(&mut job);
+        }
+    });
+
+    JobHandle { stop, thread }
+}
+
+fn rfc3339<Tz: TimeZone>(times: &[DateTime<Tz>]) -> Vec<String>
+where
+    Tz::Offset: fmt::Display,
+{
+    times.iter().map(|t| t.to_rfc3339()).collect()
+}
+
+/// # 计算cron表达式的执行时间
+/// 包括工作日、闰年2月29日以及纽约夏令时开始（跳过02:00-03:00）和结束（重复01:00-02:00）时的情况。
+pub fn compute_cron_fire_times() -> Result<(), CronError> {
+    let new_york = chrono_tz::America::New_York;
+
+    let workdays: CronSchedule = "0 9 * * MON-FRI".parse()?;
+    let start = new_york.with_ymd_and_hms(2022, 3, 10, 12, 0, 0).unwrap();
+    let times = workdays.upcoming(&start, 3);
+    println!("工作日9点：{:?}", rfc3339(&times));
+    assert_eq!(
+        rfc3339(&times),
+        [
+            "2022-03-11T09:00:00-05:00",
+            "2022-03-14T09:00:00-04:00",
+            "2022-03-15T09:00:00-04:00"
+        ]
+    );
+
+    let leap_day: CronSchedule = "0 0 29 FEB *".parse()?;
+    let start = Utc.with_ymd_and_hms(2022, 3, 1, 0, 0, 0).unwrap();
+    let times = leap_day.upcoming(&start, 3);
+    println!("闰年2月29日：{:?}", rfc3339(&times));
+    assert_eq!(
+        rfc3339(&times),
+        [
+            "2024-02-29T00:00:00+00:00",
+            "2028-02-29T00:00:00+00:00",
+            "2032-02-29T00:00:00+00:00"
+        ]
+    );
+
+    // 2022-03-13 02:30在纽约不存在，顺延到03:00
+    let daily: CronSchedule = "30 2 * * *".parse()?;
+    let start = new_york.with_ymd_and_hms(2022, 3, 12, 12, 0, 0).unwrap();
+    let times = daily.upcoming(&start, 2);
+    println!("夏令时开始：{:?}", rfc3339(&times));
+    assert_eq!(
+        rfc3339(&times),
+        ["2022-03-13T03:00:00-04:00", "2022-03-14T02:30:00-04:00"]
+    );
+
+    // 2022-11-06 01:30在纽约出现两次，只执行第一次
+    let daily: CronSchedule = "30 1 * * *".parse()?;
+    let start = new_york.with_ymd_and_hms(2022, 11, 5, 12, 0, 0).unwrap();
+    let times = daily.upcoming(&start, 2);
+    println!("夏令时结束：{:?}", rfc3339(&times));
+    assert_eq!(
+        rfc3339(&times),
+        ["2022-11-06T01:30:00-04:00", "2022-11-07T01:30:00-05:00"]
+    );
+
+    let every_20s: CronSchedule = "*/20 0 12 1 1 *".parse()?;
+    let start = Utc.with_ymd_and_hms(2022, 6, 1, 0, 0, 0).unwrap();
+    let times = every_20s.upcoming(&start, 4);
+    println!("6个字段：{:?}", rfc3339(&times));
+    assert_eq!(
+        rfc3339(&times),
+        [
+            "2023-01-01T12:00:00+00:00",
+            "2023-01-01T12:00:20+00:00",
+            "2023-01-01T12:00:40+00:00",
+            "2024-01-01T12:00:00+00:00"
+        ]
+    );
+
+    let never: CronSchedule = "0 0 30 2 *".parse()?;
+    assert_eq!(never.next_after(&start), None);
+
+    if let Err(err) = "61 * * * *".parse::<CronSchedule>() {
+        println!("错误的表达式：{}", err);
+    }
+
+    Ok(())
+}
+
+/// # 在后台线程中运行定时任务
+/// 每秒执行一次，运行约2.5秒后停止。
+/// 任务错过了3次执行时，不同策略分别执行其中的0次、1次和3次。
+pub fn run_scheduled_job() -> Result<(), CronError> {
+    let missed = vec![1, 2, 3];
+    assert_eq!(
+        MissedRunPolicy::Skip.select(missed.clone()),
+        Vec::<i32>::new()
+    );
+    assert_eq!(MissedRunPolicy::RunOnce.select(missed.clone()), [3]);
+    assert_eq!(MissedRunPolicy::RunAll.select(missed), [1, 2, 3]);
+    assert_eq!(MissedRunPolicy::Skip.select(vec![1]), [1]);
+
+    let (fired, runs) = mpsc::channel();
+    let handle = spawn_job(
+        "* * * * * *".parse()?,
+        chrono::Local,
+        MissedRunPolicy::RunAll,
+        move |time| {
+            println!("定时任务执行：{}", time);
+            let _ = fired.send(time);
+        },
+    );
+
+    thread::sleep(std::time::Duration::from_millis(2500));
+    handle.stop();
+    println!("定时任务共执行{}次", runs.try_iter().count());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fire_times(expr: &str, after: DateTime<Utc>, n: usize) -> Vec<String> {
+        let schedule: CronSchedule = expr.parse().unwrap();
+        rfc3339(&schedule.upcoming(&after, n))
+    }
+
+    #[test]
+    fn next_fire_is_strictly_after_start() {
+        let start = Utc.with_ymd_and_hms(2022, 5, 1, 10, 15, 0).unwrap();
+        assert_eq!(
+            fire_times("15 10 * * *", start, 2),
+            ["2022-05-02T10:15:00+00:00", "2022-05-03T10:15:00+00:00"]
+        );
+        // 秒和纳秒在匹配前被截掉
+        let start =
+            Utc.with_ymd_and_hms(2022, 5, 1, 10, 14, 59).unwrap() + Duration::milliseconds(500);
+        assert_eq!(
+            fire_times("15 10 * * *", start, 1),
+            ["2022-05-01T10:15:00+00:00"]
+        );
+    }
+
+    #[test]
+    fn lists_ranges_and_steps() {
+        let start = Utc.with_ymd_and_hms(2022, 1, 3, 7, 0, 0).unwrap();
+        assert_eq!(
+            fire_times("0 8-12/2 * * MON", start, 4),
+            [
+                "2022-01-03T08:00:00+00:00",
+                "2022-01-03T10:00:00+00:00",
+                "2022-01-03T12:00:00+00:00",
+                "2022-01-10T08:00:00+00:00"
+            ]
+        );
+        assert_eq!(
+            fire_times("5/20 0 1,15 * *", start, 4),
+            [
+                "2022-01-15T00:05:00+00:00",
+                "2022-01-15T00:25:00+00:00",
+                "2022-01-15T00:45:00+00:00",
+                "2022-02-01T00:05:00+00:00"
+            ]
+        );
+    }
+
+    #[test]
+    fn day_of_month_or_day_of_week() {
+        // 日和星期都被限制时满足任意一个即可：2022-01-13是星期四
+        let start = Utc.with_ymd_and_hms(2022, 1, 10, 0, 0, 0).unwrap();
+        assert_eq!(
+            fire_times("0 0 13 * FRI", start, 3),
+            [
+                "2022-01-13T00:00:00+00:00",
+                "2022-01-14T00:00:00+00:00",
+                "2022-01-21T00:00:00+00:00"
+            ]
+        );
+        // 星期7和0一样表示星期日
+        assert_eq!(
+            fire_times("0 0 * * 7", start, 1),
+            ["2022-01-16T00:00:00+00:00"]
+        );
+    }
+
+    #[test]
+    fn leap_day_and_impossible_dates() {
+        let start = Utc.with_ymd_and_hms(2022, 3, 1, 0, 0, 0).unwrap();
+        assert_eq!(
+            fire_times("0 0 29 FEB *", start, 2),
+            ["2024-02-29T00:00:00+00:00", "2028-02-29T00:00:00+00:00"]
+        );
+        let never: CronSchedule = "0 0 30 2 *".parse().unwrap();
+        assert_eq!(never.next_after(&start), None);
+        assert!(never.upcoming(&start, 3).is_empty());
+    }
+
+    #[test]
+    fn daylight_saving_transitions() {
+        let new_york = chrono_tz::America::New_York;
+
+        // 02:30在2022-03-13不存在，顺延到03:00
+        let schedule: CronSchedule = "30 2 * * *".parse().unwrap();
+        let start = new_york.with_ymd_and_hms(2022, 3, 12, 12, 0, 0).unwrap();
+        assert_eq!(
+            rfc3339(&schedule.upcoming(&start, 2)),
+            ["2022-03-13T03:00:00-04:00", "2022-03-14T02:30:00-04:00"]
+        );
+
+        // 01:30在2022-11-06出现两次，只执行第一次
+        let schedule: CronSchedule = "30 1 * * *".parse().unwrap();
+        let start = new_york.with_ymd_and_hms(2022, 11, 5, 12, 0, 0).unwrap();
+        assert_eq!(
+            rfc3339(&schedule.upcoming(&start, 2)),
+            ["2022-11-06T01:30:00-04:00", "2022-11-07T01:30:00-05:00"]
+        );
+
+        // 重复的一小时只执行一次，第一次01:59之后直接到02:00
+        let schedule: CronSchedule = "* * * * *".parse().unwrap();
+        let start = new_york
+            .with_ymd_and_hms(2022, 11, 6, 1, 59, 0)
+            .earliest()
+            .unwrap();
+        assert_eq!(
+            rfc3339(&schedule.upcoming(&start, 2)),
+            ["2022-11-06T02:00:00-05:00", "2022-11-06T02:01:00-05:00"]
+        );
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        assert_eq!(
+            "* * * *".parse::<CronSchedule>(),
+            Err(CronError::FieldCount(4))
+        );
+        assert_eq!(
+            "61 * * * *".parse::<CronSchedule>(),
+            Err(CronError::OutOfRange {
+                field: "分",
+                value: 61,
+                min: 0,
+                max: 59
+            })
+        );
+        assert!(matches!(
+            "* * * * MON-XYZ".parse::<CronSchedule>(),
+            Err(CronError::Invalid {
+                field: "星期", ..
+            })
+        ));
+        assert!(matches!(
+            "*/0 * * * *".parse::<CronSchedule>(),
+            Err(CronError::Invalid { field: "分", .. })
+        ));
+        assert!(matches!(
+            "0 18-8 * * *".parse::<CronSchedule>(),
+            Err(CronError::Invalid { field: "时", .. })
+        ));
+    }
+
+    #[test]
+    fn missed_run_policies() {
+        let missed = vec![1, 2, 3];
+        assert!(MissedRunPolicy::Skip.select(missed.clone()).is_empty());
+        assert_eq!(MissedRunPolicy::RunOnce.select(missed.clone()), [3]);
+        assert_eq!(MissedRunPolicy::RunAll.select(missed), [1, 2, 3]);
+        assert_eq!(MissedRunPolicy::Skip.select(vec![1]), [1]);
+        assert!(MissedRunPolicy::RunOnce
+            .select(Vec::<i32>::new())
+            .is_empty());
+    }
+}
@@ -0,0 +1,147 @@
+//! # IANA时区
+//! `FixedOffset`只是一个固定的偏移量，对有夏令时的时区来说一年中有一半时间是错的。
+//! 这里使用`chrono-tz`内嵌的IANA时区数据库，按名字（如`America/Sao_Paulo`）转换时间。
+//!
+//! 本地时间在时区中可能有歧义（夏令时结束时重复的一小时）或者不存在（夏令时开始时跳过的一小时），
+//! 转换时需要通过`Resolve`明确指定处理方式。
+use chrono::{DateTime, Duration, LocalResult, NaiveDateTime, Offset, TimeZone};
+use chrono_tz::{Tz, TZ_VARIANTS};
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TzError {
+    /// 时区数据库中没有这个名字
+    UnknownZone(String),
+    /// 本地时间在时区中出现两次
+    Ambiguous {
+        local: NaiveDateTime,
+        earlier: DateTime<Tz>,
+        later: DateTime<Tz>,
+    },
+    /// 本地时间在时区中不存在
+    Nonexistent { local: NaiveDateTime, zone: Tz },
+}
+
+impl fmt::Display for TzError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TzError::UnknownZone(name) => write!(f, "未知的时区：{}", name),
+            TzError::Ambiguous {
+                local,
+                earlier,
+                later,
+            } => write!(
+                f,
+                "本地时间{}在{}有歧义：可能是{}或者{}",
+                local,
+                earlier.timezone().name(),
+                earlier.offset(),
+                later.offset()
+            ),
+            TzError::Nonexistent { local, zone } => {
+                write!(f, "本地时间{}在{}不存在", local, zone.name())
+            }
+        }
+    }
+}
+
+impl Error for TzError {}
+
+/// 本地时间有歧义或者不存在时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolve {
+    /// 返回错误
+    Reject,
+    /// 歧义时取较早的时间；不存在时按跳变前的偏移量计算，相当于向后顺延跳过的时长
+    Earlier,
+    /// 歧义时取较晚的时间；不存在时和`Earlier`相同
+    Later,
+}
+
+/// 按IANA名字查找时区，例如`Asia/Shanghai`
+pub fn parse_zone(name: &str) -> Result<Tz, TzError> {
+    name.parse()
+        .map_err(|_| TzError::UnknownZone(name.to_string()))
+}
+
+/// 列出名字中包含`filter`（不区分大小写）的时区
+pub fn list_zones(filter: &str) -> Vec<&'static str> {
+    let filter = filter.to_lowercase();
+    TZ_VARIANTS
+        .iter()
+        .map(|tz| tz.name())
+        .filter(|name| name.to_lowercase().contains(&filter))
+        .collect()
+}
+
+/// 把时区`tz`中的本地时间转换为带时区的时间
+pub fn resolve_local(
+    tz: Tz,
+    local: NaiveDateTime,
+    resolve: Resolve,
+) -> Result<DateTime<Tz>, TzError> {
+    match (tz.from_local_datetime(&local), resolve) {
+        (LocalResult::Single(time), _) => Ok(time),
+        (LocalResult::Ambiguous(earlier, _), Resolve::Earlier) => Ok(earlier),
+        (LocalResult::Ambiguous(_, later), Resolve::Later) => Ok(later),
+        (LocalResult::Ambiguous(earlier, later), Resolve::Reject) => Err(TzError::Ambiguous {
+            local,
+            earlier,
+            later,
+        }),
+        (LocalResult::None, Resolve::Reject) => Err(TzError::Nonexistent { local, zone: tz }),
+        (LocalResult::None, _) => {
+            // 跳变发生在一天之内，一天前的偏移量就是跳变前的偏移量
+            let before = tz.offset_from_utc_datetime(&(local - Duration::days(1)));
+            Ok(tz.from_utc_datetime(&(local - before.fix())))
+        }
+    }
+}
+
+/// 把`from`时区的本地时间转换为`to`时区的时间
+pub fn convert(
+    local: NaiveDateTime,
+    from: &str,
+    to: &str,
+    resolve: Resolve,
+) -> Result<DateTime<Tz>, TzError> {
+    let from = parse_zone(from)?;
+    let to = parse_zone(to)?;
+    Ok(resolve_local(from, local, resolve)?.with_timezone(&to))
+}
+
+/// # 按IANA名字转换时区
+/// 2022-03-13 02:30在纽约不存在（夏令时开始），2022-11-06 01:30在纽约出现两次（夏令时结束）。
+pub fn convert_with_iana_names() -> Result<(), TzError> {
+    println!("名字包含Sao_Paulo的时区：{:?}", list_zones("sao_paulo"));
+
+    let local = NaiveDateTime::parse_from_str("2022-07-01 09:00", "%Y-%m-%d %H:%M").unwrap();
+    let shanghai = convert(local, "America/New_York", "Asia/Shanghai", Resolve::Reject)?;
+    println!("纽约{}是上海{}", local, shanghai);
+    assert_eq!(shanghai.to_rfc3339(), "2022-07-01T21:00:00+08:00");
+
+    let gap = NaiveDateTime::parse_from_str("2022-03-13 02:30", "%Y-%m-%d %H:%M").unwrap();
+    match convert(gap, "America/New_York", "Asia/Shanghai", Resolve::Reject) {
+        Err(err) => println!("{}", err),
+        Ok(time) => panic!("不存在的时间被转换成了{}", time),
+    }
+    let shifted = resolve_local(parse_zone("America/New_York")?, gap, Resolve::Earlier)?;
+    println!("顺延后是{}", shifted);
+    assert_eq!(shifted.to_rfc3339(), "2022-03-13T03:30:00-04:00");
+
+    let fold = NaiveDateTime::parse_from_str("2022-11-06 01:30", "%Y-%m-%d %H:%M").unwrap();
+    if let Err(err) = convert(fold, "America/New_York", "UTC", Resolve::Reject) {
+        println!("{}", err);
+    }
+    let earlier = convert(fold, "America/New_York", "UTC", Resolve::Earlier)?;
+    let later = convert(fold, "America/New_York", "UTC", Resolve::Later)?;
+    println!("较早的是{}，较晚的是{}", earlier, later);
+    assert_eq!(later - earlier, Duration::hours(1));
+
+    if let Err(err) = parse_zone("Mars/Olympus_Mons") {
+        println!("{}", err);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,6 @@
+-- 使用IF NOT EXISTS，已经由旧版本程序创建的表直接纳入迁移管理
+CREATE TABLE IF NOT EXISTS author (
+    id      SERIAL PRIMARY KEY,
+    name    VARCHAR NOT NULL,
+    country VARCHAR NOT NULL
+);
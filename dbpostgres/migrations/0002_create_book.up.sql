@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS book (
+    id          SERIAL PRIMARY KEY,
+    title       VARCHAR NOT NULL,
+    author_id   INTEGER NOT NULL REFERENCES author
+);
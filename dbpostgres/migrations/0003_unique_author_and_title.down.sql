@@ -0,0 +1,2 @@
+DROP INDEX book_author_title_key;
+DROP INDEX author_name_key;
@@ -0,0 +1,10 @@
+-- 合并重复的作者和图书，保留id最小的一个
+UPDATE book b SET author_id = keep.id
+FROM author a, (SELECT name, min(id) AS id FROM author GROUP BY name) keep
+WHERE b.author_id = a.id AND a.name = keep.name AND a.id <> keep.id;
+DELETE FROM author a USING author k WHERE a.name = k.name AND a.id > k.id;
+DELETE FROM book a USING book k
+WHERE a.author_id = k.author_id AND a.title = k.title AND a.id > k.id;
+
+CREATE UNIQUE INDEX IF NOT EXISTS author_name_key ON author (name);
+CREATE UNIQUE INDEX IF NOT EXISTS book_author_title_key ON book (author_id, title);
@@ -0,0 +1,5 @@
+DROP TRIGGER book_notify_change ON book;
+DROP TRIGGER author_notify_change ON author;
+DROP FUNCTION notify_change();
+DROP FUNCTION change_payload(change_log);
+DROP TABLE change_log;
@@ -0,0 +1,49 @@
+-- 记录author和book的每次修改，订阅者断开期间错过的通知从这里补齐
+CREATE TABLE change_log (
+    id          BIGSERIAL PRIMARY KEY,
+    table_name  VARCHAR NOT NULL,
+    operation   VARCHAR NOT NULL,
+    data        JSONB NOT NULL,
+    changed_at  TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- 通知和补齐使用相同的JSON格式；删除时data是删除前的行
+CREATE FUNCTION change_payload(entry change_log) RETURNS text AS $$
+    SELECT json_build_object(
+        'id', entry.id,
+        'table', entry.table_name,
+        'op', lower(entry.operation),
+        'data', entry.data
+    )::text
+$$ LANGUAGE sql STABLE;
+
+CREATE FUNCTION notify_change() RETURNS trigger AS $$
+DECLARE
+    entry change_log;
+    payload text;
+BEGIN
+    INSERT INTO change_log (table_name, operation, data)
+    VALUES (
+        TG_TABLE_NAME,
+        TG_OP,
+        to_jsonb(CASE WHEN TG_OP = 'DELETE' THEN OLD ELSE NEW END)
+    )
+    RETURNING * INTO entry;
+
+    payload := change_payload(entry);
+    -- 通知的内容不能超过8000字节，太长时只发送id，订阅者再从change_log读取
+    IF octet_length(payload) > 7900 THEN
+        payload := json_build_object('id', entry.id)::text;
+    END IF;
+    PERFORM pg_notify('library_changes', payload);
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER author_notify_change
+AFTER INSERT OR UPDATE OR DELETE ON author
+FOR EACH ROW EXECUTE FUNCTION notify_change();
+
+CREATE TRIGGER book_notify_change
+AFTER INSERT OR UPDATE OR DELETE ON book
+FOR EACH ROW EXECUTE FUNCTION notify_change();
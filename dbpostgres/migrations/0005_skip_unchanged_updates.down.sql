@@ -0,0 +1,14 @@
+DROP INDEX change_log_changed_at;
+
+DROP TRIGGER book_notify_update ON book;
+DROP TRIGGER book_notify_change ON book;
+DROP TRIGGER author_notify_update ON author;
+DROP TRIGGER author_notify_change ON author;
+
+CREATE TRIGGER author_notify_change
+AFTER INSERT OR UPDATE OR DELETE ON author
+FOR EACH ROW EXECUTE FUNCTION notify_change();
+
+CREATE TRIGGER book_notify_change
+AFTER INSERT OR UPDATE OR DELETE ON book
+FOR EACH ROW EXECUTE FUNCTION notify_change();
@@ -0,0 +1,23 @@
+-- ON CONFLICT DO UPDATE即使没有改变任何值也会触发UPDATE触发器，
+-- 重复执行的插入不应该产生update事件：更新只在行确实改变时记录
+DROP TRIGGER author_notify_change ON author;
+DROP TRIGGER book_notify_change ON book;
+
+CREATE TRIGGER author_notify_change
+AFTER INSERT OR DELETE ON author
+FOR EACH ROW EXECUTE FUNCTION notify_change();
+
+CREATE TRIGGER author_notify_update
+AFTER UPDATE ON author
+FOR EACH ROW WHEN (OLD IS DISTINCT FROM NEW) EXECUTE FUNCTION notify_change();
+
+CREATE TRIGGER book_notify_change
+AFTER INSERT OR DELETE ON book
+FOR EACH ROW EXECUTE FUNCTION notify_change();
+
+CREATE TRIGGER book_notify_update
+AFTER UPDATE ON book
+FOR EACH ROW WHEN (OLD IS DISTINCT FROM NEW) EXECUTE FUNCTION notify_change();
+
+-- 按时间清理change_log
+CREATE INDEX change_log_changed_at ON change_log (changed_at);
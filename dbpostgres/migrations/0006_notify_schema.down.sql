@@ -0,0 +1,23 @@
+-- 恢复0004中的函数：通知中没有schema，使用调用者的search_path
+CREATE OR REPLACE FUNCTION notify_change() RETURNS trigger AS $$
+DECLARE
+    entry change_log;
+    payload text;
+BEGIN
+    INSERT INTO change_log (table_name, operation, data)
+    VALUES (
+        TG_TABLE_NAME,
+        TG_OP,
+        to_jsonb(CASE WHEN TG_OP = 'DELETE' THEN OLD ELSE NEW END)
+    )
+    RETURNING * INTO entry;
+
+    payload := change_payload(entry);
+    -- 通知的内容不能超过8000字节，太长时只发送id，订阅者再从change_log读取
+    IF octet_length(payload) > 7900 THEN
+        payload := json_build_object('id', entry.id)::text;
+    END IF;
+    PERFORM pg_notify('library_changes', payload);
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,25 @@
+-- 所有schema中的触发器都在library_changes频道通知，通知中加上表所在的schema，
+-- 订阅者只处理自己的schema；函数固定使用创建时的search_path，
+-- 其他search_path的连接修改这个schema中的表时，也记录在这个schema的change_log中
+CREATE OR REPLACE FUNCTION notify_change() RETURNS trigger AS $$
+DECLARE
+    entry change_log;
+    payload text;
+BEGIN
+    INSERT INTO change_log (table_name, operation, data)
+    VALUES (
+        TG_TABLE_NAME,
+        TG_OP,
+        to_jsonb(CASE WHEN TG_OP = 'DELETE' THEN OLD ELSE NEW END)
+    )
+    RETURNING * INTO entry;
+
+    payload := (change_payload(entry)::jsonb || jsonb_build_object('schema', TG_TABLE_SCHEMA))::text;
+    -- 通知的内容不能超过8000字节，太长时只发送schema和id，订阅者再从change_log读取
+    IF octet_length(payload) > 7900 THEN
+        payload := json_build_object('schema', TG_TABLE_SCHEMA, 'id', entry.id)::text;
+    END IF;
+    PERFORM pg_notify('library_changes', payload);
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql SET search_path FROM CURRENT;
@@ -0,0 +1,537 @@
+//! # 异步的图书馆数据访问
+//! 使用`tokio-postgres`提供和`library`模块相同的作者和图书操作，给异步的服务使用：
+//! - SQL、准备好的语句、行到结构体的转换、错误的分类和`LibraryError`都和`library`共用；
+//! - 只读的方法使用`&self`，同时等待的多个查询在一个连接上流水线发送，不用等前一个返回；
+//! - `AsyncPool`是有上限的连接池，连接都在使用时`get`等待，超过`connect_timeout`返回错误；
+//! - 每个操作都有超时，超时之后通过`CancelToken`取消服务器上还在执行的查询，连接可以继续使用。
+//!   取消是针对整个连接的：服务器取消的是这个连接上当时正在执行的查询，流水线中排在前面的
+//!   查询还没有完成时，被取消的是它（返回`query_canceled`错误），超时的查询之后仍然会执行。
+//!   `AsyncLibrary`的方法都使用同一个`Deadline`，一起发送的查询同时开始计时，排在前面的查询
+//!   也已经超时；超时不同的操作不要在同一个连接上同时等待。
+use crate::config::{Config, ConfigError};
+use crate::library::{
+    author_count, author_deleted, book_moved, changed, check_no_books, sql, Author, Book,
+    BookWithAuthor, CountryStats, LibraryError, Statements,
+};
+use deadpool_postgres::{BuildError, Manager, Object, Pool, PoolError, Runtime};
+use futures_util::future;
+use postgres_native_tls::MakeTlsConnector;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_postgres::{CancelToken, Client, GenericClient};
+
+#[derive(Debug)]
+pub enum AsyncError {
+    Library(LibraryError),
+    Config(ConfigError),
+    Pool(PoolError),
+    Build(BuildError),
+    Io(io::Error),
+    /// 操作超时；`cancel_error`为空时已经请求服务器取消查询，否则是取消请求失败的原因
+    Timeout {
+        timeout: Duration,
+        cancel_error: Option<postgres::Error>,
+    },
+}
+
+impl fmt::Display for AsyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsyncError::Library(err) => write!(f, "{}", err),
+            AsyncError::Config(err) => write!(f, "{}", err),
+            AsyncError::Pool(err) => write!(f, "获取连接错误：{}", err),
+            AsyncError::Build(err) => write!(f, "创建连接池错误：{}", err),
+            AsyncError::Io(err) => write!(f, "创建运行时错误：{}", err),
+            AsyncError::Timeout {
+                timeout,
+                cancel_error: None,
+            } => write!(f, "操作超过{:?}，已经取消", timeout),
+            AsyncError::Timeout {
+                timeout,
+                cancel_error: Some(err),
+            } => write!(f, "操作超过{:?}，取消查询失败：{}", timeout, err),
+        }
+    }
+}
+
+impl Error for AsyncError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AsyncError::Library(err) => Some(err),
+            AsyncError::Config(err) => Some(err),
+            AsyncError::Pool(err) => Some(err),
+            AsyncError::Build(err) => Some(err),
+            AsyncError::Io(err) => Some(err),
+            AsyncError::Timeout { cancel_error, .. } => cancel_error
+                .as_ref()
+                .map(|err| err as &(dyn Error + 'static)),
+        }
+    }
+}
+
+impl From<LibraryError> for AsyncError {
+    fn from(err: LibraryError) -> Self {
+        AsyncError::Library(err)
+    }
+}
+
+impl From<postgres::Error> for AsyncError {
+    fn from(err: postgres::Error) -> Self {
+        AsyncError::Library(LibraryError::Postgres(err))
+    }
+}
+
+impl From<ConfigError> for AsyncError {
+    fn from(err: ConfigError) -> Self {
+        AsyncError::Config(err)
+    }
+}
+
+impl From<PoolError> for AsyncError {
+    fn from(err: PoolError) -> Self {
+        AsyncError::Pool(err)
+    }
+}
+
+impl From<BuildError> for AsyncError {
+    fn from(err: BuildError) -> Self {
+        AsyncError::Build(err)
+    }
+}
+
+impl From<io::Error> for AsyncError {
+    fn from(err: io::Error) -> Self {
+        AsyncError::Io(err)
+    }
+}
+
+/// 操作的超时时间，以及取消查询时连接服务器需要的TLS连接器
+#[derive(Clone)]
+pub struct Deadline {
+    timeout: Duration,
+    tls: MakeTlsConnector,
+}
+
+impl Deadline {
+    pub fn new(timeout: Duration, tls: MakeTlsConnector) -> Self {
+        Deadline { timeout, tls }
+    }
+
+    /// 等待`operation`完成；超时的时候通过`token`取消服务器上正在执行的查询。
+    /// `token`取消的是连接上当时正在执行的查询，同一个连接上还有其他查询排在前面时，
+    /// 被取消的是那个查询，见模块的说明
+    pub async fn run<T, F>(&self, token: &CancelToken, operation: F) -> Result<T, AsyncError>
+    where
+        F: Future<Output = Result<T, LibraryError>>,
+    {
+        match tokio::time::timeout(self.timeout, operation).await {
+            Ok(result) => Ok(result?),
+            Err(_) => {
+                // 只丢弃future的话服务器会继续执行，连接上后面的查询也要排队等待；
+                // 取消失败时查询最终也会结束，仍然报告超时，由调用者决定是否丢弃这个连接
+                let cancel_error = token.cancel_query(self.tls.clone()).await.err();
+                Err(AsyncError::Timeout {
+                    timeout: self.timeout,
+                    cancel_error,
+                })
+            }
+        }
+    }
+}
+
+/// 有上限的异步连接池，连接参数和`Config::pool`相同
+#[derive(Clone)]
+pub struct AsyncPool {
+    pool: Pool,
+    deadline: Deadline,
+}
+
+impl AsyncPool {
+    /// 创建连接池并先取一个连接检查，`query_timeout`是每个操作的超时时间
+    pub async fn new(config: &Config, query_timeout: Duration) -> Result<Self, AsyncError> {
+        let tls = config.tls()?;
+        let manager = Manager::new(config.tokio_config(), tls.clone());
+        let pool = Pool::builder(manager)
+            .max_size(config.pool_size as usize)
+            .wait_timeout(Some(config.connect_timeout))
+            .create_timeout(Some(config.connect_timeout))
+            .runtime(Runtime::Tokio1)
+            .build()?;
+        drop(pool.get().await?);
+        Ok(AsyncPool {
+            pool,
+            deadline: Deadline::new(query_timeout, tls),
+        })
+    }
+
+    /// 取得一个连接，所有连接都在使用时等待其他任务归还
+    pub async fn get(&self) -> Result<Object, AsyncError> {
+        Ok(self.pool.get().await?)
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.pool.status().max_size
+    }
+
+    pub fn deadline(&self) -> Deadline {
+        self.deadline.clone()
+    }
+}
+
+pub struct AsyncLibrary<'c, C: GenericClient> {
+    client: &'c mut C,
+    statements: Statements,
+    deadline: Deadline,
+    token: CancelToken,
+}
+
+impl<'c, C: GenericClient + Sync> AsyncLibrary<'c, C> {
+    /// 所有语句同时准备，13个`Parse`请求在一次往返中完成
+    pub async fn new(client: &'c mut C, deadline: Deadline) -> Result<Self, AsyncError> {
+        let token = client.client().cancel_token();
+        let shared = &*client;
+        let prepared = deadline
+            .run(&token, async {
+                let statements = sql::ALL.iter().map(|sql| shared.prepare(sql));
+                Ok(future::try_join_all(statements).await?)
+            })
+            .await?;
+        let statements = Statements::from_prepared(prepared);
+        Ok(AsyncLibrary {
+            client,
+            statements,
+            deadline,
+            token,
+        })
+    }
+
+    async fn run<T, F>(&self, operation: F) -> Result<T, AsyncError>
+    where
+        F: Future<Output = Result<T, LibraryError>>,
+    {
+        self.deadline.run(&self.token, operation).await
+    }
+
+    /// 插入作者，同名的作者已经存在时更新国家
+    pub async fn upsert_author(&self, name: &str, country: &str) -> Result<Author, AsyncError> {
+        self.run(async {
+            let row = self
+                .client
+                .query_one(&self.statements.upsert_author, &[&name, &country])
+                .await?;
+            Ok(Author::from(&row))
+        })
+        .await
+    }
+
+    pub async fn find_author(&self, id: i32) -> Result<Option<Author>, AsyncError> {
+        self.run(async {
+            let row = self
+                .client
+                .query_opt(&self.statements.find_author, &[&id])
+                .await?;
+            Ok(row.as_ref().map(Author::from))
+        })
+        .await
+    }
+
+    pub async fn update_author(&self, author: &Author) -> Result<(), AsyncError> {
+        self.run(async {
+            let rows = self
+                .client
+                .execute(
+                    &self.statements.update_author,
+                    &[&author.id, &author.name, &author.country],
+                )
+                .await?;
+            changed("作者", author.id, rows)
+        })
+        .await
+    }
+
+    /// 删除没有图书的作者
+    pub async fn delete_author(&self, id: i32) -> Result<(), AsyncError> {
+        self.run(async {
+            let books: i64 = self
+                .client
+                .query_one(&self.statements.count_author_books, &[&id])
+                .await?
+                .get(0);
+            check_no_books(id, books)?;
+            let result = self
+                .client
+                .execute(&self.statements.delete_author, &[&id])
+                .await;
+            author_deleted(id, result)
+        })
+        .await
+    }
+
+    /// 在同一个事务中插入作者和图书，重复执行不会产生重复的数据
+    pub async fn add_book(
+        &mut self,
+        title: &str,
+        author: &str,
+        country: &str,
+    ) -> Result<Book, AsyncError> {
+        let AsyncLibrary {
+            client,
+            statements,
+            deadline,
+            token,
+        } = self;
+        deadline
+            .run(token, async {
+                let tx = client.transaction().await?;
+                let author: i32 = tx
+                    .query_one(&statements.upsert_author, &[&author, &country])
+                    .await?
+                    .get("id");
+                let row = tx
+                    .query_one(&statements.upsert_book, &[&title, &author])
+                    .await?;
+                tx.commit().await?;
+                Ok(Book::from(&row))
+            })
+            .await
+    }
+
+    pub async fn books_by_author(&self, author: &str) -> Result<Vec<BookWithAuthor>, AsyncError> {
+        self.run(async {
+            let rows = self
+                .client
+                .query(&self.statements.books_by_author, &[&author])
+                .await?;
+            Ok(rows.iter().map(BookWithAuthor::from).collect())
+        })
+        .await
+    }
+
+    /// 一次查询多个作者的图书，所有查询流水线发送，结果和`authors`的顺序相同
+    pub async fn books_by_authors(
+        &self,
+        authors: &[&str],
+    ) -> Result<Vec<Vec<BookWithAuthor>>, AsyncError> {
+        self.run(async {
+            let queries = authors.iter().map(|author| async move {
+                self.client
+                    .query(&self.statements.books_by_author, &[author])
+                    .await
+            });
+            let results = future::try_join_all(queries).await?;
+            Ok(results
+                .iter()
+                .map(|rows| rows.iter().map(BookWithAuthor::from).collect())
+                .collect())
+        })
+        .await
+    }
+
+    pub async fn books_by_country(&self, country: &str) -> Result<Vec<BookWithAuthor>, AsyncError> {
+        self.run(async {
+            let rows = self
+                .client
+                .query(&self.statements.books_by_country, &[&country])
+                .await?;
+            Ok(rows.iter().map(BookWithAuthor::from).collect())
+        })
+        .await
+    }
+
+    pub async fn rename_book(&self, id: i32, title: &str) -> Result<(), AsyncError> {
+        self.run(async {
+            let rows = self
+                .client
+                .execute(&self.statements.rename_book, &[&id, &title])
+                .await?;
+            changed("图书", id, rows)
+        })
+        .await
+    }
+
+    /// 把图书移到另一个作者名下，作者不存在时返回`NotFound`
+    pub async fn move_book(&self, id: i32, author_id: i32) -> Result<(), AsyncError> {
+        // 先检查作者，外键错误会让所在的事务无法继续
+        if self.find_author(author_id).await?.is_none() {
+            return Err(LibraryError::NotFound {
+                what: "作者",
+                id: author_id,
+            }
+            .into());
+        }
+        self.run(async {
+            let result = self
+                .client
+                .execute(&self.statements.move_book, &[&id, &author_id])
+                .await;
+            book_moved(id, author_id, result)
+        })
+        .await
+    }
+
+    pub async fn delete_book(&self, id: i32) -> Result<(), AsyncError> {
+        self.run(async {
+            let rows = self
+                .client
+                .execute(&self.statements.delete_book, &[&id])
+                .await?;
+            changed("图书", id, rows)
+        })
+        .await
+    }
+
+    /// 每个作者的图书数量，包括没有图书的作者
+    pub async fn count_by_author(&self) -> Result<Vec<(String, i64)>, AsyncError> {
+        self.run(async {
+            let rows = self
+                .client
+                .query(&self.statements.count_by_author, &[])
+                .await?;
+            Ok(rows.iter().map(author_count).collect())
+        })
+        .await
+    }
+
+    pub async fn count_by_country(&self) -> Result<Vec<CountryStats>, AsyncError> {
+        self.run(async {
+            let rows = self
+                .client
+                .query(&self.statements.count_by_country, &[])
+                .await?;
+            Ok(rows.iter().map(CountryStats::from).collect())
+        })
+        .await
+    }
+}
+
+/// # 异步访问图书馆
+/// 修改在最后回滚的事务中进行；另外演示连接池的上限和超时取消查询。
+pub fn run_async_library(config: &Config) -> Result<(), AsyncError> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        let pool = AsyncPool::new(config, Duration::from_secs(5)).await?;
+
+        let mut object = pool.get().await?;
+        let client: &mut Client = &mut object;
+        let mut tx = client.transaction().await?;
+        let mut library = AsyncLibrary::new(&mut tx, pool.deadline()).await?;
+
+        let country = "Async Atlantis";
+        let tide = library
+            .add_book("Tide Tables", "Async Author A", country)
+            .await?;
+        library
+            .add_book("Salt and Coral", "Async Author A", country)
+            .await?;
+        let currents = library
+            .add_book("Deep Currents", "Async Author B", country)
+            .await?;
+        let again = library
+            .add_book("Tide Tables", "Async Author A", country)
+            .await?;
+        assert_eq!(again, tide);
+
+        // 三个查询一起发送，只等待一次网络往返
+        let (books, counts, stats) = tokio::try_join!(
+            library.books_by_country(country),
+            library.count_by_author(),
+            library.count_by_country(),
+        )?;
+        assert_eq!(books.len(), 3);
+        assert!(counts.contains(&("Async Author A".to_string(), 2)));
+        let atlantis = stats.iter().find(|stats| stats.country == country);
+        assert_eq!(
+            atlantis.map(|stats| (stats.authors, stats.books)),
+            Some((2, 3))
+        );
+
+        let per_author = library
+            .books_by_authors(&["Async Author A", "Async Author B", "Nobody"])
+            .await?;
+        let sizes: Vec<_> = per_author.iter().map(Vec::len).collect();
+        assert_eq!(sizes, [2, 1, 0]);
+
+        library.rename_book(currents.id, "Deeper Currents").await?;
+        library.move_book(currents.id, tide.author_id).await?;
+        match library.delete_author(tide.author_id).await {
+            Err(err @ AsyncError::Library(LibraryError::HasBooks { books: 3, .. })) => {
+                println!("{}", err)
+            }
+            other => panic!("作者还有图书，不能删除：{:?}", other),
+        }
+        match library.move_book(tide.id, -1).await {
+            Err(AsyncError::Library(LibraryError::NotFound { what: "作者", .. })) => {}
+            other => panic!("作者不存在：{:?}", other),
+        }
+        library.delete_book(currents.id).await?;
+        let author = library
+            .find_author(tide.author_id)
+            .await?
+            .expect("作者存在");
+        library
+            .update_author(&Author {
+                country: "Async Lemuria".to_string(),
+                ..author
+            })
+            .await?;
+        assert_eq!(library.books_by_author("Async Author A").await?.len(), 2);
+        drop(library);
+        tx.rollback().await?;
+
+        // 同时运行的任务比连接多，同时在用的连接数不超过上限
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let tasks: Vec<_> = (0..pool.max_size() * 3)
+            .map(|_| {
+                let pool = pool.clone();
+                let active = Arc::clone(&active);
+                let peak = Arc::clone(&peak);
+                tokio::spawn(async move {
+                    let client = pool.get().await?;
+                    let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    client.execute("SELECT pg_sleep(0.05)", &[]).await?;
+                    active.fetch_sub(1, Ordering::SeqCst);
+                    Ok::<_, AsyncError>(())
+                })
+            })
+            .collect();
+        for task in future::join_all(tasks).await {
+            task.expect("任务不应该panic")?;
+        }
+        println!(
+            "{}个任务同时使用的连接最多{}个",
+            pool.max_size() * 3,
+            peak.load(Ordering::SeqCst)
+        );
+        assert!(peak.load(Ordering::SeqCst) <= pool.max_size());
+
+        // 超时之后服务器上的查询被取消，同一个连接上的下一个查询不用等它执行完
+        let client = pool.get().await?;
+        let deadline = Deadline::new(Duration::from_millis(200), config.tls()?);
+        let started = Instant::now();
+        let sleep = deadline
+            .run(&client.cancel_token(), async {
+                Ok(client.execute("SELECT pg_sleep(30)", &[]).await?)
+            })
+            .await;
+        match sleep {
+            Err(
+                err @ AsyncError::Timeout {
+                    cancel_error: None, ..
+                },
+            ) => println!("{}", err),
+            other => panic!("查询应该超时：{:?}", other),
+        }
+        let one: i32 = client.query_one("SELECT 1", &[]).await?.get(0);
+        assert_eq!(one, 1);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        Ok(())
+    })
+}
@@ -0,0 +1,99 @@
+//! # Postgres迁移命令行工具
+//! ```text
+//! pg-migrate status
+//! pg-migrate up --to 2 --dry-run
+//! pg-migrate --dir dbpostgres/migrations down --steps 1
+//! ```
+//! 连接参数和`dbpostgres`相同，不指定`--dir`时使用编译进程序的`library`迁移。
+use clap::{App, Arg};
+use dbpostgres::config::Config;
+use dbpostgres::migrations::{self, MigrationError, Migrator};
+use std::error::Error;
+use std::io;
+use std::path::Path;
+use std::process;
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let matches = App::new("pg-migrate")
+        .about("执行、回滚和查看Postgres数据库迁移")
+        .subcommand_required(true)
+        .arg(
+            Arg::new("dir")
+                .long("dir")
+                .takes_value(true)
+                .help("包含0001_name.up.sql和0001_name.down.sql的目录"),
+        )
+        .subcommand(
+            App::new("up")
+                .about("执行未执行的迁移")
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .takes_value(true)
+                        .help("只执行到这个版本"),
+                )
+                .arg(
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .help("只打印将要执行的SQL"),
+                ),
+        )
+        .subcommand(
+            App::new("down").about("回滚最新的迁移").arg(
+                Arg::new("steps")
+                    .long("steps")
+                    .takes_value(true)
+                    .default_value("1")
+                    .help("回滚的迁移数量"),
+            ),
+        )
+        .subcommand(App::new("status").about("列出迁移的状态"))
+        .get_matches();
+
+    let migrator = match matches.value_of("dir") {
+        Some(dir) => Migrator::from_dir(Path::new(dir))?,
+        None => migrations::library(),
+    };
+    let mut client = Config::from_env()?.connect()?;
+    let number = |text: &str| {
+        text.parse::<i32>()
+            .map_err(|_| MigrationError::Invalid(format!("无效的数字：{}", text)))
+    };
+
+    match matches.subcommand() {
+        Some(("up", args)) => {
+            let target = args.value_of("to").map(number).transpose()?;
+            if args.is_present("dry-run") {
+                let planned = migrator.dry_run(&mut client, target, &mut io::stdout())?;
+                eprintln!("将要执行{}个迁移：{:?}", planned.len(), planned);
+            } else {
+                let done = migrator.up(&mut client, target)?;
+                println!("执行了{}个迁移：{:?}", done.len(), done);
+            }
+        }
+        Some(("down", args)) => {
+            let text = args.value_of("steps").unwrap();
+            let steps = text
+                .parse::<usize>()
+                .ok()
+                .filter(|steps| *steps > 0)
+                .ok_or_else(|| MigrationError::Invalid(format!("--steps应该是正整数：{}", text)))?;
+            let done = migrator.down(&mut client, steps)?;
+            println!("回滚了{}个迁移：{:?}", done.len(), done);
+        }
+        Some(("status", _)) => {
+            for status in migrator.status(&mut client)? {
+                println!("{}", status);
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,443 @@
+//! # 用COPY批量导入导出
+//! 逐行`INSERT`每一行都要往返一次服务器，ETL任务改用`COPY`：
+//! - `copy_in`把结构体的迭代器以文本或二进制格式写入`COPY ... FROM STDIN`；
+//! - `load_authors`先COPY到临时表，再合并到`author`，重复的作者只更新国家；
+//! - `load_authors_csv`从CSV文件读取作者；
+//! - `copy_out`把任意查询用`COPY ... TO STDOUT`导出为CSV或者COPY文本格式。
+//!
+//! 每写入或读出`progress_every`行调用一次进度回调，结束时服务器报告的行数和实际的行数不一致时返回错误。
+use crate::config::Config;
+use crate::migrations;
+use postgres::binary_copy::BinaryCopyInWriter;
+use postgres::types::{ToSql, Type};
+use postgres::{Client, GenericClient, IsolationLevel};
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum BulkError {
+    Postgres(postgres::Error),
+    Io(io::Error),
+    Csv(csv::Error),
+    /// 写入或者读出的行数和预期的不一致
+    RowCount {
+        expected: u64,
+        actual: u64,
+    },
+}
+
+impl fmt::Display for BulkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BulkError::Postgres(err) => write!(f, "数据库错误：{}", err),
+            BulkError::Io(err) => write!(f, "读写错误：{}", err),
+            BulkError::Csv(err) => write!(f, "CSV错误：{}", err),
+            BulkError::RowCount { expected, actual } => {
+                write!(f, "行数不一致：应该是{}行，实际是{}行", expected, actual)
+            }
+        }
+    }
+}
+
+impl Error for BulkError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BulkError::Postgres(err) => Some(err),
+            BulkError::Io(err) => Some(err),
+            BulkError::Csv(err) => Some(err),
+            BulkError::RowCount { .. } => None,
+        }
+    }
+}
+
+impl From<postgres::Error> for BulkError {
+    fn from(err: postgres::Error) -> Self {
+        BulkError::Postgres(err)
+    }
+}
+
+impl From<io::Error> for BulkError {
+    fn from(err: io::Error) -> Self {
+        BulkError::Io(err)
+    }
+}
+
+impl From<csv::Error> for BulkError {
+    fn from(err: csv::Error) -> Self {
+        BulkError::Csv(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyFormat {
+    /// COPY的文本格式，制表符分隔
+    Text,
+    Binary,
+}
+
+/// 可以用COPY写入的一行
+pub trait CopyRow {
+    fn columns() -> &'static [&'static str];
+    /// 二进制格式中每一列的类型
+    fn types() -> &'static [Type];
+    fn values(&self) -> Vec<&(dyn ToSql + Sync)>;
+    /// 文本格式中每一列的值，`None`为`NULL`
+    fn text(&self) -> Vec<Option<String>>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct NewAuthor {
+    pub name: String,
+    pub country: String,
+}
+
+impl CopyRow for NewAuthor {
+    fn columns() -> &'static [&'static str] {
+        &["name", "country"]
+    }
+
+    fn types() -> &'static [Type] {
+        &[Type::VARCHAR, Type::VARCHAR]
+    }
+
+    fn values(&self) -> Vec<&(dyn ToSql + Sync)> {
+        vec![&self.name, &self.country]
+    }
+
+    fn text(&self) -> Vec<Option<String>> {
+        vec![Some(self.name.clone()), Some(self.country.clone())]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct NewBook {
+    pub title: String,
+    pub author_id: i32,
+}
+
+impl CopyRow for NewBook {
+    fn columns() -> &'static [&'static str] {
+        &["title", "author_id"]
+    }
+
+    fn types() -> &'static [Type] {
+        &[Type::VARCHAR, Type::INT4]
+    }
+
+    fn values(&self) -> Vec<&(dyn ToSql + Sync)> {
+        vec![&self.title, &self.author_id]
+    }
+
+    fn text(&self) -> Vec<Option<String>> {
+        vec![Some(self.title.clone()), Some(self.author_id.to_string())]
+    }
+}
+
+/// 进度回调的间隔
+#[derive(Debug, Clone, Copy)]
+pub struct CopyOptions {
+    pub format: CopyFormat,
+    pub progress_every: u64,
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        CopyOptions {
+            format: CopyFormat::Text,
+            progress_every: 10_000,
+        }
+    }
+}
+
+/// 转义COPY文本格式中的反斜杠、制表符和换行
+fn escape_text(field: &Option<String>) -> String {
+    match field {
+        None => "\\N".to_string(),
+        Some(text) => {
+            let mut escaped = String::with_capacity(text.len());
+            for c in text.chars() {
+                match c {
+                    '\\' => escaped.push_str("\\\\"),
+                    '\t' => escaped.push_str("\\t"),
+                    '\n' => escaped.push_str("\\n"),
+                    '\r' => escaped.push_str("\\r"),
+                    c => escaped.push(c),
+                }
+            }
+            escaped
+        }
+    }
+}
+
+/// 把`rows`写入`table`，返回服务器报告的行数
+pub fn copy_in<C, T, I>(
+    client: &mut C,
+    table: &str,
+    rows: I,
+    options: CopyOptions,
+    progress: &mut dyn FnMut(u64),
+) -> Result<u64, BulkError>
+where
+    C: GenericClient,
+    T: CopyRow,
+    I: IntoIterator<Item = T>,
+{
+    let every = options.progress_every.max(1);
+    let mut sent = 0;
+    let copied = match options.format {
+        CopyFormat::Text => {
+            let sql = format!("COPY {} ({}) FROM STDIN", table, T::columns().join(", "));
+            let mut writer = client.copy_in(sql.as_str())?;
+            for row in rows {
+                let line: Vec<String> = row.text().iter().map(escape_text).collect();
+                writeln!(writer, "{}", line.join("\t"))?;
+                sent += 1;
+                if sent % every == 0 {
+                    progress(sent);
+                }
+            }
+            writer.finish()?
+        }
+        CopyFormat::Binary => {
+            let sql = format!(
+                "COPY {} ({}) FROM STDIN WITH (FORMAT binary)",
+                table,
+                T::columns().join(", ")
+            );
+            let mut writer = BinaryCopyInWriter::new(client.copy_in(sql.as_str())?, T::types());
+            for row in rows {
+                writer.write(&row.values())?;
+                sent += 1;
+                if sent % every == 0 {
+                    progress(sent);
+                }
+            }
+            writer.finish()?
+        }
+    };
+    if sent % every != 0 {
+        progress(sent);
+    }
+    if copied != sent {
+        return Err(BulkError::RowCount {
+            expected: sent,
+            actual: copied,
+        });
+    }
+    Ok(copied)
+}
+
+/// 导入作者：COPY到临时表后合并，已有的作者只更新国家；返回写入的行数和新增的作者数
+pub fn load_authors<I>(
+    client: &mut Client,
+    authors: I,
+    options: CopyOptions,
+    progress: &mut dyn FnMut(u64),
+) -> Result<(u64, u64), BulkError>
+where
+    I: IntoIterator<Item = NewAuthor>,
+{
+    let mut tx = client.transaction()?;
+    tx.batch_execute(
+        "CREATE TEMPORARY TABLE author_load (name VARCHAR NOT NULL, country VARCHAR NOT NULL)
+        ON COMMIT DROP",
+    )?;
+    let copied = copy_in(&mut tx, "author_load", authors, options, progress)?;
+    let before: i64 = tx.query_one("SELECT count(*) FROM author", &[])?.get(0);
+    // 同一个名字在文件中出现多次时取最后一行
+    tx.execute(
+        "INSERT INTO author (name, country)
+        SELECT DISTINCT ON (name) name, country FROM author_load ORDER BY name, ctid DESC
+        ON CONFLICT (name) DO UPDATE SET country = excluded.country",
+        &[],
+    )?;
+    let after: i64 = tx.query_one("SELECT count(*) FROM author", &[])?.get(0);
+    tx.commit()?;
+    Ok((copied, (after - before) as u64))
+}
+
+/// 从有`name,country`表头的CSV文件导入作者
+pub fn load_authors_csv<P: AsRef<Path>>(
+    client: &mut Client,
+    path: P,
+    options: CopyOptions,
+    progress: &mut dyn FnMut(u64),
+) -> Result<(u64, u64), BulkError> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let authors = reader
+        .deserialize()
+        .collect::<Result<Vec<NewAuthor>, csv::Error>>()?;
+    load_authors(client, authors, options, progress)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// 带表头的CSV
+    Csv,
+    /// COPY的文本格式，每行一条记录
+    Text,
+}
+
+/// 把查询结果写到`writer`，返回行数；行数和`count(*)`不一致时返回错误。
+/// COPY和`count(*)`在同一个只读的REPEATABLE READ事务中执行，看到的是同一个快照，
+/// 其他连接同时提交的修改不会让两者不一致。
+pub fn copy_out<W: Write>(
+    client: &mut Client,
+    query: &str,
+    format: ExportFormat,
+    writer: W,
+    progress_every: u64,
+    progress: &mut dyn FnMut(u64),
+) -> Result<u64, BulkError> {
+    let mut tx = client
+        .build_transaction()
+        .isolation_level(IsolationLevel::RepeatableRead)
+        .read_only(true)
+        .start()?;
+    let every = progress_every.max(1);
+    let mut rows = 0;
+    match format {
+        ExportFormat::Csv => {
+            let sql = format!("COPY ({}) TO STDOUT WITH (FORMAT csv, HEADER true)", query);
+            // 经过csv解析再写出，字段中的换行不会影响行数
+            let mut reader = csv::Reader::from_reader(tx.copy_out(sql.as_str())?);
+            let mut writer = csv::Writer::from_writer(writer);
+            writer.write_record(reader.headers()?)?;
+            for record in reader.records() {
+                writer.write_record(&record?)?;
+                rows += 1;
+                if rows % every == 0 {
+                    progress(rows);
+                }
+            }
+            writer.flush()?;
+        }
+        ExportFormat::Text => {
+            let sql = format!("COPY ({}) TO STDOUT", query);
+            let reader = BufReader::new(tx.copy_out(sql.as_str())?);
+            let mut writer = writer;
+            for line in reader.lines() {
+                writeln!(writer, "{}", line?)?;
+                rows += 1;
+                if rows % every == 0 {
+                    progress(rows);
+                }
+            }
+            writer.flush()?;
+        }
+    }
+    if rows % every != 0 {
+        progress(rows);
+    }
+
+    let expected: i64 = tx
+        .query_one(format!("SELECT count(*) FROM ({}) q", query).as_str(), &[])?
+        .get(0);
+    tx.commit()?;
+    if expected as u64 != rows {
+        return Err(BulkError::RowCount {
+            expected: expected as u64,
+            actual: rows,
+        });
+    }
+    Ok(rows)
+}
+
+/// # 用COPY批量导入导出作者和图书
+/// 在临时的schema中建表并导入，最后删除这个schema，不影响`public`中的数据。
+pub fn bulk_load(config: &Config) -> Result<(), Box<dyn Error>> {
+    let schema = format!("bulk_demo_{}", std::process::id());
+    let mut client = migrations::connect_to_schema(config, &schema)?;
+    migrations::library().up(&mut client, None)?;
+    let result = load_and_export(&mut client);
+    client.batch_execute(&format!("DROP SCHEMA {} CASCADE", schema))?;
+    result?;
+    Ok(())
+}
+
+fn load_and_export(client: &mut Client) -> Result<(), BulkError> {
+    let country = "Bulkland";
+    let authors = (0..20_000).map(|i| NewAuthor {
+        name: format!("Bulk Author {:05}", i),
+        country: country.to_string(),
+    });
+
+    let mut reports = vec![];
+    let options = CopyOptions {
+        format: CopyFormat::Text,
+        progress_every: 5_000,
+    };
+    let (copied, created) =
+        load_authors(client, authors.clone().take(12_000), options, &mut |rows| {
+            reports.push(rows)
+        })?;
+    println!("文本格式写入{}行，新增作者{}个", copied, created);
+    assert_eq!((copied, created), (12_000, 12_000));
+    assert_eq!(reports, [5_000, 10_000, 12_000]);
+
+    // 二进制格式，前2000个作者已经存在
+    let binary = CopyOptions {
+        format: CopyFormat::Binary,
+        ..options
+    };
+    let (copied, created) = load_authors(client, authors.skip(10_000), binary, &mut |_| {})?;
+    assert_eq!((copied, created), (10_000, 8_000));
+
+    // CSV文件：引号中的逗号、换行和制表符，以及重复的作者
+    let path = std::env::temp_dir().join(format!("authors-{}.csv", std::process::id()));
+    std::fs::write(
+        &path,
+        "name,country\n\"Bulk, Quoted\",Bulkland\n\"Bulk\tTab\nNewline\",Bulkland\nBulk Author 00000,Elsewhere\n",
+    )?;
+    let (copied, created) = load_authors_csv(client, &path, options, &mut |_| {})?;
+    std::fs::remove_file(&path)?;
+    assert_eq!((copied, created), (3, 2));
+    let moved: String = client
+        .query_one(
+            "SELECT country FROM author WHERE name = 'Bulk Author 00000'",
+            &[],
+        )?
+        .get(0);
+    assert_eq!(moved, "Elsewhere");
+
+    // 直接COPY图书
+    let first: i32 = client
+        .query_one("SELECT min(id) FROM author WHERE country = $1", &[&country])?
+        .get(0);
+    let books = (0..1_000).map(|i| NewBook {
+        title: format!("Bulk Book {}", i),
+        author_id: first,
+    });
+    assert_eq!(copy_in(client, "book", books, binary, &mut |_| {})?, 1_000);
+
+    let mut csv = vec![];
+    let mut exported = 0;
+    let rows = copy_out(
+        client,
+        "SELECT name, country FROM author WHERE name LIKE 'Bulk%' ORDER BY name",
+        ExportFormat::Csv,
+        &mut csv,
+        5_000,
+        &mut |rows| exported = rows,
+    )?;
+    assert_eq!((rows, exported), (20_002, 20_002));
+    let csv = String::from_utf8_lossy(&csv);
+    assert!(csv.starts_with("name,country\n"));
+    assert!(csv.contains("\"Bulk\tTab\nNewline\",Bulkland\n") && csv.contains("\"Bulk, Quoted\""));
+
+    let mut text = vec![];
+    let rows = copy_out(
+        client,
+        "SELECT title, author_id FROM book WHERE title LIKE 'Bulk Book%'",
+        ExportFormat::Text,
+        &mut text,
+        1_000,
+        &mut |_| {},
+    )?;
+    assert_eq!(rows, 1_000);
+    assert!(String::from_utf8_lossy(&text).contains(&format!("Bulk Book 0\t{}\n", first)));
+    Ok(())
+}
@@ -0,0 +1,497 @@
+//! # 订阅图书馆的修改
+//! 迁移`0004_change_log`在`author`和`book`上安装触发器，每次插入、更新和删除都写入
+//! `change_log`表，并通过`pg_notify`在`library_changes`频道发送JSON：
+//! ```text
+//! {"schema": "public", "id": 42, "table": "book", "op": "update", "data": {"id": 7, "title": "...", "author_id": 3}}
+//! ```
+//! 同一个数据库中所有schema的修改都在这个频道通知，迁移`0006`之后通知中带有表所在的schema。
+//! `Subscriber`执行`LISTEN`并把自己schema的通知解析成`Event`，其他schema（例如演示和测试使用的
+//! 临时schema）的通知被忽略。连接断开时重新连接，
+//! 断开期间错过的修改从`change_log`补齐，每次`poll`最多补齐一页（默认500个）；
+//! 通知和补齐可能重复，按`id`去掉重复的事件。迁移`0005`之后，没有改变任何值的更新
+//! （例如重复执行的upsert）不产生事件。
+//!
+//! `change_log`不会自动清理，用`prune_change_log`删除所有订阅者都已经处理过的或者过期的记录；
+//! 订阅者离线的时间超过保留时间时，被删除的修改就补不回来了。
+use crate::config::{Config, ConfigError};
+use crate::library::{Author, Book, Library, LibraryError};
+use crate::migrations;
+use postgres::fallible_iterator::FallibleIterator;
+use postgres::{Client, GenericClient};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+pub const CHANNEL: &str = "library_changes";
+
+/// 记住的最近事件数量；晚提交的事务`id`可能比已经收到的小，补齐时从记住的最小`id`开始
+const REMEMBERED: usize = 1024;
+
+/// 每次`poll`从`change_log`补齐的默认行数
+const REPLAY_PAGE: i64 = 500;
+
+#[derive(Debug)]
+pub enum EventError {
+    Postgres(postgres::Error),
+    Config(ConfigError),
+    Json(serde_json::Error),
+    /// 通知太长时只包含`id`，需要从`change_log`读取
+    Truncated {
+        id: i64,
+    },
+    Payload(String),
+    Library(LibraryError),
+}
+
+impl fmt::Display for EventError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventError::Postgres(err) => write!(f, "数据库错误：{}", err),
+            EventError::Config(err) => write!(f, "{}", err),
+            EventError::Json(err) => write!(f, "解析通知错误：{}", err),
+            EventError::Truncated { id } => write!(f, "通知{}只有编号", id),
+            EventError::Payload(message) => write!(f, "无效的通知：{}", message),
+            EventError::Library(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for EventError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EventError::Postgres(err) => Some(err),
+            EventError::Config(err) => Some(err),
+            EventError::Json(err) => Some(err),
+            EventError::Library(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<postgres::Error> for EventError {
+    fn from(err: postgres::Error) -> Self {
+        EventError::Postgres(err)
+    }
+}
+
+impl From<ConfigError> for EventError {
+    fn from(err: ConfigError) -> Self {
+        EventError::Config(err)
+    }
+}
+
+impl From<serde_json::Error> for EventError {
+    fn from(err: serde_json::Error) -> Self {
+        EventError::Json(err)
+    }
+}
+
+impl From<LibraryError> for EventError {
+    fn from(err: LibraryError) -> Self {
+        EventError::Library(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// 修改后的行；删除时是删除前的行
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    Author(Author),
+    Book(Book),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// `change_log`中的编号
+    pub id: i64,
+    pub operation: Operation,
+    pub record: Record,
+}
+
+#[derive(Deserialize)]
+struct Payload {
+    /// 只有通知中有，从`change_log`补齐的事件没有
+    schema: Option<String>,
+    id: i64,
+    table: Option<String>,
+    op: Option<String>,
+    data: Option<serde_json::Value>,
+}
+
+impl Event {
+    pub fn parse(payload: &str) -> Result<Event, EventError> {
+        Event::from_payload(serde_json::from_str(payload)?)
+    }
+
+    fn from_payload(payload: Payload) -> Result<Event, EventError> {
+        let (table, op, data) = match (payload.table, payload.op, payload.data) {
+            (Some(table), Some(op), Some(data)) => (table, op, data),
+            _ => return Err(EventError::Truncated { id: payload.id }),
+        };
+        let operation = match op.as_str() {
+            "insert" => Operation::Insert,
+            "update" => Operation::Update,
+            "delete" => Operation::Delete,
+            _ => return Err(EventError::Payload(format!("未知的操作：{}", op))),
+        };
+        let record = match table.as_str() {
+            "author" => Record::Author(serde_json::from_value(data)?),
+            "book" => Record::Book(serde_json::from_value(data)?),
+            _ => return Err(EventError::Payload(format!("未知的表：{}", table))),
+        };
+        Ok(Event {
+            id: payload.id,
+            operation,
+            record,
+        })
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} {:?} ", self.id, self.operation)?;
+        match &self.record {
+            Record::Author(author) => write!(
+                f,
+                "author {} {} ({})",
+                author.id, author.name, author.country
+            ),
+            Record::Book(book) => write!(
+                f,
+                "book {} {} (author {})",
+                book.id, book.title, book.author_id
+            ),
+        }
+    }
+}
+
+pub struct Subscriber {
+    config: Config,
+    /// 订阅的schema；为空时使用连接默认的schema，第一次连接之后确定
+    schema: Option<String>,
+    client: Option<Client>,
+    backend_pid: Option<i32>,
+    /// 最近收到的事件编号，用来去掉重复的事件
+    seen: BTreeSet<i64>,
+    /// 还在补齐时，下一页从这个编号之后开始
+    replay_after: Option<i64>,
+    replay_page: i64,
+}
+
+impl Subscriber {
+    /// 从现在开始订阅`schema`（为空时是连接默认的schema）中的修改，之前的修改不会收到
+    pub fn connect(config: Config, schema: Option<&str>) -> Result<Self, EventError> {
+        let mut subscriber = Subscriber::resume(config, schema, 0);
+        let mut client = subscriber.open()?;
+        let last: Option<i64> = client
+            .query_one("SELECT max(id) FROM change_log", &[])?
+            .get(0);
+        // 第一次`poll`时才LISTEN，这之间的修改从`change_log`补齐
+        subscriber.seen = BTreeSet::from([last.unwrap_or(0)]);
+        Ok(subscriber)
+    }
+
+    /// 从`change_log`中编号`after`之后的修改开始订阅，第一次`poll`时补齐
+    pub fn resume(config: Config, schema: Option<&str>, after: i64) -> Self {
+        Subscriber {
+            config,
+            schema: schema.map(str::to_string),
+            client: None,
+            backend_pid: None,
+            seen: BTreeSet::from([after]),
+            replay_after: None,
+            replay_page: REPLAY_PAGE,
+        }
+    }
+
+    /// 每次`poll`最多补齐的修改数量
+    pub fn replay_page(mut self, page: i64) -> Self {
+        self.replay_page = page.max(1);
+        self
+    }
+
+    /// 当前连接的服务器进程
+    pub fn backend_pid(&self) -> Option<i32> {
+        self.backend_pid
+    }
+
+    /// 最后收到的事件编号，保存下来之后可以用`resume`继续订阅
+    pub fn last_id(&self) -> i64 {
+        self.seen.iter().next_back().copied().unwrap_or(0)
+    }
+
+    /// 连接并切换到订阅的schema；没有指定schema时记下连接默认的schema
+    fn open(&mut self) -> Result<Client, EventError> {
+        let mut client = self.config.connect()?;
+        match &self.schema {
+            Some(schema) => client.batch_execute(&format!("SET search_path TO {}", schema))?,
+            None => {
+                let row = client.query_one("SELECT coalesce(current_schema(), '')", &[])?;
+                self.schema = Some(row.get(0));
+            }
+        }
+        Ok(client)
+    }
+
+    fn listen(&mut self) -> Result<&mut Client, EventError> {
+        let mut client = self.open()?;
+        client.batch_execute(&format!("LISTEN {}", CHANNEL))?;
+        self.backend_pid = Some(client.query_one("SELECT pg_backend_pid()", &[])?.get(0));
+        Ok(self.client.insert(client))
+    }
+
+    /// 重新连接，先`LISTEN`再从`change_log`补齐，两者之间的修改不会丢失
+    fn reconnect(&mut self) -> Result<(), EventError> {
+        let after = self.seen.iter().next().copied().unwrap_or(0);
+        self.listen()?;
+        self.replay_after = Some(after);
+        Ok(())
+    }
+
+    /// 读取`change_log`中编号`after`之后的一页，不满一页时补齐结束
+    fn replay(&mut self, after: i64, events: &mut Vec<Event>) -> Result<(), EventError> {
+        let client = self.client.as_mut().expect("已经连接");
+        let rows = client.query(
+            "SELECT c.id, change_payload(c) FROM change_log c WHERE c.id > $1 ORDER BY c.id LIMIT $2",
+            &[&after, &self.replay_page],
+        )?;
+        let page = rows
+            .iter()
+            .map(|row| Event::parse(row.get(1)))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.replay_after = match rows.last() {
+            Some(last) if rows.len() as i64 == self.replay_page => Some(last.get(0)),
+            _ => None,
+        };
+        for event in page {
+            self.deliver(event, events);
+        }
+        Ok(())
+    }
+
+    fn deliver(&mut self, event: Event, events: &mut Vec<Event>) {
+        if self.seen.insert(event.id) {
+            events.push(event);
+            while self.seen.len() > REMEMBERED {
+                self.seen.pop_first();
+            }
+        }
+    }
+
+    /// 等待最多`timeout`，返回这段时间内的修改；连接断开时重新连接并补齐错过的修改，
+    /// 补齐期间不等待，每次返回一页
+    pub fn poll(&mut self, timeout: Duration) -> Result<Vec<Event>, EventError> {
+        let mut events = vec![];
+        if self.client.as_ref().is_none_or(Client::is_closed) {
+            self.client = None;
+            self.reconnect()?;
+        }
+        if let Some(after) = self.replay_after {
+            // 补齐期间到达的通知留在连接中，补齐结束之后作为重复的事件去掉
+            self.replay(after, &mut events)?;
+            return Ok(events);
+        }
+
+        let mut payloads: Vec<String> = vec![];
+        let received = {
+            let mut notifications = self.client.as_mut().expect("已经连接").notifications();
+            let first = notifications.timeout_iter(timeout).next();
+            first.and_then(|first| {
+                payloads.extend(first.map(|n| n.payload().to_string()));
+                // 第一个通知之后，取出所有已经到达的通知
+                let mut rest = notifications.iter();
+                while let Some(n) = rest.next()? {
+                    payloads.push(n.payload().to_string());
+                }
+                Ok(())
+            })
+        };
+        if received.is_err() {
+            // 连接断开：马上重新连接，服务器还不可用时下一次`poll`再试；
+            // 从下一次`poll`开始补齐，已经收到的通知到时会作为重复的事件去掉
+            self.client = None;
+            self.reconnect()?;
+        }
+
+        for payload in payloads {
+            let payload: Payload = serde_json::from_str(&payload)?;
+            // 其他schema的修改，它们的编号和这个schema的change_log无关
+            if payload.schema != self.schema {
+                continue;
+            }
+            let event = match Event::from_payload(payload) {
+                Err(EventError::Truncated { id }) => {
+                    let client = self.client.as_mut().expect("已经连接");
+                    let row = client.query_one(
+                        "SELECT change_payload(c) FROM change_log c WHERE c.id = $1",
+                        &[&id],
+                    )?;
+                    Event::parse(row.get(0))?
+                }
+                result => result?,
+            };
+            self.deliver(event, &mut events);
+        }
+
+        Ok(events)
+    }
+}
+
+/// `change_log`的保留规则
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retention {
+    /// 删除编号不超过这个值的记录，所有订阅者的`last_id`都已经到了这里
+    Acknowledged(i64),
+    /// 删除早于这个时间的记录
+    OlderThan(Duration),
+}
+
+/// 按`retention`删除`change_log`中的记录，返回删除的行数
+pub fn prune_change_log<C: GenericClient>(
+    client: &mut C,
+    retention: Retention,
+) -> Result<u64, EventError> {
+    let deleted = match retention {
+        Retention::Acknowledged(id) => {
+            client.execute("DELETE FROM change_log WHERE id <= $1", &[&id])?
+        }
+        Retention::OlderThan(age) => client.execute(
+            "DELETE FROM change_log WHERE changed_at < now() - make_interval(secs => $1)",
+            &[&age.as_secs_f64()],
+        )?,
+    };
+    Ok(deleted)
+}
+
+/// 等待收到`count`个事件，最多等待`tries`次
+fn wait_for(
+    subscriber: &mut Subscriber,
+    count: usize,
+    tries: u32,
+) -> Result<Vec<Event>, EventError> {
+    let mut events = vec![];
+    for _ in 0..tries {
+        events.extend(subscriber.poll(Duration::from_millis(500))?);
+        if events.len() >= count {
+            break;
+        }
+    }
+    for event in &events {
+        println!("{}", event);
+    }
+    Ok(events)
+}
+
+fn summary(events: &[Event]) -> Vec<(Operation, &str)> {
+    events
+        .iter()
+        .map(|event| match &event.record {
+            Record::Author(author) => (event.operation, author.name.as_str()),
+            Record::Book(book) => (event.operation, book.title.as_str()),
+        })
+        .collect()
+}
+
+/// # 订阅作者和图书的修改
+/// 修改数据之后收到对应的事件；断开订阅者的连接，断开期间的修改在重新连接后补齐。
+/// 在临时的schema中进行，最后删除这个schema，不影响`public`中的数据和它的订阅者。
+pub fn watch_changes(config: &Config) -> Result<(), Box<dyn Error>> {
+    let schema = format!("events_demo_{}", std::process::id());
+    let other = format!("events_other_{}", std::process::id());
+    let mut writer = migrations::connect_to_schema(config, &schema)?;
+    migrations::library().up(&mut writer, None)?;
+    let mut elsewhere = migrations::connect_to_schema(config, &other)?;
+    migrations::library().up(&mut elsewhere, None)?;
+
+    let result = watch_schema(config, &schema, &mut writer, &mut elsewhere);
+    writer.batch_execute(&format!(
+        "DROP SCHEMA {} CASCADE; DROP SCHEMA {} CASCADE",
+        schema, other
+    ))?;
+    result?;
+    Ok(())
+}
+
+fn watch_schema(
+    config: &Config,
+    schema: &str,
+    writer: &mut Client,
+    elsewhere: &mut Client,
+) -> Result<(), EventError> {
+    let mut subscriber = Subscriber::connect(config.clone(), Some(schema))?;
+    let start = subscriber.last_id();
+
+    // 另一个schema中的修改在同一个频道通知，订阅者不会收到
+    Library::new(elsewhere)?.add_book("Other Horizons", "Other Author", "Elsewhere")?;
+    let mut library = Library::new(writer)?;
+    let book = library.add_book("Event Horizons", "Event Author", "Eventland")?;
+    let events = wait_for(&mut subscriber, 2, 10)?;
+    assert_eq!(
+        summary(&events),
+        [
+            (Operation::Insert, "Event Author"),
+            (Operation::Insert, "Event Horizons")
+        ]
+    );
+
+    library.rename_book(book.id, "Event Horizons II")?;
+    let events = wait_for(&mut subscriber, 1, 10)?;
+    assert_eq!(summary(&events), [(Operation::Update, "Event Horizons II")]);
+
+    // 重复执行的upsert没有改变任何值，不产生事件；紧接着的修改是收到的第一个事件
+    library.add_book("Event Horizons II", "Event Author", "Eventland")?;
+    library.upsert_author("Event Author", "Eventland")?;
+    library.upsert_author("Event Author", "Event Republic")?;
+    let events = wait_for(&mut subscriber, 1, 10)?;
+    assert_eq!(summary(&events), [(Operation::Update, "Event Author")]);
+    drop(library);
+
+    // 服务器断开订阅者的连接，断开期间删除图书和作者
+    let pid = subscriber.backend_pid().expect("已经连接");
+    writer.execute("SELECT pg_terminate_backend($1)", &[&pid])?;
+    let mut library = Library::new(writer)?;
+    library.delete_book(book.id)?;
+    library.delete_author(book.author_id)?;
+
+    let events = wait_for(&mut subscriber, 2, 10)?;
+    assert_ne!(subscriber.backend_pid(), Some(pid));
+    assert_eq!(
+        summary(&events),
+        [
+            (Operation::Delete, "Event Horizons II"),
+            (Operation::Delete, "Event Author")
+        ]
+    );
+    assert_eq!(subscriber.last_id(), events[1].id);
+
+    // 清理已经处理过的记录；演示在回滚的事务中进行，不影响其他订阅者
+    let mut tx = writer.transaction()?;
+    let pruned = prune_change_log(&mut tx, Retention::Acknowledged(subscriber.last_id()))?;
+    assert!(pruned >= 6);
+    let left: i64 = tx
+        .query_one(
+            "SELECT count(*) FROM change_log WHERE id <= $1",
+            &[&subscriber.last_id()],
+        )?
+        .get(0);
+    assert_eq!(left, 0);
+    prune_change_log(&mut tx, Retention::OlderThan(Duration::from_secs(0)))?;
+    tx.rollback()?;
+
+    // 从头重放这次演示的修改，每页4个，分两次补齐
+    let mut late = Subscriber::resume(config.clone(), Some(schema), start).replay_page(4);
+    let first = late.poll(Duration::from_millis(10))?;
+    let second = late.poll(Duration::from_millis(10))?;
+    assert_eq!((first.len(), second.len()), (4, 2));
+    assert_eq!(late.last_id(), subscriber.last_id());
+
+    Ok(())
+}
@@ -0,0 +1,10 @@
+//! # Postgres
+//! `src/main.rs`中的例子共享的模块。
+
+pub mod async_library;
+pub mod bulk;
+pub mod config;
+pub mod events;
+pub mod library;
+pub mod migrations;
+pub mod test_server;
@@ -0,0 +1,469 @@
+//! # 图书馆的数据访问
+//! `author`和`book`两张表的增删改查：
+//! - 作者按名字唯一，重复执行插入只会更新国家，不会产生重复的作者；
+//! - 按作者、按国家查询图书，按作者和国家统计数量；
+//! - 删除还有图书的作者、把图书移到不存在的作者名下时返回明确的错误；
+//! - 所有语句在`Library::new`时准备一次，之后的调用重复使用。
+//!
+//! 表和索引由`migrations`模块创建，`Library`可以建立在`Client`或者`Transaction`上。
+use postgres::error::SqlState;
+use postgres::{Client, GenericClient, Row, Statement};
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum LibraryError {
+    Postgres(postgres::Error),
+    NotFound {
+        what: &'static str,
+        id: i32,
+    },
+    /// 作者还有图书，不能删除
+    HasBooks {
+        author_id: i32,
+        books: i64,
+    },
+}
+
+impl fmt::Display for LibraryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LibraryError::Postgres(err) => write!(f, "数据库错误：{}", err),
+            LibraryError::NotFound { what, id } => write!(f, "{}{}不存在", what, id),
+            LibraryError::HasBooks { author_id, books } => {
+                write!(f, "作者{}还有{}本书，不能删除", author_id, books)
+            }
+        }
+    }
+}
+
+impl Error for LibraryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LibraryError::Postgres(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<postgres::Error> for LibraryError {
+    fn from(err: postgres::Error) -> Self {
+        LibraryError::Postgres(err)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Author {
+    pub id: i32,
+    pub name: String,
+    pub country: String,
+}
+
+impl From<&Row> for Author {
+    fn from(row: &Row) -> Self {
+        Author {
+            id: row.get("id"),
+            name: row.get("name"),
+            country: row.get("country"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Book {
+    pub id: i32,
+    pub title: String,
+    pub author_id: i32,
+}
+
+impl From<&Row> for Book {
+    fn from(row: &Row) -> Self {
+        Book {
+            id: row.get("id"),
+            title: row.get("title"),
+            author_id: row.get("author_id"),
+        }
+    }
+}
+
+/// 图书以及作者的名字和国家
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BookWithAuthor {
+    pub book: Book,
+    pub author: String,
+    pub country: String,
+}
+
+impl From<&Row> for BookWithAuthor {
+    fn from(row: &Row) -> Self {
+        BookWithAuthor {
+            book: Book::from(row),
+            author: row.get("author"),
+            country: row.get("country"),
+        }
+    }
+}
+
+/// 某个国家的作者和图书数量
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountryStats {
+    pub country: String,
+    pub authors: i64,
+    pub books: i64,
+}
+
+impl From<&Row> for CountryStats {
+    fn from(row: &Row) -> Self {
+        CountryStats {
+            country: row.get(0),
+            authors: row.get(1),
+            books: row.get(2),
+        }
+    }
+}
+
+/// `count_by_author`的一行：作者的名字和图书数量
+pub(crate) fn author_count(row: &Row) -> (String, i64) {
+    (row.get(0), row.get(1))
+}
+
+/// `Library`和`async_library::AsyncLibrary`共用的SQL
+pub(crate) mod sql {
+    pub const UPSERT_AUTHOR: &str = "INSERT INTO author (name, country) VALUES ($1, $2)
+        ON CONFLICT (name) DO UPDATE SET country = excluded.country
+        RETURNING id, name, country";
+    pub const FIND_AUTHOR: &str = "SELECT id, name, country FROM author WHERE id = $1";
+    pub const UPDATE_AUTHOR: &str = "UPDATE author SET name = $2, country = $3 WHERE id = $1";
+    pub const DELETE_AUTHOR: &str = "DELETE FROM author WHERE id = $1";
+    pub const COUNT_AUTHOR_BOOKS: &str = "SELECT count(*) FROM book WHERE author_id = $1";
+    pub const UPSERT_BOOK: &str = "INSERT INTO book (title, author_id) VALUES ($1, $2)
+        ON CONFLICT (author_id, title) DO UPDATE SET title = excluded.title
+        RETURNING id, title, author_id";
+    pub const BOOKS_BY_AUTHOR: &str =
+        "SELECT b.id, b.title, b.author_id, a.name AS author, a.country
+        FROM book b JOIN author a ON a.id = b.author_id
+        WHERE a.name = $1 ORDER BY b.title";
+    pub const BOOKS_BY_COUNTRY: &str =
+        "SELECT b.id, b.title, b.author_id, a.name AS author, a.country
+        FROM book b JOIN author a ON a.id = b.author_id
+        WHERE a.country = $1 ORDER BY a.name, b.title";
+    pub const MOVE_BOOK: &str = "UPDATE book SET author_id = $2 WHERE id = $1";
+    pub const RENAME_BOOK: &str = "UPDATE book SET title = $2 WHERE id = $1";
+    pub const DELETE_BOOK: &str = "DELETE FROM book WHERE id = $1";
+    pub const COUNT_BY_AUTHOR: &str =
+        "SELECT a.name, count(b.id) FROM author a LEFT JOIN book b ON b.author_id = a.id
+        GROUP BY a.id, a.name ORDER BY count(b.id) DESC, a.name";
+    pub const COUNT_BY_COUNTRY: &str = "SELECT a.country, count(DISTINCT a.id), count(b.id)
+        FROM author a LEFT JOIN book b ON b.author_id = a.id
+        GROUP BY a.country ORDER BY a.country";
+
+    /// 所有语句，顺序和`Statements`的字段相同
+    pub const ALL: [&str; 13] = [
+        UPSERT_AUTHOR,
+        FIND_AUTHOR,
+        UPDATE_AUTHOR,
+        DELETE_AUTHOR,
+        COUNT_AUTHOR_BOOKS,
+        UPSERT_BOOK,
+        BOOKS_BY_AUTHOR,
+        BOOKS_BY_COUNTRY,
+        MOVE_BOOK,
+        RENAME_BOOK,
+        DELETE_BOOK,
+        COUNT_BY_AUTHOR,
+        COUNT_BY_COUNTRY,
+    ];
+}
+
+/// 准备好的语句，`Library`和`AsyncLibrary`共用
+pub(crate) struct Statements {
+    pub(crate) upsert_author: Statement,
+    pub(crate) find_author: Statement,
+    pub(crate) update_author: Statement,
+    pub(crate) delete_author: Statement,
+    pub(crate) count_author_books: Statement,
+    pub(crate) upsert_book: Statement,
+    pub(crate) books_by_author: Statement,
+    pub(crate) books_by_country: Statement,
+    pub(crate) move_book: Statement,
+    pub(crate) rename_book: Statement,
+    pub(crate) delete_book: Statement,
+    pub(crate) count_by_author: Statement,
+    pub(crate) count_by_country: Statement,
+}
+
+impl Statements {
+    /// `prepared`按`sql::ALL`的顺序排列
+    pub(crate) fn from_prepared(prepared: Vec<Statement>) -> Self {
+        let [upsert_author, find_author, update_author, delete_author, count_author_books, upsert_book, books_by_author, books_by_country, move_book, rename_book, delete_book, count_by_author, count_by_country]: [Statement; 13] =
+            prepared.try_into().expect("每条SQL对应一个语句");
+        Statements {
+            upsert_author,
+            find_author,
+            update_author,
+            delete_author,
+            count_author_books,
+            upsert_book,
+            books_by_author,
+            books_by_country,
+            move_book,
+            rename_book,
+            delete_book,
+            count_by_author,
+            count_by_country,
+        }
+    }
+}
+
+/// 修改的行数为0时返回`NotFound`
+pub(crate) fn changed(what: &'static str, id: i32, rows: u64) -> Result<(), LibraryError> {
+    match rows {
+        0 => Err(LibraryError::NotFound { what, id }),
+        _ => Ok(()),
+    }
+}
+
+/// 删除作者之前的检查：还有图书时返回`HasBooks`
+pub(crate) fn check_no_books(author_id: i32, books: i64) -> Result<(), LibraryError> {
+    match books {
+        0 => Ok(()),
+        books => Err(LibraryError::HasBooks { author_id, books }),
+    }
+}
+
+/// `DELETE_AUTHOR`的结果；检查之后另一个连接插入了这个作者的书时违反外键
+pub(crate) fn author_deleted(
+    id: i32,
+    result: Result<u64, postgres::Error>,
+) -> Result<(), LibraryError> {
+    match result {
+        Err(err) if err.code() == Some(&SqlState::FOREIGN_KEY_VIOLATION) => {
+            Err(LibraryError::HasBooks {
+                author_id: id,
+                books: 1,
+            })
+        }
+        result => changed("作者", id, result?),
+    }
+}
+
+/// `MOVE_BOOK`的结果；作者在检查之后被删除时违反外键
+pub(crate) fn book_moved(
+    id: i32,
+    author_id: i32,
+    result: Result<u64, postgres::Error>,
+) -> Result<(), LibraryError> {
+    match result {
+        Err(err) if err.code() == Some(&SqlState::FOREIGN_KEY_VIOLATION) => {
+            Err(LibraryError::NotFound {
+                what: "作者",
+                id: author_id,
+            })
+        }
+        result => changed("图书", id, result?),
+    }
+}
+
+pub struct Library<'c, C: GenericClient> {
+    client: &'c mut C,
+    statements: Statements,
+}
+
+impl<'c, C: GenericClient> Library<'c, C> {
+    pub fn new(client: &'c mut C) -> Result<Self, postgres::Error> {
+        let prepared = sql::ALL
+            .iter()
+            .map(|sql| client.prepare(sql))
+            .collect::<Result<Vec<_>, _>>()?;
+        let statements = Statements::from_prepared(prepared);
+        Ok(Library { client, statements })
+    }
+
+    /// 插入作者，同名的作者已经存在时更新国家
+    pub fn upsert_author(&mut self, name: &str, country: &str) -> Result<Author, LibraryError> {
+        let row = self
+            .client
+            .query_one(&self.statements.upsert_author, &[&name, &country])?;
+        Ok(Author::from(&row))
+    }
+
+    pub fn find_author(&mut self, id: i32) -> Result<Option<Author>, LibraryError> {
+        let row = self
+            .client
+            .query_opt(&self.statements.find_author, &[&id])?;
+        Ok(row.as_ref().map(Author::from))
+    }
+
+    pub fn update_author(&mut self, author: &Author) -> Result<(), LibraryError> {
+        let rows = self.client.execute(
+            &self.statements.update_author,
+            &[&author.id, &author.name, &author.country],
+        )?;
+        changed("作者", author.id, rows)
+    }
+
+    /// 删除没有图书的作者
+    pub fn delete_author(&mut self, id: i32) -> Result<(), LibraryError> {
+        let books: i64 = self
+            .client
+            .query_one(&self.statements.count_author_books, &[&id])?
+            .get(0);
+        check_no_books(id, books)?;
+        author_deleted(
+            id,
+            self.client.execute(&self.statements.delete_author, &[&id]),
+        )
+    }
+
+    /// 在同一个事务中插入作者和图书，重复执行不会产生重复的数据
+    pub fn add_book(
+        &mut self,
+        title: &str,
+        author: &str,
+        country: &str,
+    ) -> Result<Book, LibraryError> {
+        let mut tx = self.client.transaction()?;
+        let author: i32 = tx
+            .query_one(&self.statements.upsert_author, &[&author, &country])?
+            .get("id");
+        let row = tx.query_one(&self.statements.upsert_book, &[&title, &author])?;
+        tx.commit()?;
+        Ok(Book::from(&row))
+    }
+
+    pub fn books_by_author(&mut self, author: &str) -> Result<Vec<BookWithAuthor>, LibraryError> {
+        let rows = self
+            .client
+            .query(&self.statements.books_by_author, &[&author])?;
+        Ok(rows.iter().map(BookWithAuthor::from).collect())
+    }
+
+    pub fn books_by_country(&mut self, country: &str) -> Result<Vec<BookWithAuthor>, LibraryError> {
+        let rows = self
+            .client
+            .query(&self.statements.books_by_country, &[&country])?;
+        Ok(rows.iter().map(BookWithAuthor::from).collect())
+    }
+
+    pub fn rename_book(&mut self, id: i32, title: &str) -> Result<(), LibraryError> {
+        let rows = self
+            .client
+            .execute(&self.statements.rename_book, &[&id, &title])?;
+        changed("图书", id, rows)
+    }
+
+    /// 把图书移到另一个作者名下，作者不存在时返回`NotFound`
+    pub fn move_book(&mut self, id: i32, author_id: i32) -> Result<(), LibraryError> {
+        // 先检查作者，外键错误会让所在的事务无法继续
+        if self.find_author(author_id)?.is_none() {
+            return Err(LibraryError::NotFound {
+                what: "作者",
+                id: author_id,
+            });
+        }
+        let result = self
+            .client
+            .execute(&self.statements.move_book, &[&id, &author_id]);
+        book_moved(id, author_id, result)
+    }
+
+    pub fn delete_book(&mut self, id: i32) -> Result<(), LibraryError> {
+        let rows = self.client.execute(&self.statements.delete_book, &[&id])?;
+        changed("图书", id, rows)
+    }
+
+    /// 每个作者的图书数量，包括没有图书的作者
+    pub fn count_by_author(&mut self) -> Result<Vec<(String, i64)>, LibraryError> {
+        let rows = self.client.query(&self.statements.count_by_author, &[])?;
+        Ok(rows.iter().map(author_count).collect())
+    }
+
+    pub fn count_by_country(&mut self) -> Result<Vec<CountryStats>, LibraryError> {
+        let rows = self.client.query(&self.statements.count_by_country, &[])?;
+        Ok(rows.iter().map(CountryStats::from).collect())
+    }
+}
+
+/// # 管理图书馆的作者和图书
+/// 所有修改都在一个最后回滚的事务中进行，不影响数据库中已有的数据。
+pub fn manage_library(client: &mut Client) -> Result<(), LibraryError> {
+    let mut tx = client.transaction()?;
+    let mut library = Library::new(&mut tx)?;
+
+    let country = "Atlantis";
+    let tide = library.add_book("Tide Tables", "Test Author A", country)?;
+    library.add_book("Salt and Coral", "Test Author A", country)?;
+    library.add_book("Deep Currents", "Test Author B", country)?;
+    // 重复执行不会产生重复的作者和图书
+    let again = library.add_book("Tide Tables", "Test Author A", country)?;
+    assert_eq!(again, tide);
+    let empty = library.upsert_author("Test Author C", country)?;
+    assert_eq!(
+        library.upsert_author("Test Author C", country)?.id,
+        empty.id
+    );
+
+    let titles = |books: Vec<BookWithAuthor>| {
+        books
+            .into_iter()
+            .map(|book| book.book.title)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        titles(library.books_by_author("Test Author A")?),
+        ["Salt and Coral", "Tide Tables"]
+    );
+    assert_eq!(
+        titles(library.books_by_country(country)?),
+        ["Salt and Coral", "Tide Tables", "Deep Currents"]
+    );
+
+    let stats = library.count_by_country()?;
+    let atlantis = stats.iter().find(|stats| stats.country == country).unwrap();
+    assert_eq!((atlantis.authors, atlantis.books), (3, 3));
+    let counts = library.count_by_author()?;
+    assert!(counts.contains(&("Test Author A".to_string(), 2)));
+    assert!(counts.contains(&("Test Author C".to_string(), 0)));
+
+    // 有图书的作者不能删除，不存在的作者不能接收图书
+    match library.delete_author(tide.author_id) {
+        Err(err @ LibraryError::HasBooks { books: 2, .. }) => println!("{}", err),
+        other => panic!("应该拒绝删除：{:?}", other),
+    }
+    match library.move_book(tide.id, -1) {
+        Err(err @ LibraryError::NotFound { what: "作者", .. }) => println!("{}", err),
+        other => panic!("作者不存在：{:?}", other),
+    }
+
+    library.move_book(tide.id, empty.id)?;
+    library.rename_book(tide.id, "Tide Tables (2nd ed.)")?;
+    assert_eq!(
+        titles(library.books_by_author("Test Author C")?),
+        ["Tide Tables (2nd ed.)"]
+    );
+    library.update_author(&Author {
+        country: "Lemuria".to_string(),
+        ..empty.clone()
+    })?;
+    assert_eq!(
+        library.find_author(empty.id)?.map(|author| author.country),
+        Some("Lemuria".to_string())
+    );
+
+    library.delete_book(tide.id)?;
+    library.delete_author(empty.id)?;
+    assert!(library.find_author(empty.id)?.is_none());
+    assert!(matches!(
+        library.delete_book(tide.id),
+        Err(LibraryError::NotFound { what: "图书", .. })
+    ));
+
+    for stats in library.count_by_country()? {
+        println!("{:?}", stats);
+    }
+    // 丢弃事务即回滚
+    drop(library);
+    tx.rollback()?;
+    Ok(())
+}
@@ -0,0 +1,550 @@
+//! # Postgres数据库迁移
+//! 和`sqlite`的迁移相同，按版本号顺序执行`migrations`目录中的`0001_name.up.sql`，
+//! 执行过的迁移和它的校验和记录在`schema_migrations`表中。另外：
+//! - 执行前取得`pg_advisory_lock`，同时部署的多个进程依次执行，后面的进程看到迁移已经完成就跳过；
+//! - Postgres的DDL是事务性的，每个迁移和它的记录在同一个事务中提交，失败时不留下半个迁移；
+//! - `plan`列出将要执行的迁移，`dry_run`只打印它们的SQL；`status`、`plan`和`dry_run`都不修改数据库，
+//!   `schema_migrations`表在第一次`up`或者`down`时才创建。
+use crate::config::Config;
+use postgres::Client;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::thread;
+use std::time::Instant;
+
+/// 所有迁移共用的advisory lock的键
+const LOCK_KEY: i64 = 0x6462_706f_7374_6772;
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Postgres(postgres::Error),
+    Io(io::Error),
+    /// 已经执行的迁移内容被修改了
+    ChecksumMismatch {
+        version: i32,
+        name: String,
+    },
+    /// 迁移没有`down`步骤，不能回滚
+    Irreversible {
+        version: i32,
+        name: String,
+    },
+    /// 某个版本的迁移执行失败
+    Failed {
+        version: i32,
+        name: String,
+        source: postgres::Error,
+    },
+    Invalid(String),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Postgres(err) => write!(f, "数据库错误：{}", err),
+            MigrationError::Io(err) => write!(f, "读取迁移文件错误：{}", err),
+            MigrationError::ChecksumMismatch { version, name } => {
+                write!(f, "迁移{:04}_{}在执行后被修改过", version, name)
+            }
+            MigrationError::Irreversible { version, name } => {
+                write!(f, "迁移{:04}_{}没有down步骤", version, name)
+            }
+            MigrationError::Failed {
+                version,
+                name,
+                source,
+            } => {
+                write!(f, "迁移{:04}_{}执行失败：{}", version, name, source)?;
+                match source.as_db_error() {
+                    Some(db) => write!(f, "：{}", db.message()),
+                    None => Ok(()),
+                }
+            }
+            MigrationError::Invalid(message) => write!(f, "无效的迁移：{}", message),
+        }
+    }
+}
+
+impl Error for MigrationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MigrationError::Postgres(err) | MigrationError::Failed { source: err, .. } => Some(err),
+            MigrationError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<postgres::Error> for MigrationError {
+    fn from(err: postgres::Error) -> Self {
+        MigrationError::Postgres(err)
+    }
+}
+
+impl From<io::Error> for MigrationError {
+    fn from(err: io::Error) -> Self {
+        MigrationError::Io(err)
+    }
+}
+
+pub struct Migration {
+    pub version: i32,
+    pub name: String,
+    pub up: String,
+    pub down: Option<String>,
+}
+
+impl Migration {
+    pub fn new(version: i32, name: &str, up: &str, down: Option<&str>) -> Self {
+        Migration {
+            version,
+            name: name.to_string(),
+            up: up.to_string(),
+            down: down.map(str::to_string),
+        }
+    }
+
+    /// `up`的FNV-1a校验和，用16位十六进制表示
+    pub fn checksum(&self) -> String {
+        let hash = self
+            .up
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+            });
+        format!("{:016x}", hash)
+    }
+}
+
+/// 迁移的状态
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum State {
+    Applied,
+    Pending,
+    /// 已经执行，但是内容和记录的校验和不一致
+    Modified,
+    /// 数据库中有记录，但是迁移列表中已经没有这个版本
+    Missing,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationStatus {
+    pub version: i32,
+    pub name: String,
+    pub state: State,
+    pub applied_at: Option<String>,
+}
+
+impl fmt::Display for MigrationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}  {:<30}  {:<8}  {}",
+            self.version,
+            self.name,
+            format!("{:?}", self.state),
+            self.applied_at.as_deref().unwrap_or("")
+        )
+    }
+}
+
+/// 已经执行的迁移记录：版本、名字、校验和、执行时间
+type AppliedRow = (i32, String, String, String);
+
+pub struct Migrator {
+    migrations: Vec<Migration>,
+}
+
+impl Migrator {
+    /// 版本号必须大于0并且不能重复，迁移按版本号排序
+    pub fn new(mut migrations: Vec<Migration>) -> Result<Self, MigrationError> {
+        migrations.sort_by_key(|migration| migration.version);
+        if let Some(migration) = migrations.iter().find(|migration| migration.version <= 0) {
+            return Err(MigrationError::Invalid(format!(
+                "{}的版本号必须大于0",
+                migration.name
+            )));
+        }
+        if let Some(pair) = migrations
+            .windows(2)
+            .find(|pair| pair[0].version == pair[1].version)
+        {
+            return Err(MigrationError::Invalid(format!(
+                "版本号{}重复",
+                pair[0].version
+            )));
+        }
+        Ok(Migrator { migrations })
+    }
+
+    /// 从目录加载`0001_name.up.sql`和可选的`0001_name.down.sql`
+    pub fn from_dir(dir: &Path) -> Result<Self, MigrationError> {
+        let mut migrations = vec![];
+        let mut entries: Vec<_> = fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let stem = match file_name.strip_suffix(".up.sql") {
+                Some(stem) => stem,
+                None => continue,
+            };
+            let (version, name) = stem
+                .split_once('_')
+                .and_then(|(version, name)| Some((version.parse().ok()?, name)))
+                .ok_or_else(|| {
+                    MigrationError::Invalid(format!("文件名应该是0001_name.up.sql：{}", file_name))
+                })?;
+            let up = fs::read_to_string(entry.path())?;
+            let down_path = dir.join(format!("{}.down.sql", stem));
+            let down = match down_path.exists() {
+                true => Some(fs::read_to_string(down_path)?),
+                false => None,
+            };
+            migrations.push(Migration::new(version, name, &up, down.as_deref()));
+        }
+
+        Migrator::new(migrations)
+    }
+
+    /// 只在`up`和`down`中取得锁之后调用，`status`、`plan`和`dry_run`不修改数据库
+    fn create_table(client: &mut Client) -> Result<(), postgres::Error> {
+        client.batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version     INTEGER PRIMARY KEY,
+                name        VARCHAR NOT NULL,
+                checksum    VARCHAR NOT NULL,
+                applied_at  TIMESTAMPTZ NOT NULL DEFAULT now(),
+                duration_ms BIGINT NOT NULL
+            )",
+        )
+    }
+
+    /// 已经执行的迁移，还没有`schema_migrations`表时为空
+    fn applied(client: &mut Client) -> Result<Vec<AppliedRow>, postgres::Error> {
+        if !table_exists(client, "schema_migrations")? {
+            return Ok(vec![]);
+        }
+        let rows = client.query(
+            "SELECT version, name, checksum, to_char(applied_at, 'YYYY-MM-DD HH24:MI:SS')
+            FROM schema_migrations ORDER BY version",
+            &[],
+        )?;
+        Ok(rows
+            .iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2), row.get(3)))
+            .collect())
+    }
+
+    pub fn status(&self, client: &mut Client) -> Result<Vec<MigrationStatus>, MigrationError> {
+        let applied = Migrator::applied(client)?;
+        let mut statuses: Vec<MigrationStatus> = self
+            .migrations
+            .iter()
+            .map(|migration| {
+                let row = applied
+                    .iter()
+                    .find(|(version, ..)| *version == migration.version);
+                let state = match row {
+                    None => State::Pending,
+                    Some((_, _, checksum, _)) if *checksum != migration.checksum() => {
+                        State::Modified
+                    }
+                    Some(_) => State::Applied,
+                };
+                MigrationStatus {
+                    version: migration.version,
+                    name: migration.name.clone(),
+                    state,
+                    applied_at: row.map(|(.., applied_at)| applied_at.clone()),
+                }
+            })
+            .collect();
+
+        statuses.extend(
+            applied
+                .into_iter()
+                .filter(|(version, ..)| {
+                    !self
+                        .migrations
+                        .iter()
+                        .any(|migration| migration.version == *version)
+                })
+                .map(|(version, name, _, applied_at)| MigrationStatus {
+                    version,
+                    name,
+                    state: State::Missing,
+                    applied_at: Some(applied_at),
+                }),
+        );
+        statuses.sort_by_key(|status| status.version);
+        Ok(statuses)
+    }
+
+    /// 将要执行的迁移；有被修改的迁移时返回错误
+    pub fn plan(
+        &self,
+        client: &mut Client,
+        target: Option<i32>,
+    ) -> Result<Vec<&Migration>, MigrationError> {
+        let statuses = self.status(client)?;
+        if let Some(modified) = statuses
+            .iter()
+            .find(|status| status.state == State::Modified)
+        {
+            return Err(MigrationError::ChecksumMismatch {
+                version: modified.version,
+                name: modified.name.clone(),
+            });
+        }
+        Ok(self
+            .migrations
+            .iter()
+            .filter(|migration| {
+                statuses.iter().any(|status| {
+                    status.version == migration.version && status.state == State::Pending
+                })
+            })
+            .filter(|migration| target.is_none_or(|target| migration.version <= target))
+            .collect())
+    }
+
+    /// 打印将要执行的SQL，不修改数据库
+    pub fn dry_run<W: Write>(
+        &self,
+        client: &mut Client,
+        target: Option<i32>,
+        out: &mut W,
+    ) -> Result<Vec<i32>, MigrationError> {
+        let plan = self.plan(client, target)?;
+        for migration in &plan {
+            writeln!(out, "-- {:04}_{}", migration.version, migration.name)?;
+            writeln!(out, "{}", migration.up.trim_end())?;
+        }
+        Ok(plan.iter().map(|migration| migration.version).collect())
+    }
+
+    /// 在advisory lock中执行`f`，无论成功与否都释放锁
+    fn locked<T, F>(&self, client: &mut Client, f: F) -> Result<T, MigrationError>
+    where
+        F: FnOnce(&mut Client) -> Result<T, MigrationError>,
+    {
+        client.execute("SELECT pg_advisory_lock($1)", &[&LOCK_KEY])?;
+        let result = f(client);
+        client.execute("SELECT pg_advisory_unlock($1)", &[&LOCK_KEY])?;
+        result
+    }
+
+    /// 执行所有未执行的迁移，`target`不为空时只执行到这个版本，返回执行了的版本
+    pub fn up(&self, client: &mut Client, target: Option<i32>) -> Result<Vec<i32>, MigrationError> {
+        self.locked(client, |client| {
+            // 取得锁之后再计算计划，等待期间其他进程可能已经执行了迁移
+            Migrator::create_table(client)?;
+            let mut done = vec![];
+            for migration in self.plan(client, target)? {
+                let failed = |source| MigrationError::Failed {
+                    version: migration.version,
+                    name: migration.name.clone(),
+                    source,
+                };
+                let started = Instant::now();
+                let mut tx = client.transaction()?;
+                tx.batch_execute(&migration.up).map_err(failed)?;
+                tx.execute(
+                    "INSERT INTO schema_migrations (version, name, checksum, duration_ms)
+                    VALUES ($1, $2, $3, $4)",
+                    &[
+                        &migration.version,
+                        &migration.name,
+                        &migration.checksum(),
+                        &(started.elapsed().as_millis() as i64),
+                    ],
+                )?;
+                tx.commit()?;
+                done.push(migration.version);
+            }
+            Ok(done)
+        })
+    }
+
+    /// 按版本从新到旧回滚`steps`个已经执行的迁移，返回回滚了的版本
+    pub fn down(&self, client: &mut Client, steps: usize) -> Result<Vec<i32>, MigrationError> {
+        self.locked(client, |client| {
+            Migrator::create_table(client)?;
+            let applied = Migrator::applied(client)?;
+            let mut done = vec![];
+
+            for (version, name, ..) in applied.iter().rev().take(steps) {
+                let down = self
+                    .migrations
+                    .iter()
+                    .find(|migration| migration.version == *version)
+                    .and_then(|migration| migration.down.as_ref())
+                    .ok_or_else(|| MigrationError::Irreversible {
+                        version: *version,
+                        name: name.clone(),
+                    })?;
+
+                let mut tx = client.transaction()?;
+                tx.batch_execute(down)
+                    .map_err(|source| MigrationError::Failed {
+                        version: *version,
+                        name: name.clone(),
+                        source,
+                    })?;
+                tx.execute(
+                    "DELETE FROM schema_migrations WHERE version = $1",
+                    &[version],
+                )?;
+                tx.commit()?;
+                done.push(*version);
+            }
+            Ok(done)
+        })
+    }
+}
+
+/// `library`数据库的迁移，内容来自`dbpostgres/migrations`目录
+pub fn library() -> Migrator {
+    Migrator::new(vec![
+        Migration::new(
+            1,
+            "create_author",
+            include_str!("../migrations/0001_create_author.up.sql"),
+            Some(include_str!("../migrations/0001_create_author.down.sql")),
+        ),
+        Migration::new(
+            2,
+            "create_book",
+            include_str!("../migrations/0002_create_book.up.sql"),
+            Some(include_str!("../migrations/0002_create_book.down.sql")),
+        ),
+        Migration::new(
+            3,
+            "unique_author_and_title",
+            include_str!("../migrations/0003_unique_author_and_title.up.sql"),
+            Some(include_str!(
+                "../migrations/0003_unique_author_and_title.down.sql"
+            )),
+        ),
+        Migration::new(
+            4,
+            "change_log",
+            include_str!("../migrations/0004_change_log.up.sql"),
+            Some(include_str!("../migrations/0004_change_log.down.sql")),
+        ),
+        Migration::new(
+            5,
+            "skip_unchanged_updates",
+            include_str!("../migrations/0005_skip_unchanged_updates.up.sql"),
+            Some(include_str!(
+                "../migrations/0005_skip_unchanged_updates.down.sql"
+            )),
+        ),
+        Migration::new(
+            6,
+            "notify_schema",
+            include_str!("../migrations/0006_notify_schema.up.sql"),
+            Some(include_str!("../migrations/0006_notify_schema.down.sql")),
+        ),
+    ])
+    .expect("内置的迁移版本号不重复")
+}
+
+fn table_exists(client: &mut Client, table: &str) -> Result<bool, postgres::Error> {
+    let row = client.query_one("SELECT to_regclass($1) IS NOT NULL", &[&table])?;
+    Ok(row.get(0))
+}
+
+/// 连接到一个单独的schema，迁移和批量导入的演示不影响`public`中的表
+pub(crate) fn connect_to_schema(config: &Config, schema: &str) -> Result<Client, Box<dyn Error>> {
+    let mut client = config.connect()?;
+    client.batch_execute(&format!(
+        "CREATE SCHEMA IF NOT EXISTS {0}; SET search_path TO {0}",
+        schema
+    ))?;
+    Ok(client)
+}
+
+/// # 带advisory lock的Postgres迁移
+/// 在临时的schema中演示并发执行、dry-run、失败回滚、校验和检查和回滚迁移，最后删除这个schema。
+pub fn run_migrations(config: &Config) -> Result<(), Box<dyn Error>> {
+    let schema = format!("migration_demo_{}", std::process::id());
+    let mut client = connect_to_schema(config, &schema)?;
+
+    // 只打印SQL，不修改数据库
+    let mut sql = vec![];
+    let planned = library().dry_run(&mut client, Some(2), &mut sql)?;
+    let sql = String::from_utf8(sql)?;
+    println!("{}", sql);
+    assert_eq!(planned, [1, 2]);
+    assert!(sql.contains("-- 0002_create_book\nCREATE TABLE IF NOT EXISTS book"));
+    assert!(!table_exists(&mut client, "author")?);
+    assert!(!table_exists(&mut client, "schema_migrations")?);
+
+    // 4个进程同时部署：advisory lock保证每个迁移只执行一次
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let config = config.clone();
+            let schema = schema.clone();
+            thread::spawn(move || -> Result<Vec<i32>, String> {
+                let mut client =
+                    connect_to_schema(&config, &schema).map_err(|err| err.to_string())?;
+                library()
+                    .up(&mut client, None)
+                    .map_err(|err| err.to_string())
+            })
+        })
+        .collect();
+    let mut applied: Vec<i32> = vec![];
+    for handle in handles {
+        applied.extend(handle.join().expect("迁移线程不应该panic")?);
+    }
+    applied.sort_unstable();
+    assert_eq!(applied, [1, 2, 3, 4, 5, 6]);
+    for status in library().status(&mut client)? {
+        println!("{}", status);
+    }
+
+    // 失败的迁移整个回滚：第一条语句创建的表也不存在
+    let mut migrations = library().migrations;
+    migrations.push(Migration::new(
+        7,
+        "broken",
+        "CREATE TABLE publisher (id SERIAL PRIMARY KEY); INSERT INTO missing_table VALUES (1);",
+        None,
+    ));
+    let extended = Migrator::new(migrations)?;
+    match extended.up(&mut client, None) {
+        Err(err @ MigrationError::Failed { version: 7, .. }) => println!("{}", err),
+        other => panic!("迁移7应该失败：{:?}", other),
+    }
+    assert!(!table_exists(&mut client, "publisher")?);
+    assert_eq!(extended.plan(&mut client, None)?.len(), 1);
+
+    // 已经执行的迁移被修改后拒绝继续执行
+    let edited = Migrator::new(vec![Migration::new(
+        1,
+        "create_author",
+        "CREATE TABLE author (id SERIAL PRIMARY KEY)",
+        None,
+    )])?;
+    match edited.up(&mut client, None) {
+        Err(err @ MigrationError::ChecksumMismatch { version: 1, .. }) => println!("{}", err),
+        other => panic!("应该检测到校验和不一致：{:?}", other),
+    }
+    // 出错之后锁已经释放，其他连接可以继续迁移
+    let mut other = connect_to_schema(config, &schema)?;
+    assert!(library().up(&mut other, None)?.is_empty());
+
+    assert_eq!(library().down(&mut client, 5)?, [6, 5, 4, 3, 2]);
+    assert!(!table_exists(&mut client, "book")?);
+    assert!(table_exists(&mut client, "author")?);
+    assert_eq!(library().up(&mut client, None)?, [2, 3, 4, 5, 6]);
+
+    client.batch_execute(&format!("DROP SCHEMA {} CASCADE", schema))?;
+    Ok(())
+}
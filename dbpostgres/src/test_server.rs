@@ -0,0 +1,131 @@
+//! # 测试使用的Postgres
+//! 集成测试需要一个可以随意使用的Postgres，`TestServer::find`按下面的顺序查找：
+//! - 设置了指定的环境变量时连接这个URL；
+//! - 否则用`PATH`中的`initdb`和`pg_ctl`在临时目录中创建一个集群，测试结束时停止并删除；
+//! - 两者都不可用时（例如以root运行，`initdb`拒绝执行）打印原因，返回`None`，测试跳过。
+//!
+//! `dbpostgres`的`tests/config.rs`和`store`的一致性测试都使用这里的集群。
+use crate::config::Config;
+use std::io::Write;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::Command;
+use tempfile::TempDir;
+
+/// 临时目录中的集群，只监听127.0.0.1，不需要密码
+pub struct TempCluster {
+    dir: TempDir,
+    port: u16,
+}
+
+impl TempCluster {
+    pub fn start() -> Result<TempCluster, String> {
+        let dir = tempfile::tempdir().map_err(|err| err.to_string())?;
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .map_err(|err| err.to_string())?
+            .port();
+        let cluster = TempCluster { dir, port };
+        run(Command::new("initdb").arg("-D").arg(cluster.data()).args([
+            "-U",
+            "postgres",
+            "--auth=trust",
+            "--no-sync",
+        ]))?;
+        cluster.pg_ctl_start()?;
+        Ok(cluster)
+    }
+
+    fn data(&self) -> PathBuf {
+        self.dir.path().join("data")
+    }
+
+    pub fn pg_ctl_start(&self) -> Result<(), String> {
+        run(Command::new("pg_ctl")
+            .arg("-D")
+            .arg(self.data())
+            .arg("-l")
+            .arg(self.dir.path().join("log"))
+            .arg("-o")
+            .arg(format!(
+                "-p {} -k {} -c listen_addresses=127.0.0.1",
+                self.port,
+                self.dir.path().display()
+            ))
+            .args(["-w", "start"]))
+    }
+
+    pub fn pg_ctl_stop(&self) -> Result<(), String> {
+        run(Command::new("pg_ctl")
+            .arg("-D")
+            .arg(self.data())
+            .args(["-m", "fast", "-w", "stop"]))
+    }
+
+    pub fn config(&self) -> Config {
+        Config {
+            host: "127.0.0.1".to_string(),
+            port: self.port,
+            user: "postgres".to_string(),
+            dbname: "postgres".to_string(),
+            ..Config::default()
+        }
+    }
+}
+
+impl Drop for TempCluster {
+    fn drop(&mut self) {
+        let _ = self.pg_ctl_stop();
+    }
+}
+
+fn run(command: &mut Command) -> Result<(), String> {
+    let output = command
+        .output()
+        .map_err(|err| format!("无法执行{:?}：{}", command.get_program(), err))?;
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(format!(
+            "{:?}失败：{}",
+            command.get_program(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+    }
+}
+
+/// 测试使用的服务器，`cluster`为`None`时是环境变量指定的外部服务器
+pub struct TestServer {
+    pub config: Config,
+    pub cluster: Option<TempCluster>,
+}
+
+impl TestServer {
+    /// `var`是连接URL的环境变量，`test`是跳过时打印的测试名字
+    pub fn find(var: &str, test: &str) -> Option<TestServer> {
+        if let Ok(url) = std::env::var(var) {
+            let config =
+                Config::from_url(&url).unwrap_or_else(|err| panic!("{}无效：{}", var, err));
+            return Some(TestServer {
+                config,
+                cluster: None,
+            });
+        }
+        match TempCluster::start() {
+            Ok(cluster) => Some(TestServer {
+                config: cluster.config(),
+                cluster: Some(cluster),
+            }),
+            Err(reason) => {
+                // 直接写stderr，不会被测试框架捕获，跳过的时候总是能看到
+                let _ = writeln!(
+                    std::io::stderr(),
+                    "跳过{}：没有设置{}，也无法启动临时集群（{}）",
+                    test,
+                    var,
+                    reason
+                );
+                None
+            }
+        }
+    }
+}
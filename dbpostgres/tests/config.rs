@@ -0,0 +1,141 @@
+//! # 连接配置的集成测试
+//! 设置了`DBPOSTGRES_TEST_URL`时连接这个URL，否则启动一个临时集群，见`dbpostgres::test_server`。
+use dbpostgres::config::{Config, ConfigError};
+use dbpostgres::test_server::TestServer;
+use std::io::Write;
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+fn server(test: &str) -> Option<TestServer> {
+    TestServer::find("DBPOSTGRES_TEST_URL", test)
+}
+
+#[test]
+fn connect_runs_queries() {
+    let Some(server) = server("connect_runs_queries") else {
+        return;
+    };
+    let mut client = server.config.connect().unwrap();
+    let row = client.query_one("select 1 + 1", &[]).unwrap();
+    assert_eq!(row.get::<_, i32>(0), 2);
+}
+
+#[test]
+fn pool_is_shared_and_bounded() {
+    let Some(server) = server("pool_is_shared_and_bounded") else {
+        return;
+    };
+    let config = Config {
+        pool_size: 2,
+        connect_timeout: Duration::from_secs(1),
+        ..server.config.clone()
+    };
+    let pool = config.pool().unwrap();
+
+    // 同时借出两个连接，它们是不同的后端进程
+    let mut first = pool.get().unwrap();
+    let mut second = pool.get().unwrap();
+    let pid = |client: &mut postgres::Client| -> i32 {
+        client
+            .query_one("select pg_backend_pid()", &[])
+            .unwrap()
+            .get(0)
+    };
+    let pids = [pid(&mut first), pid(&mut second)];
+    assert_ne!(pids[0], pids[1]);
+    // 连接都借出之后，再借就会超时
+    assert!(pool.get_timeout(Duration::from_millis(200)).is_err());
+
+    // 归还一个之后其他线程可以借到
+    drop(first);
+    let pool = pool.clone();
+    thread::spawn(move || pid(&mut pool.get().unwrap()))
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn connect_retries_until_the_server_is_up() {
+    let Some(server) = server("connect_retries_until_the_server_is_up") else {
+        return;
+    };
+    let Some(cluster) = server.cluster else {
+        let _ = writeln!(
+            std::io::stderr(),
+            "跳过connect_retries_until_the_server_is_up：外部服务器不能停止"
+        );
+        return;
+    };
+    cluster.pg_ctl_stop().unwrap();
+
+    let config = Config {
+        retries: 8,
+        retry_backoff: Duration::from_millis(100),
+        ..cluster.config()
+    };
+    let starting = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(300));
+        cluster.pg_ctl_start().map(|_| cluster)
+    });
+    let mut client = config.connect().unwrap();
+    let _cluster = starting.join().unwrap().unwrap();
+    client.query_one("select 1", &[]).unwrap();
+}
+
+#[test]
+fn server_errors_are_not_retried() {
+    let Some(server) = server("server_errors_are_not_retried") else {
+        return;
+    };
+    let config = Config {
+        dbname: "no_such_database".to_string(),
+        retries: 3,
+        retry_backoff: Duration::from_secs(10),
+        ..server.config.clone()
+    };
+    match config.connect() {
+        Err(ConfigError::Connect {
+            attempts, source, ..
+        }) => {
+            assert_eq!(attempts, 1);
+            assert!(source.as_db_error().is_some());
+        }
+        other => panic!("应该连接失败：{:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn zero_connect_timeout_is_rejected() {
+    let env = |name: &str| Some("0".to_string()).filter(|_| name == "PGCONNECT_TIMEOUT");
+    assert!(matches!(
+        Config::load(None, env),
+        Err(ConfigError::Invalid(_))
+    ));
+    assert!(matches!(
+        Config::load(Some("connect_timeout = 0"), |_| None),
+        Err(ConfigError::Invalid(_))
+    ));
+    let config = Config::load(Some("connect_timeout = 2"), |_| None).unwrap();
+    assert_eq!(config.connect_timeout, Duration::from_secs(2));
+}
+
+#[test]
+fn unreachable_server_is_retried() {
+    // 没有服务器监听的端口，不需要数据库
+    let port = TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .unwrap()
+        .port();
+    let config = Config {
+        host: "127.0.0.1".to_string(),
+        port,
+        retries: 2,
+        retry_backoff: Duration::from_millis(10),
+        ..Config::default()
+    };
+    match config.connect() {
+        Err(ConfigError::Connect { attempts, .. }) => assert_eq!(attempts, 3),
+        other => panic!("应该连接失败：{:?}", other.map(|_| ())),
+    }
+}
@@ -1,35 +1,8 @@
 //! 标准偏差
-//! 下面例子计算一组测量数据的标准偏差和z分数
+//! 下面例子计算一组测量数据的标准偏差和z分数，计算方法在`science::statistics`中
 //!
 
-fn mean(data: &[i32]) -> Option<f32> {
-    let sum = data.iter().sum::<i32>() as f32;
-    let count = data.len();
-
-    match count {
-        positive if positive > 0 => Some(sum / count as f32),
-        _ => None,
-    }
-}
-
-fn std_deviation(data: &[i32]) -> Option<f32> {
-    match (mean(data), data.len()) {
-        (Some(data_mean), count) if count > 0 => {
-            let variance = data
-                .iter()
-                .map(|value| {
-                    let diff = data_mean - (*value as f32);
-
-                    diff * diff
-                })
-                .sum::<f32>()
-                / count as f32;
-
-            Some(variance.sqrt())
-        }
-        _ => None,
-    }
-}
+use science::statistics::{mean, std_deviation};
 
 fn main() {
     let data = [3, 1, 6, 1, 5, 8, 1, 8, 10, 11];
@@ -42,7 +15,7 @@ fn main() {
 
     let zscore = match (data_mean, data_std_deviation) {
         (Some(mean), Some(std_deviation)) => {
-            let diff = data[4] as f32 - mean;
+            let diff = data[4] as f64 - mean;
 
             Some(diff / std_deviation)
         }
@@ -0,0 +1,4 @@
+//! # 科学计算
+//! `src/bin`下的例子以及其他crate共享的模块。
+
+pub mod statistics;
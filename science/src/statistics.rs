@@ -0,0 +1,34 @@
+//! # 统计
+//! 平均值和标准偏差，从`standard-deviation`例子中提取出来，
+//! 接受任何可以无损转换为`f64`的数值，方便其他crate复用。
+
+/// 平均值，数据为空时返回`None`
+pub fn mean<T: Copy + Into<f64>>(data: &[T]) -> Option<f64> {
+    let sum = data.iter().map(|value| (*value).into()).sum::<f64>();
+    let count = data.len();
+
+    match count {
+        positive if positive > 0 => Some(sum / count as f64),
+        _ => None,
+    }
+}
+
+/// 总体标准偏差，数据为空时返回`None`
+pub fn std_deviation<T: Copy + Into<f64>>(data: &[T]) -> Option<f64> {
+    match (mean(data), data.len()) {
+        (Some(data_mean), count) if count > 0 => {
+            let variance = data
+                .iter()
+                .map(|value| {
+                    let diff = data_mean - (*value).into();
+
+                    diff * diff
+                })
+                .sum::<f64>()
+                / count as f64;
+
+            Some(variance.sqrt())
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1 @@
+drop table cat_colors;
@@ -0,0 +1,5 @@
+-- 旧版本的cats.db已经有这张表，所以使用if not exists
+create table if not exists cat_colors (
+    id integer primary key,
+    name text not null unique
+);
@@ -0,0 +1,5 @@
+create table if not exists cats (
+    id integer primary key,
+    name text not null,
+    color_id integer not null references cat_colors(id)
+);
@@ -0,0 +1,125 @@
+//! # CSV导入导出命令行工具
+//! ```text
+//! csv-table import cats.csv --table cats_import
+//! csv-table --db other.db import cats.csv --table cats --schema "name:text,age:integer"
+//! csv-table export "select * from cats" --format json --output cats.json
+//! ```
+use clap::{App, Arg};
+use rusqlite::Connection;
+use sqlite::csv_table::{self, CsvTableError, Format, ImportOptions};
+use std::fs::File;
+use std::io;
+use std::process;
+
+fn run() -> Result<(), CsvTableError> {
+    let matches = App::new("csv-table")
+        .about("在CSV文件和SQLite表之间导入导出")
+        .subcommand_required(true)
+        .arg(
+            Arg::new("db")
+                .long("db")
+                .takes_value(true)
+                .default_value("cats.db")
+                .help("数据库文件"),
+        )
+        .subcommand(
+            App::new("import")
+                .about("把CSV文件导入到表中")
+                .arg(Arg::new("file").required(true).help("CSV文件"))
+                .arg(
+                    Arg::new("table")
+                        .long("table")
+                        .takes_value(true)
+                        .required(true)
+                        .help("目标表，不存在时创建"),
+                )
+                .arg(
+                    Arg::new("schema")
+                        .long("schema")
+                        .takes_value(true)
+                        .help("表结构，例如name:text,age:integer；不指定时根据样本推断"),
+                )
+                .arg(
+                    Arg::new("sample")
+                        .long("sample")
+                        .takes_value(true)
+                        .default_value("100")
+                        .help("推断类型时读取的行数"),
+                )
+                .arg(
+                    Arg::new("delimiter")
+                        .long("delimiter")
+                        .takes_value(true)
+                        .default_value(",")
+                        .help("字段分隔符"),
+                ),
+        )
+        .subcommand(
+            App::new("export")
+                .about("把查询结果导出为CSV或者JSON")
+                .arg(Arg::new("query").required(true).help("SQL查询"))
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(["csv", "json"])
+                        .default_value("csv"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .takes_value(true)
+                        .help("输出文件，不指定时输出到标准输出"),
+                ),
+        )
+        .get_matches();
+
+    let mut conn = Connection::open(matches.value_of("db").unwrap())?;
+
+    match matches.subcommand() {
+        Some(("import", args)) => {
+            let mut options = ImportOptions::new(args.value_of("table").unwrap());
+            options.schema = args
+                .value_of("schema")
+                .map(csv_table::parse_schema)
+                .transpose()?;
+            options.sample_size = args
+                .value_of("sample")
+                .unwrap()
+                .parse()
+                .map_err(|_| CsvTableError::Schema("--sample应该是数字".to_string()))?;
+            options.delimiter = match args.value_of("delimiter").unwrap().as_bytes() {
+                [delimiter] => *delimiter,
+                _ => return Err(CsvTableError::Schema("分隔符应该是一个字符".to_string())),
+            };
+
+            let file = File::open(args.value_of("file").unwrap())?;
+            let report = csv_table::import_csv(&mut conn, file, &options)?;
+            for rejected in &report.rejected {
+                eprintln!("第{}行：{}", rejected.line, rejected.reason);
+            }
+            println!("导入{}行，拒绝{}行", report.inserted, report.rejected.len());
+        }
+        Some(("export", args)) => {
+            let format = match args.value_of("format") {
+                Some("json") => Format::Json,
+                _ => Format::Csv,
+            };
+            let query = args.value_of("query").unwrap();
+            let count = match args.value_of("output") {
+                Some(path) => csv_table::export_query(&conn, query, format, File::create(path)?)?,
+                None => csv_table::export_query(&conn, query, format, io::stdout().lock())?,
+            };
+            eprintln!("导出{}行", count);
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,80 @@
+//! # SQLite的例子
+//! 原来`src/main.rs`中依次执行的例子，`src/main.rs`现在是交互式命令行。
+// use rusqlite::NO_PARAMS;
+use rusqlite::{Connection, Result};
+use sqlite::csv_table;
+use sqlite::migrations;
+use sqlite::pool;
+use sqlite::repository::{self, CatRepo, Page};
+use sqlite::search;
+use sqlite::shell;
+use sqlite::transactions;
+use std::collections::HashMap;
+
+fn main() -> Result<()> {
+    let mut conn = Connection::open("cats.db")?;
+    if let Err(err) = migrations::cats().up(&mut conn, None) {
+        println!("执行数据库迁移错误：{}", err);
+    }
+    if let Err(err) = migrations::run_migrations() {
+        println!("演示数据库迁移错误：{}", err);
+    }
+
+    if let Err(err) = insert_data(&mut conn) {
+        println!("插入数据错误：{}", err);
+    }
+    // 事务的例子会替换所有颜色，`cats`中的猫仍然引用旧的颜色id，所以不能在`cats.db`上运行
+    let mut scratch = Connection::open_in_memory()?;
+    if let Err(err) = migrations::cats().up(&mut scratch, None) {
+        println!("执行数据库迁移错误：{}", err);
+    }
+    if let Err(err) = transactions::successful_tx(&mut scratch) {
+        println!("提交事务错误：{}", err);
+    }
+    match transactions::rolled_back_tx(&mut scratch) {
+        Ok(()) => println!("事务应该回滚，但是提交了"),
+        Err(err) => println!("事务已回滚：{}", err),
+    }
+    if let Err(err) = transactions::transaction_helpers() {
+        println!("演示事务错误：{}", err);
+    }
+    if let Err(err) = pool::hammer_with_rayon() {
+        println!("并发访问数据库错误：{}", err);
+    }
+    if let Err(err) = repository::use_cat_repository() {
+        println!("访问猫的数据错误：{}", err);
+    }
+    if let Err(err) = csv_table::import_and_export_csv() {
+        println!("导入导出CSV错误：{}", err);
+    }
+    if let Err(err) = search::search_cat_notes() {
+        println!("全文搜索错误：{}", err);
+    }
+    if let Err(err) = shell::run_shell_script() {
+        println!("交互式命令行错误：{}", err);
+    }
+
+    Ok(())
+}
+
+fn insert_data(conn: &mut Connection) -> Result<()> {
+    conn.execute("delete from cats", [])?;
+    conn.execute("delete from cat_colors", [])?;
+
+    let mut cat_colors = HashMap::new();
+    cat_colors.insert(String::from("Blue"), vec!["Tigger", "Sammy"]);
+    cat_colors.insert(String::from("Black"), vec!["Oreo", "Biscuit"]);
+
+    let repo = CatRepo::new(conn);
+    for (color, catnames) in &cat_colors {
+        for cat in catnames {
+            repo.create(cat, color)?;
+        }
+    }
+
+    for cat in repo.list(Page::default())? {
+        println!("Found cat {:?}", cat);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,87 @@
+//! # 数据库迁移命令行工具
+//! ```text
+//! migrate status
+//! migrate --db other.db up --to 1
+//! migrate --dir sqlite/migrations down --steps 2
+//! ```
+//! 不指定`--dir`时使用编译进程序的`cats.db`迁移。
+use clap::{App, Arg};
+use rusqlite::Connection;
+use sqlite::migrations::{self, MigrationError, Migrator};
+use std::path::Path;
+use std::process;
+
+fn run() -> Result<(), MigrationError> {
+    let matches = App::new("migrate")
+        .about("执行、回滚和查看数据库迁移")
+        .subcommand_required(true)
+        .arg(
+            Arg::new("db")
+                .long("db")
+                .takes_value(true)
+                .default_value("cats.db")
+                .help("数据库文件"),
+        )
+        .arg(
+            Arg::new("dir")
+                .long("dir")
+                .takes_value(true)
+                .help("包含0001_name.up.sql和0001_name.down.sql的目录"),
+        )
+        .subcommand(
+            App::new("up").about("执行未执行的迁移").arg(
+                Arg::new("to")
+                    .long("to")
+                    .takes_value(true)
+                    .help("只执行到这个版本"),
+            ),
+        )
+        .subcommand(
+            App::new("down").about("回滚最新的迁移").arg(
+                Arg::new("steps")
+                    .long("steps")
+                    .takes_value(true)
+                    .default_value("1")
+                    .help("回滚的迁移数量"),
+            ),
+        )
+        .subcommand(App::new("status").about("列出迁移的状态"))
+        .get_matches();
+
+    let migrator = match matches.value_of("dir") {
+        Some(dir) => Migrator::from_dir(Path::new(dir))?,
+        None => migrations::cats(),
+    };
+    let mut conn = Connection::open(matches.value_of("db").unwrap())?;
+    let number = |text: &str| {
+        text.parse::<u32>()
+            .map_err(|_| MigrationError::Invalid(format!("无效的数字：{}", text)))
+    };
+
+    match matches.subcommand() {
+        Some(("up", args)) => {
+            let target = args.value_of("to").map(number).transpose()?;
+            let done = migrator.up(&mut conn, target)?;
+            println!("执行了{}个迁移：{:?}", done.len(), done);
+        }
+        Some(("down", args)) => {
+            let steps = number(args.value_of("steps").unwrap())?;
+            let done = migrator.down(&mut conn, steps as usize)?;
+            println!("回滚了{}个迁移：{:?}", done.len(), done);
+        }
+        Some(("status", _)) => {
+            for status in migrator.status(&conn)? {
+                println!("{}", status);
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,121 @@
+//! # 全文搜索命令行工具
+//! ```text
+//! search install
+//! search query tigger
+//! search --table cat_notes --columns body install
+//! search --table cat_notes --columns body query '"laundry basket" OR chase*'
+//! search --table cat_notes --columns body uninstall
+//! ```
+//! 查询使用FTS5的语法。索引和触发器会一直留在数据库中，所以需要先用`install`显式创建，
+//! `query`只读取已有的索引，索引不存在或者建立在其他列上时报错。
+use ansi_term::Colour::{Green, Red};
+use clap::{App, Arg};
+use rusqlite::Connection;
+use sqlite::search::{FtsIndex, Query, SearchError};
+use std::process;
+
+fn run() -> Result<(), SearchError> {
+    let matches = App::new("search")
+        .about("用FTS5搜索数据库中的文本")
+        .subcommand_required(true)
+        .arg(
+            Arg::new("db")
+                .long("db")
+                .takes_value(true)
+                .default_value("cats.db")
+                .help("数据库文件"),
+        )
+        .arg(
+            Arg::new("table")
+                .long("table")
+                .takes_value(true)
+                .default_value("cats")
+                .help("内容表"),
+        )
+        .arg(
+            Arg::new("key")
+                .long("key")
+                .takes_value(true)
+                .default_value("id")
+                .help("内容表的整数主键"),
+        )
+        .arg(
+            Arg::new("columns")
+                .long("columns")
+                .takes_value(true)
+                .default_value("name")
+                .help("建立索引的列，用逗号分隔"),
+        )
+        .subcommand(App::new("install").about("创建索引和同步用的触发器"))
+        .subcommand(App::new("uninstall").about("删除索引和触发器"))
+        .subcommand(
+            App::new("query")
+                .about("搜索已经创建的索引")
+                .arg(Arg::new("query").required(true).help("FTS5查询"))
+                .arg(
+                    Arg::new("limit")
+                        .long("limit")
+                        .takes_value(true)
+                        .default_value("20")
+                        .help("最多显示的结果数"),
+                )
+                .arg(Arg::new("no-color").long("no-color").help("不使用颜色")),
+        )
+        .get_matches();
+
+    let columns: Vec<&str> = matches.value_of("columns").unwrap().split(',').collect();
+    let index = FtsIndex::new(
+        matches.value_of("table").unwrap(),
+        matches.value_of("key").unwrap(),
+        &columns,
+    );
+    let conn = Connection::open(matches.value_of("db").unwrap())?;
+
+    let args = match matches.subcommand() {
+        Some(("install", _)) => {
+            index.ensure(&conn)?;
+            eprintln!("已创建索引{}", index.name());
+            return Ok(());
+        }
+        Some(("uninstall", _)) => {
+            index.drop_index(&conn)?;
+            eprintln!("已删除索引{}", index.name());
+            return Ok(());
+        }
+        Some(("query", args)) => args,
+        _ => unreachable!(),
+    };
+
+    index.check(&conn)?;
+    let limit = args.value_of("limit").unwrap().parse().unwrap_or(20);
+    let color = !args.is_present("no-color") && std::env::var_os("NO_COLOR").is_none();
+    let hits = index.search(&conn, &Query::raw(args.value_of("query").unwrap()), limit)?;
+
+    for hit in &hits {
+        let snippet: String = match color {
+            true => hit
+                .fragments()
+                .into_iter()
+                .map(|(text, matched)| match matched {
+                    true => Red.bold().paint(text).to_string(),
+                    false => text.to_string(),
+                })
+                .collect(),
+            false => hit.highlight("[", "]"),
+        };
+        let rowid = format!("{:>6}", hit.rowid);
+        match color {
+            true => println!("{} {}", Green.paint(rowid), snippet),
+            false => println!("{} {}", rowid, snippet),
+        }
+    }
+    eprintln!("找到{}条结果", hits.len());
+    Ok(())
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
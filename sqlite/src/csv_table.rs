@@ -0,0 +1,604 @@
+//! # CSV和SQLite表之间的导入导出
+//! 导入时根据前面若干行推断每一列的类型（或者使用给定的表结构），
+//! 所有行在同一个事务中按批插入，空字段保存为`NULL`（和`csv::invalid_option`的处理相同）。
+//! 字段数量不对、值不符合列类型或者插入时出错（例如违反约束）的行不会插入，
+//! 而是和它在文件中的行号一起报告出来。
+//!
+//! 一批的参数数量不超过`SQLITE_MAX_VARIABLE_NUMBER`在3.32之前的默认值999，列很多时每批的行数会减少。
+//!
+//! 导出时可以把任意查询的结果写为CSV或者JSON。
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::{params_from_iter, Connection};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// 一条语句最多使用的参数数量
+const MAX_VARIABLES: usize = 999;
+
+#[derive(Debug)]
+pub enum CsvTableError {
+    Csv(csv::Error),
+    Sqlite(rusqlite::Error),
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// 表结构或者参数不正确
+    Schema(String),
+}
+
+impl fmt::Display for CsvTableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CsvTableError::Csv(err) => write!(f, "CSV错误：{}", err),
+            CsvTableError::Sqlite(err) => write!(f, "数据库错误：{}", err),
+            CsvTableError::Io(err) => write!(f, "读写错误：{}", err),
+            CsvTableError::Json(err) => write!(f, "JSON错误：{}", err),
+            CsvTableError::Schema(message) => write!(f, "表结构错误：{}", message),
+        }
+    }
+}
+
+impl Error for CsvTableError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CsvTableError::Csv(err) => Some(err),
+            CsvTableError::Sqlite(err) => Some(err),
+            CsvTableError::Io(err) => Some(err),
+            CsvTableError::Json(err) => Some(err),
+            CsvTableError::Schema(_) => None,
+        }
+    }
+}
+
+impl From<csv::Error> for CsvTableError {
+    fn from(err: csv::Error) -> Self {
+        CsvTableError::Csv(err)
+    }
+}
+
+impl From<rusqlite::Error> for CsvTableError {
+    fn from(err: rusqlite::Error) -> Self {
+        CsvTableError::Sqlite(err)
+    }
+}
+
+impl From<io::Error> for CsvTableError {
+    fn from(err: io::Error) -> Self {
+        CsvTableError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for CsvTableError {
+    fn from(err: serde_json::Error) -> Self {
+        CsvTableError::Json(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Integer,
+    Real,
+    Text,
+}
+
+impl ColumnType {
+    fn sql(self) -> &'static str {
+        match self {
+            ColumnType::Integer => "integer",
+            ColumnType::Real => "real",
+            ColumnType::Text => "text",
+        }
+    }
+
+    /// 把字段转换为这一列的值，空字段为`NULL`
+    fn convert(self, field: &str) -> Result<Value, String> {
+        if field.is_empty() {
+            return Ok(Value::Null);
+        }
+        match self {
+            ColumnType::Integer => field
+                .trim()
+                .parse()
+                .map(Value::Integer)
+                .map_err(|_| format!("{:?}不是整数", field)),
+            ColumnType::Real => field
+                .trim()
+                .parse()
+                .map(Value::Real)
+                .map_err(|_| format!("{:?}不是数字", field)),
+            ColumnType::Text => Ok(Value::Text(field.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub name: String,
+    pub ty: ColumnType,
+}
+
+/// 解析`name:type,name:type`格式的表结构，类型为`integer`、`real`或`text`
+pub fn parse_schema(text: &str) -> Result<Vec<Column>, CsvTableError> {
+    text.split(',')
+        .map(|column| {
+            let (name, ty) = column.split_once(':').unwrap_or((column, "text"));
+            let ty = match ty.trim().to_lowercase().as_str() {
+                "integer" | "int" => ColumnType::Integer,
+                "real" | "float" => ColumnType::Real,
+                "text" => ColumnType::Text,
+                other => return Err(CsvTableError::Schema(format!("未知的类型：{}", other))),
+            };
+            Ok(Column {
+                name: name.trim().to_string(),
+                ty,
+            })
+        })
+        .collect()
+}
+
+/// 根据样本推断列类型：全部是整数为`Integer`，全部是数字为`Real`，否则为`Text`
+pub fn infer_schema(headers: &csv::StringRecord, sample: &[csv::StringRecord]) -> Vec<Column> {
+    headers
+        .iter()
+        .enumerate()
+        .map(|(index, name)| {
+            let values: Vec<&str> = sample
+                .iter()
+                .filter(|record| record.len() == headers.len())
+                .map(|record| record[index].trim())
+                .filter(|value| !value.is_empty())
+                .collect();
+            let ty = if values.is_empty() {
+                ColumnType::Text
+            } else if values.iter().all(|value| value.parse::<i64>().is_ok()) {
+                ColumnType::Integer
+            } else if values.iter().all(|value| value.parse::<f64>().is_ok()) {
+                ColumnType::Real
+            } else {
+                ColumnType::Text
+            };
+            Column {
+                name: name.to_string(),
+                ty,
+            }
+        })
+        .collect()
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    pub table: String,
+    /// 为空时根据样本推断
+    pub schema: Option<Vec<Column>>,
+    pub sample_size: usize,
+    pub batch_size: usize,
+    pub delimiter: u8,
+}
+
+impl ImportOptions {
+    pub fn new(table: &str) -> Self {
+        ImportOptions {
+            table: table.to_string(),
+            schema: None,
+            sample_size: 100,
+            batch_size: 100,
+            delimiter: b',',
+        }
+    }
+}
+
+/// 没有导入的行
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejected {
+    pub line: u64,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportReport {
+    pub schema: Vec<Column>,
+    pub inserted: usize,
+    pub rejected: Vec<Rejected>,
+}
+
+/// 导入CSV到`options.table`，表不存在时按表结构创建
+pub fn import_csv<R: Read>(
+    conn: &mut Connection,
+    reader: R,
+    options: &ImportOptions,
+) -> Result<ImportReport, CsvTableError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(options.delimiter)
+        .flexible(true)
+        .from_reader(reader);
+    let headers = reader.headers()?.clone();
+    let mut records = reader.records();
+
+    let mut rejected = vec![];
+    let mut pending = vec![];
+    for result in records.by_ref().take(options.sample_size.max(1)) {
+        match result {
+            Ok(record) => pending.push(record),
+            Err(err) => rejected.push(rejected_csv(err)),
+        }
+    }
+
+    let schema = match &options.schema {
+        Some(schema) => schema.clone(),
+        None => infer_schema(&headers, &pending),
+    };
+    if schema.len() != headers.len() {
+        return Err(CsvTableError::Schema(format!(
+            "表结构有{}列，CSV有{}列",
+            schema.len(),
+            headers.len()
+        )));
+    }
+
+    let tx = conn.transaction()?;
+    let columns: Vec<String> = schema
+        .iter()
+        .map(|column| format!("{} {}", quote(&column.name), column.ty.sql()))
+        .collect();
+    tx.execute_batch(&format!(
+        "create table if not exists {} ({})",
+        quote(&options.table),
+        columns.join(", ")
+    ))?;
+
+    let insert = |rows: usize| {
+        let placeholders = format!("({})", vec!["?"; schema.len()].join(", "));
+        format!(
+            "insert into {} ({}) values {}",
+            quote(&options.table),
+            schema
+                .iter()
+                .map(|column| quote(&column.name))
+                .collect::<Vec<_>>()
+                .join(", "),
+            vec![placeholders; rows].join(", ")
+        )
+    };
+    let batch_size = options
+        .batch_size
+        .clamp(1, (MAX_VARIABLES / schema.len().max(1)).max(1));
+    let mut batch: Vec<(u64, Vec<Value>)> = Vec::with_capacity(batch_size);
+    let mut inserted = 0;
+
+    let rows = pending.into_iter().map(Ok).chain(records);
+    for result in rows {
+        let record = match result {
+            Ok(record) => record,
+            Err(err) => {
+                rejected.push(rejected_csv(err));
+                continue;
+            }
+        };
+        let line = record.position().map_or(0, |position| position.line());
+        if record.len() != schema.len() {
+            rejected.push(Rejected {
+                line,
+                reason: format!("应该有{}个字段，实际有{}个", schema.len(), record.len()),
+            });
+            continue;
+        }
+        let values: Result<Vec<Value>, String> = schema
+            .iter()
+            .zip(record.iter())
+            .map(|(column, field)| {
+                column
+                    .ty
+                    .convert(field)
+                    .map_err(|reason| format!("{}列：{}", column.name, reason))
+            })
+            .collect();
+        match values {
+            Ok(values) => batch.push((line, values)),
+            Err(reason) => {
+                rejected.push(Rejected { line, reason });
+                continue;
+            }
+        }
+
+        if batch.len() == batch_size {
+            inserted += insert_batch(&tx, insert, &mut batch, &mut rejected)?;
+        }
+    }
+    if !batch.is_empty() {
+        inserted += insert_batch(&tx, insert, &mut batch, &mut rejected)?;
+    }
+    rejected.sort_by_key(|rejected| rejected.line);
+    tx.commit()?;
+
+    Ok(ImportReport {
+        schema,
+        inserted,
+        rejected,
+    })
+}
+
+/// 用一条语句插入`batch`中的所有行，返回插入的行数。
+/// 出错的语句不会留下任何修改，这时逐行重试，出错的行记录到`rejected`中；
+/// 如果错误让整个事务回滚了（例如磁盘已满），直接返回错误。
+fn insert_batch<F: Fn(usize) -> String>(
+    conn: &Connection,
+    insert: F,
+    batch: &mut Vec<(u64, Vec<Value>)>,
+    rejected: &mut Vec<Rejected>,
+) -> Result<usize, CsvTableError> {
+    let rows = batch.len();
+    let values = batch.iter().flat_map(|(_, values)| values);
+    match conn
+        .prepare_cached(&insert(rows))?
+        .execute(params_from_iter(values))
+    {
+        Ok(_) => {
+            batch.clear();
+            return Ok(rows);
+        }
+        Err(err) if conn.is_autocommit() => return Err(err.into()),
+        Err(_) => {}
+    }
+
+    let mut single = conn.prepare_cached(&insert(1))?;
+    let mut inserted = 0;
+    for (line, values) in batch.drain(..) {
+        match single.execute(params_from_iter(&values)) {
+            Ok(_) => inserted += 1,
+            Err(err) if conn.is_autocommit() => return Err(err.into()),
+            Err(err) => rejected.push(Rejected {
+                line,
+                reason: err.to_string(),
+            }),
+        }
+    }
+    Ok(inserted)
+}
+
+fn rejected_csv(err: csv::Error) -> Rejected {
+    Rejected {
+        line: err.position().map_or(0, |position| position.line()),
+        reason: err.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+fn text(value: ValueRef) -> String {
+    match value {
+        ValueRef::Null => String::new(),
+        ValueRef::Integer(value) => value.to_string(),
+        ValueRef::Real(value) => value.to_string(),
+        ValueRef::Text(text) => String::from_utf8_lossy(text).to_string(),
+        ValueRef::Blob(blob) => blob.iter().map(|byte| format!("{:02x}", byte)).collect(),
+    }
+}
+
+fn json(value: ValueRef) -> serde_json::Value {
+    match value {
+        ValueRef::Null => serde_json::Value::Null,
+        ValueRef::Integer(value) => value.into(),
+        ValueRef::Real(value) => value.into(),
+        ValueRef::Text(_) | ValueRef::Blob(_) => text(value).into(),
+    }
+}
+
+/// 把查询结果写为CSV（第一行是列名，`NULL`为空字段）或者JSON对象数组，返回行数
+pub fn export_query<W: Write>(
+    conn: &Connection,
+    sql: &str,
+    format: Format,
+    mut writer: W,
+) -> Result<usize, CsvTableError> {
+    let mut stmt = conn.prepare(sql)?;
+    let names: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+    let mut rows = stmt.query([])?;
+    let mut count = 0;
+
+    match format {
+        Format::Csv => {
+            let mut csv = csv::Writer::from_writer(writer);
+            csv.write_record(&names)?;
+            while let Some(row) = rows.next()? {
+                let fields = (0..names.len())
+                    .map(|index| row.get_ref(index).map(text))
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                csv.write_record(&fields)?;
+                count += 1;
+            }
+            csv.flush()?;
+        }
+        Format::Json => {
+            // 按列的顺序输出对象的字段
+            writer.write_all(b"[")?;
+            while let Some(row) = rows.next()? {
+                let fields = names
+                    .iter()
+                    .enumerate()
+                    .map(|(index, name)| {
+                        Ok(format!(
+                            "{}:{}",
+                            serde_json::to_string(name)?,
+                            serde_json::to_string(&json(row.get_ref(index)?))?
+                        ))
+                    })
+                    .collect::<Result<Vec<_>, CsvTableError>>()?;
+                let separator = if count == 0 { "\n" } else { ",\n" };
+                write!(writer, "{}  {{{}}}", separator, fields.join(","))?;
+                count += 1;
+            }
+            writer.write_all(b"\n]\n")?;
+        }
+    }
+    Ok(count)
+}
+
+const CAT_CSV: &str = "name,age,weight,color
+Tigger,3,4.5,Blue
+\"Oreo, the second\",5,,Black
+Sammy,,3.2,\"Blue
+and white\"
+Biscuit,two,4.0,Black
+Misty,1
+Smokey,7,5.1,
+";
+
+/// # 在CSV文件和SQLite表之间导入导出
+pub fn import_and_export_csv() -> Result<(), CsvTableError> {
+    let mut conn = Connection::open_in_memory()?;
+    let mut options = ImportOptions::new("cat_import");
+    // 只看前3行推断类型，后面"two"不是整数，这一行被拒绝
+    options.sample_size = 3;
+    options.batch_size = 2;
+    let report = import_csv(&mut conn, CAT_CSV.as_bytes(), &options)?;
+
+    let types: Vec<ColumnType> = report.schema.iter().map(|column| column.ty).collect();
+    assert_eq!(
+        types,
+        [
+            ColumnType::Text,
+            ColumnType::Integer,
+            ColumnType::Real,
+            ColumnType::Text
+        ]
+    );
+    for rejected in &report.rejected {
+        println!("第{}行没有导入：{}", rejected.line, rejected.reason);
+    }
+    assert_eq!(report.inserted, 4);
+    let lines: Vec<u64> = report
+        .rejected
+        .iter()
+        .map(|rejected| rejected.line)
+        .collect();
+    assert_eq!(lines, [6, 7]);
+
+    // 使用给定的表结构
+    let mut options = ImportOptions::new("cat_typed");
+    options.schema = Some(parse_schema(
+        "name:text,age:integer,weight:real,color:text",
+    )?);
+    let typed = import_csv(&mut conn, CAT_CSV.as_bytes(), &options)?;
+    assert_eq!(typed.inserted, 4);
+    assert_eq!(typed.rejected[0].line, 6);
+    assert!(typed.rejected[0].reason.contains("age"));
+
+    let nulls: i64 = conn.query_row(
+        "select count(*) from cat_typed where age is null or weight is null or color is null",
+        [],
+        |row| row.get(0),
+    )?;
+    assert_eq!(nulls, 3);
+
+    let mut csv = vec![];
+    let count = export_query(
+        &conn,
+        "select name, age, color from cat_typed order by rowid",
+        Format::Csv,
+        &mut csv,
+    )?;
+    let csv = String::from_utf8_lossy(&csv);
+    println!("{}", csv);
+    assert_eq!(count, 4);
+    assert!(csv.starts_with("name,age,color\nTigger,3,Blue\n\"Oreo, the second\",5,Black\n"));
+    assert!(csv.contains("Sammy,,\"Blue\nand white\"\n"));
+
+    let mut json = vec![];
+    export_query(
+        &conn,
+        "select name, age, weight from cat_typed where age > 4 order by age",
+        Format::Json,
+        &mut json,
+    )?;
+    let json = String::from_utf8_lossy(&json);
+    println!("{}", json);
+    let parsed: serde_json::Value = serde_json::from_str(&json)?;
+    assert_eq!(parsed[0]["name"], "Oreo, the second");
+    assert!(parsed[0]["weight"].is_null());
+    assert_eq!(parsed[1]["weight"], 5.1);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(conn: &Connection, table: &str) -> i64 {
+        conn.query_row(&format!("select count(*) from {}", table), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn wide_csv_is_split_into_smaller_batches() {
+        // 400列 × 100行有40000个参数，超过任何版本SQLite一条语句的参数上限
+        let columns = 400;
+        let header: Vec<String> = (0..columns).map(|column| format!("c{}", column)).collect();
+        let mut csv = header.join(",") + "\n";
+        for row in 0..100 {
+            let fields: Vec<String> = (0..columns)
+                .map(|column| (row * column).to_string())
+                .collect();
+            csv.push_str(&fields.join(","));
+            csv.push('\n');
+        }
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        let report = import_csv(&mut conn, csv.as_bytes(), &ImportOptions::new("wide")).unwrap();
+        assert_eq!(report.inserted, 100);
+        assert!(report.rejected.is_empty());
+        assert_eq!(count(&conn, "wide"), 100);
+    }
+
+    #[test]
+    fn failing_rows_are_rejected_without_aborting_the_import() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("create table cats (name text not null unique, age integer)")
+            .unwrap();
+        let csv = "name,age\nTigger,3\nSammy,5\nTigger,4\n,2\nOreo,1\n";
+        let mut options = ImportOptions::new("cats");
+        options.batch_size = 10;
+
+        let report = import_csv(&mut conn, csv.as_bytes(), &options).unwrap();
+        assert_eq!(report.inserted, 3);
+        let lines: Vec<u64> = report
+            .rejected
+            .iter()
+            .map(|rejected| rejected.line)
+            .collect();
+        assert_eq!(lines, [4, 5]);
+        assert!(report.rejected[0].reason.contains("UNIQUE"));
+        assert!(report.rejected[1].reason.contains("NOT NULL"));
+        assert_eq!(count(&conn, "cats"), 3);
+    }
+
+    #[test]
+    fn rejections_are_reported_in_line_order() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("create table cats (name text unique, age integer)")
+            .unwrap();
+        // 第3行在插入时出错，第4行在转换类型时出错
+        let csv = "name,age\nTigger,3\nTigger,4\nSammy,old\nOreo,1\n";
+        let mut options = ImportOptions::new("cats");
+        options.schema = Some(parse_schema("name:text,age:integer").unwrap());
+
+        let report = import_csv(&mut conn, csv.as_bytes(), &options).unwrap();
+        let lines: Vec<u64> = report
+            .rejected
+            .iter()
+            .map(|rejected| rejected.line)
+            .collect();
+        assert_eq!(lines, [3, 4]);
+        assert_eq!(report.inserted, 2);
+    }
+}
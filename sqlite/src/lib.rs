@@ -0,0 +1,10 @@
+//! # SQLite
+//! `src/main.rs`的交互式命令行、`src/bin/demos.rs`中的例子以及`src/bin`下的其他命令行工具共享的模块。
+
+pub mod csv_table;
+pub mod migrations;
+pub mod pool;
+pub mod repository;
+pub mod search;
+pub mod shell;
+pub mod transactions;
@@ -1,96 +1,77 @@
-// use rusqlite::NO_PARAMS;
-use rusqlite::{Connection, Result};
-use std::collections::HashMap;
-
-fn main() -> Result<()> {
-    let mut conn = Connection::open("cats.db")?;
-    if let Err(err) = create_table(&mut conn) {
-        println!("创建数据表错误：{}", err);
-    }
-
-    if let Err(err) = insert_data(&mut conn) {
-        println!("插入数据错误：{}", err);
-    }
-
-    Ok(())
-}
-
-#[derive(Debug)]
-struct Cat {
-    name: String,
-    color: String,
+//! # SQLite交互式命令行
+//! ```text
+//! sqlite            打开当前目录下的cats.db
+//! sqlite other.db
+//! ```
+//! 支持行编辑和历史记录（保存在`~/.sqlite_rs_history`，不和`sqlite3`的`~/.sqlite_history`混用），
+//! 输入`.help`查看点命令。
+//! 原来在这里的例子移到了`src/bin/demos.rs`。
+use clap::{App, Arg};
+use rusqlite::Connection;
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+use sqlite::shell::{Input, Shell};
+use std::io;
+use std::path::PathBuf;
+use std::process;
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".sqlite_rs_history"))
 }
 
-fn create_table(conn: &mut Connection) -> Result<()> {
-    // let conn = Connection::open("cats.db")?;
-
-    conn.execute(
-        "create table if not exists cat_colors (id integer primary key, name text not null unique)",
-        [],
-    )?;
-    conn.execute("create table if not exists cats (id integer primary key, name text not null, color_id integer not null references cat_colors(id))", [],)?;
-    Ok(())
-}
-
-fn insert_data(conn: &mut Connection) -> Result<()> {
-    // let conn = Connection::open("cats.db")?;
-
-    conn.execute("delete from cat_colors", [])?;
-    conn.execute("delete from cats", [])?;
-
-    let mut cat_colors = HashMap::new();
-    cat_colors.insert(String::from("Blue"), vec!["Tigger", "Sammy"]);
-    cat_colors.insert(String::from("Black"), vec!["Oreo", "Biscuit"]);
-
-    for (color, catnames) in &cat_colors {
-        conn.execute(
-            "INSERT INTO cat_colors (name) values (?1)",
-            &[&color.to_string()],
-        )?;
-        let last_id: String = conn.last_insert_rowid().to_string();
-
-        for cat in catnames {
-            conn.execute(
-                "INSERT INTO cats (name, color_id) values(?1, ?2)",
-                &[&cat.to_string(), &last_id],
-            )?;
+fn main() {
+    let matches = App::new("sqlite")
+        .about("SQLite交互式命令行")
+        .arg(Arg::new("db").default_value("cats.db").help("数据库文件"))
+        .get_matches();
+
+    let path = matches.value_of("db").unwrap();
+    let conn = match Connection::open(path) {
+        Ok(conn) => conn,
+        Err(err) => {
+            eprintln!("打开{}错误：{}", path, err);
+            process::exit(1);
         }
+    };
+    println!("已打开{}，输入.help查看帮助，.quit退出", path);
+
+    let mut shell = Shell::new(conn);
+    let mut editor = Editor::<()>::new();
+    let history = history_path();
+    if let Some(history) = &history {
+        let _ = editor.load_history(history);
     }
-    let mut stmt = conn.prepare(
-        "SELECT c.name, cc.name from cats c INNER JOIN cat_colors cc ON cc.id=c.color_id;",
-    )?;
 
-    let cats = stmt.query_map([], |row| {
-        Ok(Cat {
-            name: row.get(0)?,
-            color: row.get(1)?,
-        })
-    })?;
+    loop {
+        let prompt = match shell.is_pending() {
+            true => "   ...> ",
+            false => "sqlite> ",
+        };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            // Ctrl-C放弃当前输入，Ctrl-D退出
+            Err(ReadlineError::Interrupted) => {
+                shell.discard();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("读取输入错误：{}", err);
+                break;
+            }
+        };
+        if !line.trim().is_empty() {
+            editor.add_history_entry(line.as_str());
+        }
 
-    for cat in cats {
-        println!("Found cat {:?}", cat);
+        match shell.feed(&line, &mut io::stdout()) {
+            Ok(Input::Quit) => break,
+            Ok(_) => {}
+            Err(err) => eprintln!("错误：{}", err),
+        }
     }
 
-    Ok(())
-}
-
-fn successful_tx(conn: &mut Connection) -> Result<()> {
-    let tx = conn.transaction()?;
-
-    tx.execute("delete from cat_colors", [])?;
-    tx.execute("insert into cat_colors (name) values(?1)", &[&"lavender"])?;
-    tx.execute("insert into cat_colors (name) values(?1)", &[&"blue"])?;
-
-    tx.commit()
-}
-
-fn rolled_back_tx(conn: &mut Connection) -> Result<()> {
-    let tx = conn.transaction()?;
-
-    tx.execute("delete from cat_colors", [])?;
-    tx.execute("insert into cat_colors (name) values(?1)", &[&"lavender"])?;
-    tx.execute("insert into cat_colors (name) values(?1)", &[&"blue"])?;
-    tx.execute("insert into cat_colors (name) values(?1)", &[&"lavender"])?;
-
-    tx.commit()
+    if let Some(history) = &history {
+        let _ = editor.save_history(history);
+    }
 }
@@ -0,0 +1,514 @@
+//! # 数据库迁移
+//! 按版本号顺序执行的迁移，每个迁移可以是SQL文本（例如`migrations`目录中的`.sql`文件），
+//! 也可以是Rust闭包。已经执行的迁移记录在`schema_migrations`表中，
+//! 同时保存内容的校验和，迁移执行后被修改会在`up`之前报错。
+//!
+//! 每个迁移和它在`schema_migrations`中的记录在同一个`conn.transaction()`中完成，
+//! 失败时整个迁移回滚，数据库停留在上一个版本。
+use rusqlite::{params, Connection, Transaction};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Sqlite(rusqlite::Error),
+    Io(io::Error),
+    /// 已经执行的迁移内容被修改了
+    ChecksumMismatch {
+        version: u32,
+        name: String,
+    },
+    /// 迁移没有`down`步骤，不能回滚
+    Irreversible {
+        version: u32,
+        name: String,
+    },
+    /// 某个版本的迁移执行失败
+    Failed {
+        version: u32,
+        name: String,
+        source: rusqlite::Error,
+    },
+    Invalid(String),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Sqlite(err) => write!(f, "数据库错误：{}", err),
+            MigrationError::Io(err) => write!(f, "读取迁移文件错误：{}", err),
+            MigrationError::ChecksumMismatch { version, name } => {
+                write!(f, "迁移{:04}_{}在执行后被修改过", version, name)
+            }
+            MigrationError::Irreversible { version, name } => {
+                write!(f, "迁移{:04}_{}没有down步骤", version, name)
+            }
+            MigrationError::Failed {
+                version,
+                name,
+                source,
+            } => write!(f, "迁移{:04}_{}执行失败：{}", version, name, source),
+            MigrationError::Invalid(message) => write!(f, "无效的迁移：{}", message),
+        }
+    }
+}
+
+impl Error for MigrationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MigrationError::Sqlite(err) | MigrationError::Failed { source: err, .. } => Some(err),
+            MigrationError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for MigrationError {
+    fn from(err: rusqlite::Error) -> Self {
+        MigrationError::Sqlite(err)
+    }
+}
+
+impl From<io::Error> for MigrationError {
+    fn from(err: io::Error) -> Self {
+        MigrationError::Io(err)
+    }
+}
+
+pub type RustStep = Box<dyn Fn(&Transaction) -> rusqlite::Result<()>>;
+
+/// 迁移的一个方向
+pub enum Step {
+    Sql(String),
+    /// 闭包的代码无法计算校验和，只有迁移的名字参与校验
+    Rust(RustStep),
+}
+
+impl Step {
+    fn run(&self, tx: &Transaction) -> rusqlite::Result<()> {
+        match self {
+            Step::Sql(sql) => tx.execute_batch(sql),
+            Step::Rust(run) => run(tx),
+        }
+    }
+}
+
+pub struct Migration {
+    pub version: u32,
+    pub name: String,
+    pub up: Step,
+    pub down: Option<Step>,
+}
+
+impl Migration {
+    pub fn sql(version: u32, name: &str, up: &str, down: Option<&str>) -> Self {
+        Migration {
+            version,
+            name: name.to_string(),
+            up: Step::Sql(up.to_string()),
+            down: down.map(|down| Step::Sql(down.to_string())),
+        }
+    }
+
+    pub fn rust<F>(version: u32, name: &str, up: F, down: Option<RustStep>) -> Self
+    where
+        F: Fn(&Transaction) -> rusqlite::Result<()> + 'static,
+    {
+        Migration {
+            version,
+            name: name.to_string(),
+            up: Step::Rust(Box::new(up)),
+            down: down.map(Step::Rust),
+        }
+    }
+
+    /// `up`步骤的FNV-1a校验和，用16位十六进制表示
+    pub fn checksum(&self) -> String {
+        let text = match &self.up {
+            Step::Sql(sql) => sql.as_str(),
+            Step::Rust(_) => self.name.as_str(),
+        };
+        let hash = text.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        });
+        format!("{:016x}", hash)
+    }
+}
+
+/// 迁移的状态
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum State {
+    Applied,
+    Pending,
+    /// 已经执行，但是内容和记录的校验和不一致
+    Modified,
+    /// 数据库中有记录，但是迁移列表中已经没有这个版本
+    Missing,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationStatus {
+    pub version: u32,
+    pub name: String,
+    pub state: State,
+    pub applied_at: Option<String>,
+}
+
+impl fmt::Display for MigrationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}  {:<30}  {:<8}  {}",
+            self.version,
+            self.name,
+            format!("{:?}", self.state),
+            self.applied_at.as_deref().unwrap_or("")
+        )
+    }
+}
+
+/// 已经执行的迁移记录：版本、名字、校验和、执行时间
+type AppliedRow = (u32, String, String, String);
+
+pub struct Migrator {
+    migrations: Vec<Migration>,
+}
+
+impl Migrator {
+    /// 版本号必须大于0并且不能重复，迁移按版本号排序
+    pub fn new(mut migrations: Vec<Migration>) -> Result<Self, MigrationError> {
+        migrations.sort_by_key(|migration| migration.version);
+        if let Some(migration) = migrations.iter().find(|migration| migration.version == 0) {
+            return Err(MigrationError::Invalid(format!(
+                "{}的版本号不能为0",
+                migration.name
+            )));
+        }
+        if let Some(pair) = migrations
+            .windows(2)
+            .find(|pair| pair[0].version == pair[1].version)
+        {
+            return Err(MigrationError::Invalid(format!(
+                "版本号{}重复",
+                pair[0].version
+            )));
+        }
+        Ok(Migrator { migrations })
+    }
+
+    /// 从目录加载`0001_name.up.sql`和可选的`0001_name.down.sql`
+    pub fn from_dir(dir: &Path) -> Result<Self, MigrationError> {
+        let mut migrations = vec![];
+        let mut entries: Vec<_> = fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let stem = match file_name.strip_suffix(".up.sql") {
+                Some(stem) => stem,
+                None => continue,
+            };
+            let (version, name) = stem
+                .split_once('_')
+                .and_then(|(version, name)| Some((version.parse().ok()?, name)))
+                .ok_or_else(|| {
+                    MigrationError::Invalid(format!("文件名应该是0001_name.up.sql：{}", file_name))
+                })?;
+            let up = fs::read_to_string(entry.path())?;
+            let down_path = dir.join(format!("{}.down.sql", stem));
+            let down = match down_path.exists() {
+                true => Some(fs::read_to_string(down_path)?),
+                false => None,
+            };
+            migrations.push(Migration::sql(version, name, &up, down.as_deref()));
+        }
+
+        Migrator::new(migrations)
+    }
+
+    fn init(conn: &Connection) -> rusqlite::Result<()> {
+        conn.execute_batch(
+            "create table if not exists schema_migrations (
+                version integer primary key,
+                name text not null,
+                checksum text not null,
+                applied_at text not null default current_timestamp
+            )",
+        )
+    }
+
+    /// 还没有执行过迁移时`schema_migrations`不存在，返回空列表而不创建它，
+    /// 这样`status`和`down`不会修改数据库
+    fn applied(conn: &Connection) -> rusqlite::Result<Vec<AppliedRow>> {
+        if !table_exists(conn, "schema_migrations")? {
+            return Ok(vec![]);
+        }
+        let mut stmt = conn.prepare(
+            "select version, name, checksum, applied_at from schema_migrations order by version",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?;
+        rows.collect()
+    }
+
+    pub fn status(&self, conn: &Connection) -> Result<Vec<MigrationStatus>, MigrationError> {
+        let applied = Migrator::applied(conn)?;
+        let mut statuses: Vec<MigrationStatus> = self
+            .migrations
+            .iter()
+            .map(|migration| {
+                let row = applied
+                    .iter()
+                    .find(|(version, ..)| *version == migration.version);
+                let state = match row {
+                    None => State::Pending,
+                    Some((_, _, checksum, _)) if *checksum != migration.checksum() => {
+                        State::Modified
+                    }
+                    Some(_) => State::Applied,
+                };
+                MigrationStatus {
+                    version: migration.version,
+                    name: migration.name.clone(),
+                    state,
+                    applied_at: row.map(|(.., applied_at)| applied_at.clone()),
+                }
+            })
+            .collect();
+
+        statuses.extend(
+            applied
+                .into_iter()
+                .filter(|(version, ..)| {
+                    !self
+                        .migrations
+                        .iter()
+                        .any(|migration| migration.version == *version)
+                })
+                .map(|(version, name, _, applied_at)| MigrationStatus {
+                    version,
+                    name,
+                    state: State::Missing,
+                    applied_at: Some(applied_at),
+                }),
+        );
+        statuses.sort_by_key(|status| status.version);
+        Ok(statuses)
+    }
+
+    /// 执行所有未执行的迁移，`target`不为空时只执行到这个版本，返回执行了的版本
+    pub fn up(
+        &self,
+        conn: &mut Connection,
+        target: Option<u32>,
+    ) -> Result<Vec<u32>, MigrationError> {
+        Migrator::init(conn)?;
+        let statuses = self.status(conn)?;
+        if let Some(modified) = statuses
+            .iter()
+            .find(|status| status.state == State::Modified)
+        {
+            return Err(MigrationError::ChecksumMismatch {
+                version: modified.version,
+                name: modified.name.clone(),
+            });
+        }
+
+        let mut done = vec![];
+        for migration in &self.migrations {
+            let pending = statuses.iter().any(|status| {
+                status.version == migration.version && status.state == State::Pending
+            });
+            if !pending || target.is_some_and(|target| migration.version > target) {
+                continue;
+            }
+
+            let failed = |source| MigrationError::Failed {
+                version: migration.version,
+                name: migration.name.clone(),
+                source,
+            };
+            let tx = conn.transaction()?;
+            migration.up.run(&tx).map_err(failed)?;
+            tx.execute(
+                "insert into schema_migrations (version, name, checksum) values (?1, ?2, ?3)",
+                params![migration.version, migration.name, migration.checksum()],
+            )?;
+            tx.commit()?;
+            done.push(migration.version);
+        }
+        Ok(done)
+    }
+
+    /// 按版本从新到旧回滚`steps`个已经执行的迁移，返回回滚了的版本
+    pub fn down(&self, conn: &mut Connection, steps: usize) -> Result<Vec<u32>, MigrationError> {
+        let applied = Migrator::applied(conn)?;
+        let mut done = vec![];
+
+        for (version, name, ..) in applied.iter().rev().take(steps) {
+            let migration = self
+                .migrations
+                .iter()
+                .find(|migration| migration.version == *version);
+            let down = match migration.and_then(|migration| migration.down.as_ref()) {
+                Some(down) => down,
+                None => {
+                    return Err(MigrationError::Irreversible {
+                        version: *version,
+                        name: name.clone(),
+                    })
+                }
+            };
+
+            let tx = conn.transaction()?;
+            down.run(&tx).map_err(|source| MigrationError::Failed {
+                version: *version,
+                name: name.clone(),
+                source,
+            })?;
+            tx.execute(
+                "delete from schema_migrations where version = ?1",
+                [version],
+            )?;
+            tx.commit()?;
+            done.push(*version);
+        }
+        Ok(done)
+    }
+}
+
+/// `cats.db`的迁移，内容来自`sqlite/migrations`目录
+pub fn cats() -> Migrator {
+    Migrator::new(vec![
+        Migration::sql(
+            1,
+            "create_cat_colors",
+            include_str!("../migrations/0001_create_cat_colors.up.sql"),
+            Some(include_str!(
+                "../migrations/0001_create_cat_colors.down.sql"
+            )),
+        ),
+        Migration::sql(
+            2,
+            "create_cats",
+            include_str!("../migrations/0002_create_cats.up.sql"),
+            Some(include_str!("../migrations/0002_create_cats.down.sql")),
+        ),
+    ])
+    .expect("内置的迁移版本号不重复")
+}
+
+fn table_exists(conn: &Connection, table: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "select count(*) from sqlite_master where type = 'table' and name = ?1",
+        [table],
+        |row| row.get::<_, i64>(0),
+    )
+    .map(|count| count > 0)
+}
+
+/// # 版本化的数据库迁移
+/// 在内存数据库中演示`up`、`status`、`down`，以及失败的迁移和被修改的迁移。
+pub fn run_migrations() -> Result<(), MigrationError> {
+    let mut conn = Connection::open_in_memory()?;
+    let migrator = cats();
+
+    assert_eq!(migrator.up(&mut conn, Some(1))?, [1]);
+    assert!(table_exists(&conn, "cat_colors")? && !table_exists(&conn, "cats")?);
+    assert_eq!(migrator.up(&mut conn, None)?, [2]);
+    assert!(migrator.up(&mut conn, None)?.is_empty());
+    for status in migrator.status(&conn)? {
+        println!("{}", status);
+    }
+
+    // Rust闭包迁移，以及一个会失败的迁移：失败的迁移不留下表也不留下记录
+    let mut migrations = cats().migrations;
+    migrations.push(Migration::rust(
+        3,
+        "seed_colors",
+        |tx| {
+            for color in ["Blue", "Black"] {
+                tx.execute("insert into cat_colors (name) values (?1)", [color])?;
+            }
+            Ok(())
+        },
+        Some(Box::new(|tx| {
+            tx.execute("delete from cat_colors where name in ('Blue', 'Black')", [])
+                .map(|_| ())
+        })),
+    ));
+    migrations.push(Migration::sql(
+        4,
+        "broken",
+        "create table toys (id integer primary key); insert into missing_table values (1);",
+        None,
+    ));
+    let extended = Migrator::new(migrations)?;
+    match extended.up(&mut conn, None) {
+        Err(MigrationError::Failed { version: 4, .. }) => {}
+        other => panic!("迁移4应该失败：{:?}", other),
+    }
+    assert!(!table_exists(&conn, "toys")?);
+    let states: Vec<State> = extended
+        .status(&conn)?
+        .into_iter()
+        .map(|status| status.state)
+        .collect();
+    assert_eq!(
+        states,
+        [
+            State::Applied,
+            State::Applied,
+            State::Applied,
+            State::Pending
+        ]
+    );
+
+    // 已经执行的迁移被修改后拒绝继续执行
+    let edited = Migrator::new(vec![Migration::sql(
+        1,
+        "create_cat_colors",
+        "create table cat_colors (id integer primary key)",
+        None,
+    )])?;
+    match edited.up(&mut conn, None) {
+        Err(err @ MigrationError::ChecksumMismatch { version: 1, .. }) => println!("{}", err),
+        other => panic!("应该检测到校验和不一致：{:?}", other),
+    }
+
+    // 回滚最新的两个版本：3和2
+    assert_eq!(extended.down(&mut conn, 2)?, [3, 2]);
+    assert!(!table_exists(&conn, "cats")?);
+    let colors: i64 = conn.query_row("select count(*) from cat_colors", [], |row| row.get(0))?;
+    assert_eq!(colors, 0);
+    assert_eq!(cats().status(&conn)?[1].state, State::Pending);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_and_down_leave_a_new_database_untouched() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let migrator = cats();
+        let states: Vec<State> = migrator
+            .status(&conn)
+            .unwrap()
+            .into_iter()
+            .map(|status| status.state)
+            .collect();
+        assert_eq!(states, [State::Pending, State::Pending]);
+        assert!(migrator.down(&mut conn, 1).unwrap().is_empty());
+        assert!(!table_exists(&conn, "schema_migrations").unwrap());
+
+        assert_eq!(migrator.up(&mut conn, None).unwrap(), [1, 2]);
+        assert!(table_exists(&conn, "schema_migrations").unwrap());
+    }
+}
@@ -0,0 +1,395 @@
+//! # 连接池
+//! SQLite同一时间只允许一个写事务，所以连接池分为一个写连接和多个只读连接：
+//! - 数据库设置为WAL模式，读和写互不阻塞；
+//! - 每个连接都设置了`busy_timeout`，短暂的锁冲突由SQLite自己等待，不会变成`database is locked`错误；
+//! - 取连接时最多等待`checkout_timeout`，取出的连接先用`select 1`检查，失败时重新打开。
+//!
+//! 取出的`PooledConnection`在drop时自动归还，可以在线程之间共享`Pool`的克隆。
+use crate::migrations;
+use crate::repository::{CatRepo, Page};
+use rayon::prelude::*;
+use rusqlite::{Connection, OpenFlags};
+use std::error::Error;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub enum PoolError {
+    Sqlite(rusqlite::Error),
+    /// 在超时时间内没有空闲的连接
+    Timeout(Duration),
+    /// 数据库无法切换到WAL模式，例如内存数据库
+    JournalMode(String),
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolError::Sqlite(err) => write!(f, "数据库错误：{}", err),
+            PoolError::Timeout(timeout) => write!(f, "等待{:?}后仍然没有空闲的连接", timeout),
+            PoolError::JournalMode(mode) => write!(f, "无法切换到WAL模式，当前为{}", mode),
+        }
+    }
+}
+
+impl Error for PoolError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PoolError::Sqlite(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for PoolError {
+    fn from(err: rusqlite::Error) -> Self {
+        PoolError::Sqlite(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PoolConfig {
+    /// 只读连接的数量
+    pub readers: usize,
+    pub checkout_timeout: Duration,
+    pub busy_timeout: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            readers: 4,
+            checkout_timeout: Duration::from_secs(5),
+            busy_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// 一组同类的连接以及等待空闲连接的条件变量
+struct Slots {
+    idle: Mutex<Vec<Connection>>,
+    returned: Condvar,
+    read_only: bool,
+}
+
+struct Inner {
+    path: PathBuf,
+    config: PoolConfig,
+    writer: Slots,
+    readers: Slots,
+}
+
+#[derive(Clone)]
+pub struct Pool {
+    inner: Arc<Inner>,
+}
+
+impl Inner {
+    fn open(&self, read_only: bool) -> rusqlite::Result<Connection> {
+        let flags = match read_only {
+            true => OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            false => OpenFlags::default(),
+        };
+        let conn = Connection::open_with_flags(&self.path, flags)?;
+        conn.busy_timeout(self.config.busy_timeout)?;
+        Ok(conn)
+    }
+}
+
+impl Pool {
+    /// 打开数据库并切换到WAL模式
+    pub fn open<P: AsRef<Path>>(path: P, config: PoolConfig) -> Result<Pool, PoolError> {
+        let slots = |read_only| Slots {
+            idle: Mutex::new(vec![]),
+            returned: Condvar::new(),
+            read_only,
+        };
+        let inner = Inner {
+            path: path.as_ref().to_path_buf(),
+            config,
+            writer: slots(false),
+            readers: slots(true),
+        };
+
+        let writer = inner.open(false)?;
+        let mode: String = writer.query_row("pragma journal_mode = wal", [], |row| row.get(0))?;
+        if !mode.eq_ignore_ascii_case("wal") {
+            return Err(PoolError::JournalMode(mode));
+        }
+        writer.execute_batch("pragma synchronous = normal")?;
+        inner.writer.idle.lock().unwrap().push(writer);
+
+        let readers = (0..config.readers.max(1))
+            .map(|_| inner.open(true))
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        *inner.readers.idle.lock().unwrap() = readers;
+
+        Ok(Pool {
+            inner: Arc::new(inner),
+        })
+    }
+
+    /// 取出唯一的写连接
+    pub fn write(&self) -> Result<PooledConnection, PoolError> {
+        self.checkout(false)
+    }
+
+    /// 取出一个只读连接
+    pub fn read(&self) -> Result<PooledConnection, PoolError> {
+        self.checkout(true)
+    }
+
+    fn checkout(&self, read_only: bool) -> Result<PooledConnection, PoolError> {
+        let slots = self.slots(read_only);
+        let timeout = self.inner.config.checkout_timeout;
+        let deadline = Instant::now() + timeout;
+
+        let mut idle = slots.idle.lock().unwrap();
+        let conn = loop {
+            if let Some(conn) = idle.pop() {
+                break conn;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(PoolError::Timeout(timeout));
+            }
+            idle = slots.returned.wait_timeout(idle, deadline - now).unwrap().0;
+        };
+        drop(idle);
+
+        // 健康检查失败的连接直接丢弃，换一个新打开的
+        let conn = match conn.query_row("select 1", [], |row| row.get::<_, i64>(0)) {
+            Ok(_) => conn,
+            Err(_) => match self.inner.open(slots.read_only) {
+                Ok(conn) => conn,
+                Err(err) => {
+                    self.checkin(read_only, conn);
+                    return Err(err.into());
+                }
+            },
+        };
+        Ok(PooledConnection {
+            conn: Some(conn),
+            pool: self.clone(),
+            read_only,
+        })
+    }
+
+    fn slots(&self, read_only: bool) -> &Slots {
+        match read_only {
+            true => &self.inner.readers,
+            false => &self.inner.writer,
+        }
+    }
+
+    fn checkin(&self, read_only: bool, conn: Connection) {
+        let slots = self.slots(read_only);
+        slots.idle.lock().unwrap().push(conn);
+        slots.returned.notify_one();
+    }
+
+    /// 当前空闲的只读连接和写连接数量
+    pub fn idle(&self) -> (usize, usize) {
+        (
+            self.inner.readers.idle.lock().unwrap().len(),
+            self.inner.writer.idle.lock().unwrap().len(),
+        )
+    }
+}
+
+/// 从连接池取出的连接，drop时归还
+pub struct PooledConnection {
+    conn: Option<Connection>,
+    pool: Pool,
+    read_only: bool,
+}
+
+impl Deref for PooledConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().expect("连接在归还之前一直存在")
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.conn.as_mut().expect("连接在归还之前一直存在")
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.pool.checkin(self.read_only, conn);
+        }
+    }
+}
+
+/// # 用rayon从多个线程同时读写数据库
+/// 400个任务中每4个有1个写入，其余的读取，所有任务都不应该遇到`database is locked`。
+pub fn hammer_with_rayon() -> Result<(), Box<dyn Error>> {
+    let path = std::env::temp_dir().join(format!("pool-{}.db", std::process::id()));
+    let pool = Pool::open(&path, PoolConfig::default())?;
+    migrations::cats().up(&mut *pool.write()?, None)?;
+
+    let results: Vec<Result<i64, PoolError>> = (0..400)
+        .into_par_iter()
+        .map(|i| {
+            if i % 4 == 0 {
+                let conn = pool.write()?;
+                let cat = CatRepo::new(&conn).create(&format!("cat-{}", i), "Grey")?;
+                Ok(cat.id)
+            } else {
+                let conn = pool.read()?;
+                let repo = CatRepo::new(&conn);
+                let cats = repo.list(Page::new(0, 10))?;
+                Ok(cats.len() as i64 + repo.count()?)
+            }
+        })
+        .collect();
+
+    let errors: Vec<String> = results
+        .iter()
+        .filter_map(|result| result.as_ref().err().map(|err| err.to_string()))
+        .collect();
+    println!("400个并发任务，错误{}个", errors.len());
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert_eq!(CatRepo::new(&*pool.read()?).count()?, 100);
+    assert_eq!(pool.idle(), (PoolConfig::default().readers, 1));
+
+    // 只读连接不能写入
+    assert!(pool.read()?.execute("delete from cats", []).is_err());
+
+    // 写连接被占用时，另一个取写连接的请求按时超时
+    let busy = Pool::open(
+        &path,
+        PoolConfig {
+            checkout_timeout: Duration::from_millis(20),
+            ..PoolConfig::default()
+        },
+    )?;
+    let held = busy.write()?;
+    match busy.write() {
+        Err(PoolError::Timeout(_)) => {}
+        other => panic!("应该超时：{:?}", other.map(|_| ())),
+    }
+    drop(held);
+    assert!(busy.write().is_ok());
+
+    drop((pool, busy));
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use tempfile::TempDir;
+
+    fn open(config: PoolConfig) -> (TempDir, Pool) {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = Pool::open(dir.path().join("cats.db"), config).unwrap();
+        migrations::cats()
+            .up(&mut pool.write().unwrap(), None)
+            .unwrap();
+        (dir, pool)
+    }
+
+    fn quick_timeout() -> PoolConfig {
+        PoolConfig {
+            readers: 2,
+            checkout_timeout: Duration::from_millis(20),
+            ..PoolConfig::default()
+        }
+    }
+
+    #[test]
+    fn rayon_workers_never_see_locked_database() {
+        let (_dir, pool) = open(PoolConfig::default());
+
+        let errors: Vec<String> = (0..800)
+            .into_par_iter()
+            .filter_map(|i| {
+                let result: Result<(), PoolError> = if i % 4 == 0 {
+                    pool.write().and_then(|conn| {
+                        CatRepo::new(&conn).create(&format!("cat-{}", i), "Grey")?;
+                        Ok(())
+                    })
+                } else {
+                    pool.read().and_then(|conn| {
+                        CatRepo::new(&conn).list(Page::new(0, 10))?;
+                        Ok(())
+                    })
+                };
+                result.err().map(|err| err.to_string())
+            })
+            .collect();
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(CatRepo::new(&pool.read().unwrap()).count().unwrap(), 200);
+        assert_eq!(pool.idle(), (PoolConfig::default().readers, 1));
+    }
+
+    #[test]
+    fn checkout_times_out_when_all_connections_are_taken() {
+        let (_dir, pool) = open(quick_timeout());
+
+        let writer = pool.write().unwrap();
+        let started = Instant::now();
+        assert!(matches!(pool.write(), Err(PoolError::Timeout(_))));
+        assert!(started.elapsed() >= Duration::from_millis(20));
+        drop(writer);
+        assert!(pool.write().is_ok());
+
+        let readers = (pool.read().unwrap(), pool.read().unwrap());
+        assert!(matches!(pool.read(), Err(PoolError::Timeout(_))));
+        drop(readers);
+        assert_eq!(pool.idle(), (2, 1));
+    }
+
+    #[test]
+    fn waiting_checkout_gets_returned_connection() {
+        let (_dir, pool) = open(PoolConfig::default());
+
+        let writer = pool.write().unwrap();
+        let waiting = {
+            let pool = pool.clone();
+            thread::spawn(move || pool.write().map(|_| ()))
+        };
+        thread::sleep(Duration::from_millis(50));
+        drop(writer);
+        assert!(waiting.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn readers_cannot_write() {
+        let (_dir, pool) = open(PoolConfig::default());
+        assert!(pool
+            .read()
+            .unwrap()
+            .execute("delete from cats", [])
+            .is_err());
+        assert_eq!(
+            pool.write()
+                .unwrap()
+                .query_row("pragma journal_mode", [], |row| row.get::<_, String>(0))
+                .unwrap(),
+            "wal"
+        );
+    }
+
+    #[test]
+    fn memory_database_is_rejected() {
+        assert!(matches!(
+            Pool::open(":memory:", PoolConfig::default()),
+            Err(PoolError::JournalMode(_))
+        ));
+    }
+}
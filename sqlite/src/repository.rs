@@ -0,0 +1,359 @@
+//! # 猫和颜色的数据访问
+//! `FromRow`把查询结果的一行转换为结构体，`CatRepo`封装了`cats`和`cat_colors`两张表的增删改查。
+//! 颜色的`id`作为整数保存和传递，不再转换为字符串参数。
+use crate::migrations;
+use rusqlite::{params, Connection, OptionalExtension, Params, Result, Row};
+use std::error::Error;
+
+/// 从查询结果的一行构造结构体，列按名字读取
+pub trait FromRow: Sized {
+    fn from_row(row: &Row) -> Result<Self>;
+}
+
+/// 执行查询并把每一行转换为`T`
+pub fn query_all<T: FromRow, P: Params>(conn: &Connection, sql: &str, params: P) -> Result<Vec<T>> {
+    let mut stmt = conn.prepare_cached(sql)?;
+    let rows = stmt.query_map(params, |row| T::from_row(row))?;
+    rows.collect()
+}
+
+pub fn query_one<T: FromRow, P: Params>(
+    conn: &Connection,
+    sql: &str,
+    params: P,
+) -> Result<Option<T>> {
+    let mut stmt = conn.prepare_cached(sql)?;
+    stmt.query_row(params, |row| T::from_row(row)).optional()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CatColor {
+    pub id: i64,
+    pub name: String,
+}
+
+impl FromRow for CatColor {
+    fn from_row(row: &Row) -> Result<Self> {
+        Ok(CatColor {
+            id: row.get("id")?,
+            name: row.get("name")?,
+        })
+    }
+}
+
+/// 一只猫以及它的颜色名字
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cat {
+    pub id: i64,
+    pub name: String,
+    pub color_id: i64,
+    pub color: String,
+}
+
+impl FromRow for Cat {
+    fn from_row(row: &Row) -> Result<Self> {
+        Ok(Cat {
+            id: row.get("id")?,
+            name: row.get("name")?,
+            color_id: row.get("color_id")?,
+            color: row.get("color")?,
+        })
+    }
+}
+
+/// 分页参数，`number`从0开始
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    pub number: u32,
+    pub size: u32,
+}
+
+impl Page {
+    pub fn new(number: u32, size: u32) -> Self {
+        Page { number, size }
+    }
+
+    pub fn next(self) -> Self {
+        Page {
+            number: self.number + 1,
+            ..self
+        }
+    }
+
+    fn limit_offset(self) -> (i64, i64) {
+        (self.size as i64, self.number as i64 * self.size as i64)
+    }
+}
+
+impl Default for Page {
+    fn default() -> Self {
+        Page::new(0, 20)
+    }
+}
+
+const SELECT_CATS: &str =
+    "select c.id, c.name, c.color_id, cc.name as color from cats c join cat_colors cc on cc.id = c.color_id";
+
+pub struct CatRepo<'c> {
+    conn: &'c Connection,
+}
+
+impl<'c> CatRepo<'c> {
+    pub fn new(conn: &'c Connection) -> Self {
+        CatRepo { conn }
+    }
+
+    /// 按名字查找颜色，不存在时插入
+    pub fn color(&self, name: &str) -> Result<CatColor> {
+        self.conn.execute(
+            "insert into cat_colors (name) values (?1) on conflict (name) do nothing",
+            [name],
+        )?;
+        self.conn.query_row(
+            "select id, name from cat_colors where name = ?1",
+            [name],
+            CatColor::from_row,
+        )
+    }
+
+    pub fn colors(&self) -> Result<Vec<CatColor>> {
+        query_all(
+            self.conn,
+            "select id, name from cat_colors order by name",
+            [],
+        )
+    }
+
+    pub fn create(&self, name: &str, color: &str) -> Result<Cat> {
+        let color = self.color(color)?;
+        self.conn.execute(
+            "insert into cats (name, color_id) values (?1, ?2)",
+            params![name, color.id],
+        )?;
+        Ok(Cat {
+            id: self.conn.last_insert_rowid(),
+            name: name.to_string(),
+            color_id: color.id,
+            color: color.name,
+        })
+    }
+
+    pub fn find(&self, id: i64) -> Result<Option<Cat>> {
+        query_one(self.conn, &format!("{} where c.id = ?1", SELECT_CATS), [id])
+    }
+
+    pub fn list(&self, page: Page) -> Result<Vec<Cat>> {
+        let (limit, offset) = page.limit_offset();
+        query_all(
+            self.conn,
+            &format!("{} order by c.id limit ?1 offset ?2", SELECT_CATS),
+            [limit, offset],
+        )
+    }
+
+    pub fn find_by_color(&self, color: &str, page: Page) -> Result<Vec<Cat>> {
+        let (limit, offset) = page.limit_offset();
+        query_all(
+            self.conn,
+            &format!(
+                "{} where cc.name = ?1 order by c.id limit ?2 offset ?3",
+                SELECT_CATS
+            ),
+            params![color, limit, offset],
+        )
+    }
+
+    pub fn count(&self) -> Result<i64> {
+        self.conn
+            .query_row("select count(*) from cats", [], |row| row.get(0))
+    }
+
+    /// 按`cat.id`更新名字和颜色，颜色不存在时自动创建；返回是否找到了这只猫
+    pub fn update(&self, cat: &Cat) -> Result<bool> {
+        let color = self.color(&cat.color)?;
+        let changed = self.conn.execute(
+            "update cats set name = ?1, color_id = ?2 where id = ?3",
+            params![cat.name, color.id, cat.id],
+        )?;
+        Ok(changed > 0)
+    }
+
+    pub fn delete(&self, id: i64) -> Result<bool> {
+        let changed = self.conn.execute("delete from cats where id = ?1", [id])?;
+        Ok(changed > 0)
+    }
+}
+
+/// # 使用`CatRepo`访问数据
+/// 在内存数据库中验证创建、按颜色分页查询、更新和删除。
+pub fn use_cat_repository() -> std::result::Result<(), Box<dyn Error>> {
+    let mut conn = Connection::open_in_memory()?;
+    migrations::cats().up(&mut conn, None)?;
+    let repo = CatRepo::new(&conn);
+
+    let tigger = repo.create("Tigger", "Blue")?;
+    repo.create("Oreo", "Black")?;
+    for name in ["Sammy", "Smokey", "Bluebell", "Misty"] {
+        repo.create(name, "Blue")?;
+    }
+    assert_eq!(repo.count()?, 6);
+    assert_eq!(repo.colors()?.len(), 2);
+    assert_eq!(repo.find(tigger.id)?, Some(tigger.clone()));
+
+    // 每页2只，蓝色的猫一共5只
+    let page = Page::new(0, 2);
+    let names = |cats: Vec<Cat>| cats.into_iter().map(|cat| cat.name).collect::<Vec<_>>();
+    assert_eq!(
+        names(repo.find_by_color("Blue", page)?),
+        ["Tigger", "Sammy"]
+    );
+    assert_eq!(
+        names(repo.find_by_color("Blue", page.next())?),
+        ["Smokey", "Bluebell"]
+    );
+    assert_eq!(
+        names(repo.find_by_color("Blue", page.next().next())?),
+        ["Misty"]
+    );
+    assert!(repo.find_by_color("Green", page)?.is_empty());
+
+    let tabby = Cat {
+        color: "Tabby".to_string(),
+        ..tigger.clone()
+    };
+    assert!(repo.update(&tabby)?);
+    let updated = repo.find(tigger.id)?.unwrap();
+    assert_eq!(updated.color, "Tabby");
+    assert_ne!(updated.color_id, tigger.color_id);
+
+    assert!(repo.delete(tigger.id)?);
+    assert!(!repo.delete(tigger.id)?);
+    assert!(!repo.update(&tabby)?);
+    assert_eq!(repo.find(tigger.id)?, None);
+
+    for cat in repo.list(Page::default())? {
+        println!("{:?}", cat);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::cats().up(&mut conn, None).unwrap();
+        conn
+    }
+
+    fn names(cats: Vec<Cat>) -> Vec<String> {
+        cats.into_iter().map(|cat| cat.name).collect()
+    }
+
+    #[test]
+    fn create_and_find() {
+        let conn = database();
+        let repo = CatRepo::new(&conn);
+
+        let tigger = repo.create("Tigger", "Blue").unwrap();
+        assert_eq!(repo.find(tigger.id).unwrap(), Some(tigger.clone()));
+        assert_eq!(repo.find(tigger.id + 1).unwrap(), None);
+        assert_eq!(repo.count().unwrap(), 1);
+
+        // 同名的颜色只保存一次
+        let sammy = repo.create("Sammy", "Blue").unwrap();
+        assert_eq!(sammy.color_id, tigger.color_id);
+        assert_eq!(
+            repo.colors().unwrap(),
+            [CatColor {
+                id: tigger.color_id,
+                name: "Blue".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn foreign_key_is_stored_as_integer() {
+        let conn = database();
+        let repo = CatRepo::new(&conn);
+        let oreo = repo.create("Oreo", "Black").unwrap();
+
+        let kind: String = conn
+            .query_row(
+                "select typeof(color_id) from cats where id = ?1",
+                [oreo.id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(kind, "integer");
+    }
+
+    #[test]
+    fn find_by_color_pages() {
+        let conn = database();
+        let repo = CatRepo::new(&conn);
+        repo.create("Tigger", "Blue").unwrap();
+        repo.create("Oreo", "Black").unwrap();
+        for name in ["Sammy", "Smokey", "Bluebell"] {
+            repo.create(name, "Blue").unwrap();
+        }
+
+        let page = Page::new(0, 2);
+        assert_eq!(
+            names(repo.find_by_color("Blue", page).unwrap()),
+            ["Tigger", "Sammy"]
+        );
+        assert_eq!(
+            names(repo.find_by_color("Blue", page.next()).unwrap()),
+            ["Smokey", "Bluebell"]
+        );
+        assert!(repo
+            .find_by_color("Blue", page.next().next())
+            .unwrap()
+            .is_empty());
+        assert!(repo.find_by_color("Green", page).unwrap().is_empty());
+        assert_eq!(
+            names(repo.list(Page::new(1, 3)).unwrap()),
+            ["Smokey", "Bluebell"]
+        );
+    }
+
+    #[test]
+    fn update_changes_name_and_color() {
+        let conn = database();
+        let repo = CatRepo::new(&conn);
+        let misty = repo.create("Misty", "Grey").unwrap();
+
+        let renamed = Cat {
+            name: "Misty II".to_string(),
+            color: "Tabby".to_string(),
+            ..misty.clone()
+        };
+        assert!(repo.update(&renamed).unwrap());
+        let found = repo.find(misty.id).unwrap().unwrap();
+        assert_eq!(found.name, "Misty II");
+        assert_eq!(found.color, "Tabby");
+        assert_ne!(found.color_id, misty.color_id);
+
+        let missing = Cat {
+            id: misty.id + 100,
+            ..renamed
+        };
+        assert!(!repo.update(&missing).unwrap());
+    }
+
+    #[test]
+    fn delete_removes_only_that_cat() {
+        let conn = database();
+        let repo = CatRepo::new(&conn);
+        let tigger = repo.create("Tigger", "Blue").unwrap();
+        let sammy = repo.create("Sammy", "Blue").unwrap();
+
+        assert!(repo.delete(tigger.id).unwrap());
+        assert!(!repo.delete(tigger.id).unwrap());
+        assert_eq!(repo.find(tigger.id).unwrap(), None);
+        assert_eq!(repo.list(Page::default()).unwrap(), [sammy]);
+    }
+}
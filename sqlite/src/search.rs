@@ -0,0 +1,598 @@
+//! # FTS5全文搜索
+//! `FtsIndex`为一张普通的表（内容表）创建外部内容的FTS5虚拟表，
+//! 内容表的插入、删除和更新通过触发器同步到索引，索引本身不重复保存文本。
+//!
+//! `Query`用来组合词、短语、前缀和`AND`/`OR`/`NOT`，生成FTS5的`MATCH`表达式，
+//! 搜索结果按`bm25`排序，并用`snippet()`截取命中的片段。
+//!
+//! 索引会在数据库中留下虚拟表和触发器，所以只在`create`/`ensure`时创建；
+//! 已有的索引列和要求的列不同时返回`SearchError::Mismatch`，不会使用列不对的索引。
+use crate::migrations;
+use crate::repository::CatRepo;
+use rusqlite::{params, Connection};
+use std::error::Error;
+use std::fmt;
+
+/// 片段中命中词的开始和结束标记，用`Hit::fragments`拆分
+pub const HIGHLIGHT_OPEN: &str = "\u{2}";
+pub const HIGHLIGHT_CLOSE: &str = "\u{3}";
+
+fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+fn quote_string(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+#[derive(Debug)]
+pub enum SearchError {
+    Sqlite(rusqlite::Error),
+    /// 索引还没有创建
+    NotInstalled(String),
+    /// 已有的索引建立在其他列上
+    Mismatch {
+        index: String,
+        existing: Vec<String>,
+        requested: Vec<String>,
+    },
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchError::Sqlite(err) => write!(f, "数据库错误：{}", err),
+            SearchError::NotInstalled(index) => write!(f, "索引{}不存在，需要先创建", index),
+            SearchError::Mismatch {
+                index,
+                existing,
+                requested,
+            } => write!(
+                f,
+                "索引{}建立在{}列上，不是要求的{}列",
+                index,
+                existing.join(","),
+                requested.join(",")
+            ),
+        }
+    }
+}
+
+impl Error for SearchError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SearchError::Sqlite(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for SearchError {
+    fn from(err: rusqlite::Error) -> Self {
+        SearchError::Sqlite(err)
+    }
+}
+
+/// 内容表上的全文索引，内容表必须有整数主键
+#[derive(Debug, Clone)]
+pub struct FtsIndex {
+    pub content: String,
+    pub key: String,
+    pub columns: Vec<String>,
+}
+
+impl FtsIndex {
+    pub fn new(content: &str, key: &str, columns: &[&str]) -> Self {
+        FtsIndex {
+            content: content.to_string(),
+            key: key.to_string(),
+            columns: columns.iter().map(|column| column.to_string()).collect(),
+        }
+    }
+
+    /// 虚拟表的名字：`<内容表>_fts`
+    pub fn name(&self) -> String {
+        format!("{}_fts", self.content)
+    }
+
+    pub fn exists(&self, conn: &Connection) -> rusqlite::Result<bool> {
+        conn.query_row(
+            "select count(*) from sqlite_master where type = 'table' and name = ?1",
+            [self.name()],
+            |row| row.get::<_, i64>(0),
+        )
+        .map(|count| count > 0)
+    }
+
+    /// 已有索引的列，索引不存在时返回`None`
+    pub fn installed_columns(&self, conn: &Connection) -> rusqlite::Result<Option<Vec<String>>> {
+        if !self.exists(conn)? {
+            return Ok(None);
+        }
+        let mut stmt = conn.prepare(&format!(
+            "pragma table_info({})",
+            quote_identifier(&self.name())
+        ))?;
+        let columns = stmt.query_map([], |row| row.get("name"))?;
+        columns.collect::<rusqlite::Result<_>>().map(Some)
+    }
+
+    /// 检查索引已经创建并且建立在要求的列上
+    pub fn check(&self, conn: &Connection) -> Result<(), SearchError> {
+        match self.installed_columns(conn)? {
+            None => Err(SearchError::NotInstalled(self.name())),
+            Some(existing) if existing != self.columns => Err(SearchError::Mismatch {
+                index: self.name(),
+                existing,
+                requested: self.columns.clone(),
+            }),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// 创建虚拟表和三个触发器，并用内容表中已有的数据重建索引
+    pub fn create(&self, conn: &Connection) -> rusqlite::Result<()> {
+        let fts = quote_identifier(&self.name());
+        let content = quote_identifier(&self.content);
+        let key = quote_identifier(&self.key);
+        let columns: Vec<String> = self
+            .columns
+            .iter()
+            .map(|column| quote_identifier(column))
+            .collect();
+        let values = |prefix: &str| {
+            columns
+                .iter()
+                .map(|column| format!("{}.{}", prefix, column))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        // 外部内容表删除索引中的行时，需要提供原来的值
+        let delete = format!(
+            "insert into {fts} ({fts}, rowid, {columns}) values ('delete', old.{key}, {old});",
+            fts = fts,
+            columns = columns.join(", "),
+            key = key,
+            old = values("old"),
+        );
+        let insert = format!(
+            "insert into {fts} (rowid, {columns}) values (new.{key}, {new});",
+            fts = fts,
+            columns = columns.join(", "),
+            key = key,
+            new = values("new"),
+        );
+        let trigger = |suffix: &str| quote_identifier(&format!("{}_{}", self.name(), suffix));
+
+        conn.execute_batch(&format!(
+            "create virtual table {fts} using fts5({columns}, content={content_name}, content_rowid={key_name});
+            create trigger {ai} after insert on {content} begin {insert} end;
+            create trigger {ad} after delete on {content} begin {delete} end;
+            create trigger {au} after update on {content} begin {delete} {insert} end;
+            insert into {fts} ({fts}) values ('rebuild');",
+            fts = fts,
+            columns = columns.join(", "),
+            content_name = quote_string(&self.content),
+            key_name = quote_string(&self.key),
+            content = content,
+            ai = trigger("ai"),
+            ad = trigger("ad"),
+            au = trigger("au"),
+            insert = insert,
+            delete = delete,
+        ))
+    }
+
+    /// 索引不存在时创建，已经存在时检查它的列
+    pub fn ensure(&self, conn: &Connection) -> Result<(), SearchError> {
+        match self.check(conn) {
+            Err(SearchError::NotInstalled(_)) => Ok(self.create(conn)?),
+            result => result,
+        }
+    }
+
+    pub fn drop_index(&self, conn: &Connection) -> rusqlite::Result<()> {
+        let trigger = |suffix: &str| quote_identifier(&format!("{}_{}", self.name(), suffix));
+        conn.execute_batch(&format!(
+            "drop trigger if exists {}; drop trigger if exists {}; drop trigger if exists {};
+            drop table if exists {};",
+            trigger("ai"),
+            trigger("ad"),
+            trigger("au"),
+            quote_identifier(&self.name()),
+        ))
+    }
+
+    /// 按`bm25`排序返回最多`limit`条结果，`rank`越小越相关
+    pub fn search(
+        &self,
+        conn: &Connection,
+        query: &Query,
+        limit: u32,
+    ) -> rusqlite::Result<Vec<Hit>> {
+        let fts = quote_identifier(&self.name());
+        let sql = format!(
+            "select rowid, bm25({fts}), snippet({fts}, -1, ?2, ?3, '…', 12)
+            from {fts} where {fts} match ?1 order by bm25({fts}) limit ?4",
+            fts = fts
+        );
+        let mut stmt = conn.prepare_cached(&sql)?;
+        let hits = stmt.query_map(
+            params![query.to_string(), HIGHLIGHT_OPEN, HIGHLIGHT_CLOSE, limit],
+            |row| {
+                Ok(Hit {
+                    rowid: row.get(0)?,
+                    rank: row.get(1)?,
+                    snippet: row.get(2)?,
+                })
+            },
+        )?;
+        hits.collect()
+    }
+}
+
+/// FTS5查询表达式
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    /// 单个词
+    Term(String),
+    /// 按顺序相邻的几个词
+    Phrase(String),
+    /// 以这个前缀开头的词
+    Prefix(String),
+    /// 只在某一列中匹配
+    Column(String, Box<Query>),
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>, Box<Query>),
+    /// 原样使用的FTS5查询语法，例如命令行输入
+    Raw(String),
+}
+
+impl Query {
+    pub fn term(text: &str) -> Self {
+        Query::Term(text.to_string())
+    }
+
+    pub fn phrase(text: &str) -> Self {
+        Query::Phrase(text.to_string())
+    }
+
+    pub fn prefix(text: &str) -> Self {
+        Query::Prefix(text.to_string())
+    }
+
+    pub fn raw(text: &str) -> Self {
+        Query::Raw(text.to_string())
+    }
+
+    pub fn column(self, column: &str) -> Self {
+        Query::Column(column.to_string(), Box::new(self))
+    }
+
+    pub fn and(self, other: Query) -> Self {
+        Query::And(Box::new(self), Box::new(other))
+    }
+
+    pub fn or(self, other: Query) -> Self {
+        Query::Or(Box::new(self), Box::new(other))
+    }
+
+    /// 匹配`self`但不匹配`other`
+    pub fn not(self, other: Query) -> Self {
+        Query::Not(Box::new(self), Box::new(other))
+    }
+}
+
+/// FTS5的字符串用双引号，内部的双引号写两次
+fn fts_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\"\""))
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Query::Term(text) | Query::Phrase(text) => write!(f, "{}", fts_string(text)),
+            Query::Prefix(text) => write!(f, "{}*", fts_string(text)),
+            Query::Column(column, query) => write!(f, "{} : ({})", fts_string(column), query),
+            Query::And(left, right) => write!(f, "({} AND {})", left, right),
+            Query::Or(left, right) => write!(f, "({} OR {})", left, right),
+            Query::Not(left, right) => write!(f, "({} NOT {})", left, right),
+            Query::Raw(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+/// 一条搜索结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+    /// 内容表中的主键
+    pub rowid: i64,
+    pub rank: f64,
+    pub snippet: String,
+}
+
+impl Hit {
+    /// 把片段拆分为`(文本, 是否命中)`
+    pub fn fragments(&self) -> Vec<(&str, bool)> {
+        let mut fragments = vec![];
+        for (index, part) in self.snippet.split(HIGHLIGHT_OPEN).enumerate() {
+            match part.split_once(HIGHLIGHT_CLOSE) {
+                Some((hit, rest)) if index > 0 => {
+                    fragments.push((hit, true));
+                    fragments.push((rest, false));
+                }
+                _ => fragments.push((part, false)),
+            }
+        }
+        fragments.retain(|(text, _)| !text.is_empty());
+        fragments
+    }
+
+    /// 用`open`和`close`包围命中的词
+    pub fn highlight(&self, open: &str, close: &str) -> String {
+        self.snippet
+            .replace(HIGHLIGHT_OPEN, open)
+            .replace(HIGHLIGHT_CLOSE, close)
+    }
+}
+
+const NOTES: [(&str, &str); 5] = [
+    (
+        "Tigger",
+        "Bounces on the sofa every morning and chases the red laser dot",
+    ),
+    (
+        "Oreo",
+        "Sleeps all day in the laundry basket, a very lazy black cat",
+    ),
+    (
+        "Sammy",
+        "Chases mice in the garden and brings them to the kitchen door",
+    ),
+    (
+        "Smokey",
+        "Grey fur, likes the warm laundry fresh from the dryer",
+    ),
+    (
+        "Misty",
+        "A shy cat who hides under the bed when visitors arrive",
+    ),
+];
+
+/// # 用FTS5搜索猫的备注
+/// 在内存数据库中验证短语、前缀、布尔查询，以及触发器对插入、更新和删除的同步。
+pub fn search_cat_notes() -> Result<(), Box<dyn Error>> {
+    let mut conn = Connection::open_in_memory()?;
+    migrations::cats().up(&mut conn, None)?;
+    conn.execute_batch(
+        "create table cat_notes (
+            id integer primary key,
+            cat_id integer not null references cats(id),
+            body text not null
+        )",
+    )?;
+
+    let repo = CatRepo::new(&conn);
+    for (name, note) in NOTES {
+        let cat = repo.create(name, "Grey")?;
+        conn.execute(
+            "insert into cat_notes (cat_id, body) values (?1, ?2)",
+            params![cat.id, note],
+        )?;
+    }
+
+    // 已有的备注在创建索引时重建进索引
+    let index = FtsIndex::new("cat_notes", "id", &["body"]);
+    index.ensure(&conn)?;
+    index.ensure(&conn)?;
+
+    let ids = |query: &Query| -> rusqlite::Result<Vec<i64>> {
+        let hits = index.search(&conn, query, 10)?;
+        Ok(hits.into_iter().map(|hit| hit.rowid).collect())
+    };
+    let sorted = |query: &Query| -> rusqlite::Result<Vec<i64>> {
+        let mut ids = ids(query)?;
+        ids.sort_unstable();
+        Ok(ids)
+    };
+
+    assert_eq!(sorted(&Query::phrase("laundry basket"))?, [2]);
+    assert_eq!(sorted(&Query::prefix("chase"))?, [1, 3]);
+    assert_eq!(
+        sorted(&Query::term("laundry").or(Query::term("mice")))?,
+        [2, 3, 4]
+    );
+    assert_eq!(
+        sorted(&Query::term("laundry").not(Query::term("lazy")))?,
+        [4]
+    );
+    assert_eq!(
+        sorted(
+            &Query::prefix("cha")
+                .and(Query::term("garden"))
+                .column("body")
+        )?,
+        [3]
+    );
+    assert_eq!(sorted(&Query::raw("cat NOT shy"))?, [2]);
+    // 引号会被转义，不会破坏查询语法
+    assert_eq!(sorted(&Query::term("\"laser"))?, [1]);
+
+    // 在更短的文本中出现次数更多的词排在前面
+    conn.execute(
+        "insert into cat_notes (cat_id, body) values (1, 'Laser laser laser!')",
+        [],
+    )?;
+    assert_eq!(ids(&Query::term("laser"))?, [6, 1]);
+
+    let hits = index.search(&conn, &Query::term("laundry"), 10)?;
+    for hit in &hits {
+        println!(
+            "{:>2} {:8.4} {}",
+            hit.rowid,
+            hit.rank,
+            hit.highlight("[", "]")
+        );
+    }
+    assert!(hits.iter().all(|hit| hit.snippet.contains(HIGHLIGHT_OPEN)));
+    assert!(hits[0].fragments().contains(&("laundry", true)));
+
+    // 更新和删除通过触发器同步到索引
+    conn.execute(
+        "update cat_notes set body = 'Now sleeps in a cardboard box' where id = 2",
+        [],
+    )?;
+    assert_eq!(sorted(&Query::term("laundry"))?, [4]);
+    assert_eq!(sorted(&Query::term("cardboard"))?, [2]);
+    conn.execute("delete from cat_notes where id = 4", [])?;
+    assert!(sorted(&Query::term("laundry"))?.is_empty());
+
+    let integrity = conn.execute(
+        &format!(
+            "insert into {0} ({0}) values ('integrity-check')",
+            index.name()
+        ),
+        [],
+    );
+    assert!(integrity.is_ok());
+
+    index.drop_index(&conn)?;
+    assert!(!index.exists(&conn)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notes(bodies: &[&str]) -> (Connection, FtsIndex) {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("create table notes (id integer primary key, title text, body text)")
+            .unwrap();
+        for body in bodies {
+            conn.execute(
+                "insert into notes (title, body) values ('note', ?1)",
+                [body],
+            )
+            .unwrap();
+        }
+        let index = FtsIndex::new("notes", "id", &["body"]);
+        index.ensure(&conn).unwrap();
+        (conn, index)
+    }
+
+    fn ids(conn: &Connection, index: &FtsIndex, query: &Query) -> Vec<i64> {
+        let hits = index.search(conn, query, 10).unwrap();
+        hits.into_iter().map(|hit| hit.rowid).collect()
+    }
+
+    #[test]
+    fn results_are_ranked_by_bm25() {
+        let (conn, index) = notes(&[
+            "a long note that mentions the laser only once among many other words",
+            "laser laser laser",
+            "the laser dot",
+            "no match here",
+        ]);
+
+        let hits = index.search(&conn, &Query::term("laser"), 10).unwrap();
+        let rowids: Vec<i64> = hits.iter().map(|hit| hit.rowid).collect();
+        assert_eq!(rowids, [2, 3, 1]);
+        assert!(hits.windows(2).all(|pair| pair[0].rank <= pair[1].rank));
+        assert_eq!(ids(&conn, &index, &Query::term("laser"))[..1], [2]);
+    }
+
+    #[test]
+    fn limit_keeps_best_results() {
+        let (conn, index) = notes(&["cat", "cat cat", "cat cat cat"]);
+        let hits = index.search(&conn, &Query::term("cat"), 1).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].rowid, 3);
+    }
+
+    #[test]
+    fn phrase_prefix_and_boolean_queries() {
+        let (conn, index) = notes(&[
+            "sleeps in the laundry basket",
+            "the basket of laundry",
+            "chases mice in the garden",
+            "chased the laser",
+        ]);
+        let sorted = |query: Query| {
+            let mut ids = ids(&conn, &index, &query);
+            ids.sort_unstable();
+            ids
+        };
+
+        assert_eq!(sorted(Query::phrase("laundry basket")), [1]);
+        assert_eq!(sorted(Query::prefix("chase")), [3, 4]);
+        assert_eq!(
+            sorted(Query::term("laundry").and(Query::term("basket"))),
+            [1, 2]
+        );
+        assert_eq!(sorted(Query::term("mice").or(Query::term("laser"))), [3, 4]);
+        assert_eq!(sorted(Query::prefix("chase").not(Query::term("mice"))), [4]);
+        assert_eq!(sorted(Query::term("garden").column("body")), [3]);
+        // 引号被转义，不会破坏查询语法，分词时被忽略
+        assert_eq!(sorted(Query::term("\"laundry")), [1, 2]);
+    }
+
+    #[test]
+    fn snippets_mark_matched_terms() {
+        let (conn, index) = notes(&["Bounces on the sofa and chases the red laser dot"]);
+        let hit = &index.search(&conn, &Query::term("laser"), 10).unwrap()[0];
+        assert!(hit.highlight("[", "]").contains("[laser]"));
+        assert!(hit.fragments().contains(&("laser", true)));
+        assert!(hit.fragments().contains(&(" dot", false)));
+    }
+
+    #[test]
+    fn triggers_keep_index_in_sync() {
+        let (conn, index) = notes(&["sleeps in the laundry"]);
+        conn.execute("insert into notes (body) values ('laundry again')", [])
+            .unwrap();
+        assert_eq!(ids(&conn, &index, &Query::term("again")), [2]);
+
+        conn.execute("update notes set body = 'cardboard box' where id = 1", [])
+            .unwrap();
+        assert_eq!(ids(&conn, &index, &Query::term("laundry")), [2]);
+        assert_eq!(ids(&conn, &index, &Query::term("cardboard")), [1]);
+
+        conn.execute("delete from notes where id = 2", []).unwrap();
+        assert!(ids(&conn, &index, &Query::term("laundry")).is_empty());
+        conn.execute(
+            "insert into notes_fts (notes_fts) values ('integrity-check')",
+            [],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn ensure_checks_existing_columns() {
+        let (conn, index) = notes(&["laundry"]);
+        index.ensure(&conn).unwrap();
+        index.check(&conn).unwrap();
+
+        let other = FtsIndex::new("notes", "id", &["title", "body"]);
+        assert!(matches!(
+            other.ensure(&conn),
+            Err(SearchError::Mismatch { existing, .. }) if existing == ["body"]
+        ));
+        assert!(matches!(
+            other.check(&conn),
+            Err(SearchError::Mismatch { .. })
+        ));
+
+        index.drop_index(&conn).unwrap();
+        assert!(matches!(
+            index.check(&conn),
+            Err(SearchError::NotInstalled(_))
+        ));
+        other.ensure(&conn).unwrap();
+        assert_eq!(
+            other.installed_columns(&conn).unwrap(),
+            Some(vec!["title".to_string(), "body".to_string()])
+        );
+    }
+}
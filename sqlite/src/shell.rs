@@ -0,0 +1,585 @@
+//! # 交互式SQL命令行
+//! `Shell`接收一行行输入：SQL语句以`;`结束，可以跨多行；以`.`开头的是点命令：
+//! - `.tables`、`.schema [表]`列出表和建表语句；
+//! - `.mode table|csv|json`切换查询结果的格式，`table`为对齐的表格；
+//! - `.import 文件 表`导入CSV，`.timer on|off`显示执行时间。
+//!
+//! 出错时只返回错误，已经输入的语句被丢弃，`Shell`可以继续使用。
+//! 读取输入、行编辑和历史记录在`src/main.rs`中。
+use crate::csv_table::{self, CsvTableError, Format, ImportOptions};
+use crate::migrations;
+use rusqlite::types::ValueRef;
+use rusqlite::Connection;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::time::Instant;
+
+#[derive(Debug)]
+pub enum ShellError {
+    Sqlite(rusqlite::Error),
+    CsvTable(CsvTableError),
+    Io(io::Error),
+    /// 点命令的用法不正确
+    Usage(String),
+}
+
+impl fmt::Display for ShellError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShellError::Sqlite(err) => write!(f, "{}", err),
+            ShellError::CsvTable(err) => write!(f, "{}", err),
+            ShellError::Io(err) => write!(f, "读写错误：{}", err),
+            ShellError::Usage(usage) => write!(f, "用法：{}", usage),
+        }
+    }
+}
+
+impl Error for ShellError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ShellError::Sqlite(err) => Some(err),
+            ShellError::CsvTable(err) => Some(err),
+            ShellError::Io(err) => Some(err),
+            ShellError::Usage(_) => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for ShellError {
+    fn from(err: rusqlite::Error) -> Self {
+        ShellError::Sqlite(err)
+    }
+}
+
+impl From<CsvTableError> for ShellError {
+    fn from(err: CsvTableError) -> Self {
+        ShellError::CsvTable(err)
+    }
+}
+
+impl From<io::Error> for ShellError {
+    fn from(err: io::Error) -> Self {
+        ShellError::Io(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Table,
+    Csv,
+    Json,
+}
+
+/// 处理一行输入之后的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    /// 语句还没有结束，需要继续输入
+    Pending,
+    Done,
+    Quit,
+}
+
+const HELP: &str = ".tables              列出所有的表和视图
+.schema [表]          显示建表语句
+.mode table|csv|json  查询结果的格式
+.import 文件 表        把CSV文件导入到表中
+.timer on|off         显示语句的执行时间
+.help                 显示帮助
+.quit                 退出";
+
+/// 把输入按`;`拆分为完整的语句，引号和注释中的分号不算；
+/// 返回完整的语句和最后未结束的部分。
+/// 输入结束也算行注释的结束，`select 1; -- note`之后不会再等待输入。
+pub fn split_statements(text: &str) -> (Vec<String>, String) {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut statements = vec![];
+    let mut start = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        let (offset, c) = chars[i];
+        let next = chars.get(i + 1).map(|(_, c)| *c);
+        if let Some(close) = closing(c, next) {
+            // 跳到结束符之后，没有结束符时剩下的都还没有结束
+            let from = offset + c.len_utf8();
+            match text[from..].find(close) {
+                Some(end) => {
+                    let end = from + end + close.len();
+                    while i < chars.len() && chars[i].0 < end {
+                        i += 1;
+                    }
+                    continue;
+                }
+                // 最后一个语句之后只剩行注释时，没有未结束的部分
+                None if c == '-' && text[start..offset].trim().is_empty() => {
+                    return (statements, String::new())
+                }
+                None => break,
+            }
+        }
+        if c == ';' {
+            let statement = text[start..=offset].trim();
+            // 触发器的语句体中有分号，到`end;`才结束
+            if !is_open_trigger(statement) {
+                if statement != ";" {
+                    statements.push(statement.to_string());
+                }
+                start = offset + 1;
+            }
+        }
+        i += 1;
+    }
+    (statements, text[start..].trim().to_string())
+}
+
+/// 字符串、带引号的标识符和注释的结束符
+fn closing(c: char, next: Option<char>) -> Option<&'static str> {
+    match (c, next) {
+        ('\'', _) => Some("'"),
+        ('"', _) => Some("\""),
+        ('`', _) => Some("`"),
+        ('[', _) => Some("]"),
+        ('-', Some('-')) => Some("\n"),
+        ('/', Some('*')) => Some("*/"),
+        _ => None,
+    }
+}
+
+/// 语句中的关键字和标识符（小写），跳过字符串、带引号的标识符、注释和`.`之后的列名
+fn words(statement: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut chars = statement.char_indices().peekable();
+    let mut after_dot = false;
+    while let Some((offset, c)) = chars.next() {
+        if let Some(close) = closing(c, chars.peek().map(|(_, c)| *c)) {
+            let from = offset + c.len_utf8();
+            let end = statement[from..]
+                .find(close)
+                .map_or(statement.len(), |end| from + end + close.len());
+            while chars.next_if(|(offset, _)| *offset < end).is_some() {}
+            after_dot = false;
+            continue;
+        }
+        if c.is_alphanumeric() || c == '_' {
+            let mut word: String = c.to_lowercase().collect();
+            while let Some((_, c)) = chars.next_if(|(_, c)| c.is_alphanumeric() || *c == '_') {
+                word.extend(c.to_lowercase());
+            }
+            if !after_dot {
+                words.push(word);
+            }
+        }
+        after_dot = c == '.';
+    }
+    words
+}
+
+/// `CREATE TRIGGER`的语句体还没有结束：没有遇到`BEGIN`，或者`BEGIN`/`CASE`和`END`还没有配对
+fn is_open_trigger(statement: &str) -> bool {
+    let words = words(statement);
+    let create_trigger = words.first().is_some_and(|word| word == "create")
+        && words.iter().skip(1).take(3).any(|word| word == "trigger");
+    if !create_trigger {
+        return false;
+    }
+    let mut body = false;
+    let mut depth = 0usize;
+    for word in &words {
+        match word.as_str() {
+            "begin" => {
+                body = true;
+                depth += 1;
+            }
+            "case" => depth += 1,
+            "end" => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    !body || depth > 0
+}
+
+fn display(value: ValueRef) -> String {
+    match value {
+        ValueRef::Null => "NULL".to_string(),
+        ValueRef::Integer(value) => value.to_string(),
+        ValueRef::Real(value) => value.to_string(),
+        ValueRef::Text(text) => String::from_utf8_lossy(text).to_string(),
+        ValueRef::Blob(blob) => format!("<{}字节>", blob.len()),
+    }
+}
+
+/// 把查询结果渲染为对齐的表格，数字右对齐，返回行数
+pub fn render_table<W: Write>(
+    conn: &Connection,
+    sql: &str,
+    out: &mut W,
+) -> Result<usize, ShellError> {
+    let mut stmt = conn.prepare(sql)?;
+    let names: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+    let mut rows = stmt.query([])?;
+    let mut cells: Vec<Vec<(String, bool)>> = vec![];
+    while let Some(row) = rows.next()? {
+        let mut cells_in_row = vec![];
+        for index in 0..names.len() {
+            let value = row.get_ref(index)?;
+            let numeric = matches!(value, ValueRef::Integer(_) | ValueRef::Real(_));
+            cells_in_row.push((display(value), numeric));
+        }
+        cells.push(cells_in_row);
+    }
+
+    let widths: Vec<usize> = names
+        .iter()
+        .enumerate()
+        .map(|(index, name)| {
+            cells
+                .iter()
+                .map(|row| row[index].0.chars().count())
+                .chain(Some(name.chars().count()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let border: String = widths
+        .iter()
+        .map(|width| format!("+{}", "-".repeat(width + 2)))
+        .collect::<String>()
+        + "+";
+    let line = |values: Vec<(&str, bool)>| {
+        values
+            .iter()
+            .zip(&widths)
+            .map(|((text, numeric), width)| {
+                let padding = " ".repeat(width - text.chars().count());
+                match numeric {
+                    true => format!("| {}{} ", padding, text),
+                    false => format!("| {}{} ", text, padding),
+                }
+            })
+            .collect::<String>()
+            + "|"
+    };
+
+    writeln!(out, "{}", border)?;
+    writeln!(
+        out,
+        "{}",
+        line(names.iter().map(|name| (name.as_str(), false)).collect())
+    )?;
+    writeln!(out, "{}", border)?;
+    for row in &cells {
+        writeln!(
+            out,
+            "{}",
+            line(
+                row.iter()
+                    .map(|(text, numeric)| (text.as_str(), *numeric))
+                    .collect()
+            )
+        )?;
+    }
+    if !cells.is_empty() {
+        writeln!(out, "{}", border)?;
+    }
+    Ok(cells.len())
+}
+
+pub struct Shell {
+    conn: Connection,
+    mode: Mode,
+    timer: bool,
+    buffer: String,
+}
+
+impl Shell {
+    pub fn new(conn: Connection) -> Self {
+        Shell {
+            conn,
+            mode: Mode::Table,
+            timer: false,
+            buffer: String::new(),
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// 是否有没有结束的语句，用来选择提示符
+    pub fn is_pending(&self) -> bool {
+        !self.buffer.is_empty()
+    }
+
+    /// 丢弃没有结束的语句
+    pub fn discard(&mut self) {
+        self.buffer.clear();
+    }
+
+    /// 处理一行输入，结果和提示写到`out`
+    pub fn feed<W: Write>(&mut self, line: &str, out: &mut W) -> Result<Input, ShellError> {
+        if self.buffer.is_empty() && line.trim_start().starts_with('.') {
+            return self.command(line.trim(), out);
+        }
+        if !self.buffer.is_empty() {
+            self.buffer.push('\n');
+        }
+        self.buffer.push_str(line);
+
+        let (statements, rest) = split_statements(&self.buffer);
+        self.buffer = rest;
+        if statements.is_empty() {
+            return Ok(if self.is_pending() {
+                Input::Pending
+            } else {
+                Input::Done
+            });
+        }
+        let result = statements
+            .iter()
+            .try_for_each(|statement| self.execute(statement, out));
+        if result.is_err() {
+            self.discard();
+        }
+        result?;
+        Ok(match self.is_pending() {
+            true => Input::Pending,
+            false => Input::Done,
+        })
+    }
+
+    fn execute<W: Write>(&self, sql: &str, out: &mut W) -> Result<(), ShellError> {
+        let started = Instant::now();
+        let returns_rows = self.conn.prepare(sql)?.column_count() > 0;
+        if returns_rows {
+            let count = match self.mode {
+                Mode::Table => render_table(&self.conn, sql, out)?,
+                Mode::Csv => csv_table::export_query(&self.conn, sql, Format::Csv, &mut *out)?,
+                Mode::Json => csv_table::export_query(&self.conn, sql, Format::Json, &mut *out)?,
+            };
+            if self.mode == Mode::Table {
+                writeln!(out, "({}行)", count)?;
+            }
+        } else {
+            let changed = self.conn.execute(sql, [])?;
+            if changed > 0 {
+                writeln!(out, "修改了{}行", changed)?;
+            }
+        }
+        if self.timer {
+            writeln!(out, "用时：{:?}", started.elapsed())?;
+        }
+        Ok(())
+    }
+
+    fn command<W: Write>(&mut self, line: &str, out: &mut W) -> Result<Input, ShellError> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        match args.as_slice() {
+            [".quit"] | [".exit"] => return Ok(Input::Quit),
+            [".help"] => writeln!(out, "{}", HELP)?,
+            [".tables"] => {
+                let mut stmt = self.conn.prepare(
+                    "select name from sqlite_master where type in ('table', 'view')
+                    and name not like 'sqlite_%' order by name",
+                )?;
+                let names = stmt
+                    .query_map([], |row| row.get::<_, String>(0))?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                writeln!(out, "{}", names.join("  "))?;
+            }
+            [".schema"] | [".schema", _] => {
+                let mut stmt = self.conn.prepare(
+                    "select sql from sqlite_master where sql is not null
+                    and (?1 is null or tbl_name = ?1) and name not like 'sqlite_%'
+                    order by tbl_name, type desc, name",
+                )?;
+                let sqls = stmt
+                    .query_map([args.get(1).copied()], |row| row.get::<_, String>(0))?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                for sql in sqls {
+                    writeln!(out, "{};", sql)?;
+                }
+            }
+            [".mode"] => writeln!(out, "{:?}", self.mode)?,
+            [".mode", mode] => {
+                self.mode = match *mode {
+                    "table" => Mode::Table,
+                    "csv" => Mode::Csv,
+                    "json" => Mode::Json,
+                    _ => return Err(ShellError::Usage(".mode table|csv|json".to_string())),
+                }
+            }
+            [".timer", "on"] => self.timer = true,
+            [".timer", "off"] => self.timer = false,
+            [".timer", ..] => return Err(ShellError::Usage(".timer on|off".to_string())),
+            [".import", path, table] => {
+                let file = File::open(path)?;
+                let report =
+                    csv_table::import_csv(&mut self.conn, file, &ImportOptions::new(table))?;
+                for rejected in &report.rejected {
+                    writeln!(out, "第{}行没有导入：{}", rejected.line, rejected.reason)?;
+                }
+                writeln!(out, "导入{}行到{}", report.inserted, table)?;
+            }
+            [".import", ..] => return Err(ShellError::Usage(".import 文件 表".to_string())),
+            _ => {
+                return Err(ShellError::Usage(format!(
+                    "未知的命令{}，输入.help查看帮助",
+                    line
+                )))
+            }
+        }
+        Ok(Input::Done)
+    }
+}
+
+/// # 用脚本驱动交互式命令行
+/// 模拟一次输入：跨行的语句、一行中的多条语句、点命令、错误之后继续执行。
+pub fn run_shell_script() -> Result<(), Box<dyn Error>> {
+    let (statements, rest) =
+        split_statements("select 'a;b' as \"x;\"; -- c;\n/* d; */ select 1;\nselect");
+    assert_eq!(
+        statements,
+        ["select 'a;b' as \"x;\";", "-- c;\n/* d; */ select 1;"]
+    );
+    assert_eq!(rest, "select");
+    let (statements, rest) =
+        split_statements("create trigger t after insert on a begin delete from b; end; select 2;");
+    assert_eq!(statements.len(), 2);
+    assert!(rest.is_empty());
+
+    let mut conn = Connection::open_in_memory()?;
+    migrations::cats().up(&mut conn, None)?;
+    let mut shell = Shell::new(conn);
+    let mut out = vec![];
+
+    assert_eq!(
+        shell.feed("insert into cat_colors (name)", &mut out)?,
+        Input::Pending
+    );
+    assert!(shell.is_pending());
+    assert_eq!(
+        shell.feed("values ('Blue'), ('Black');", &mut out)?,
+        Input::Done
+    );
+    shell.feed(
+        "insert into cats (name, color_id) values ('Tigger', 1); insert into cats (name, color_id) values ('Oreo', 2);",
+        &mut out,
+    )?;
+    out.clear();
+
+    shell.feed(
+        "select c.id, c.name, cc.name as color from cats c join cat_colors cc on cc.id = c.color_id;",
+        &mut out,
+    )?;
+    let table = String::from_utf8(out.clone())?;
+    print!("{}", table);
+    assert_eq!(
+        table,
+        "+----+--------+-------+
+| id | name   | color |
++----+--------+-------+
+|  1 | Tigger | Blue  |
+|  2 | Oreo   | Black |
++----+--------+-------+
+(2行)
+"
+    );
+
+    // 错误不会结束命令行，未完成的语句被丢弃
+    out.clear();
+    assert!(shell.feed("select * from missing;", &mut out).is_err());
+    assert!(shell.feed(".mode yaml", &mut out).is_err());
+    assert!(!shell.is_pending());
+
+    shell.feed(".mode csv", &mut out)?;
+    shell.feed(".tables", &mut out)?;
+    shell.feed("select name from cats order by id;", &mut out)?;
+    assert_eq!(
+        String::from_utf8(out.clone())?,
+        "cat_colors  cats  schema_migrations\nname\nTigger\nOreo\n"
+    );
+
+    out.clear();
+    shell.feed(".schema cats", &mut out)?;
+    assert!(String::from_utf8(out.clone())?.starts_with("CREATE TABLE cats ("));
+
+    out.clear();
+    shell.feed(".mode json", &mut out)?;
+    shell.feed(".timer on", &mut out)?;
+    shell.feed("select count(*) as cats from cats;", &mut out)?;
+    let json = String::from_utf8(out.clone())?;
+    assert!(json.starts_with("[\n  {\"cats\":2}\n]\n用时："));
+
+    assert_eq!(shell.feed(".quit", &mut out)?, Input::Quit);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trailing_line_comment_ends_with_input() {
+        let (statements, rest) = split_statements("select 1; -- note");
+        assert_eq!(statements, ["select 1;"]);
+        assert!(rest.is_empty());
+
+        let (statements, rest) = split_statements("-- only a comment");
+        assert!(statements.is_empty());
+        assert!(rest.is_empty());
+
+        // 语句还没有结束时，注释留到下一行再处理
+        let (statements, rest) = split_statements("select 1 -- note");
+        assert!(statements.is_empty());
+        assert_eq!(rest, "select 1 -- note");
+    }
+
+    #[test]
+    fn trigger_body_ends_at_matching_end() {
+        let trigger = "create trigger name_cats after insert on cats begin
+            update cats set name = case when new.name = '' then 'end;' else new.name end;
+            insert into legend (name) values (new.name);
+            select new.end from legend;
+        end;";
+        let (statements, rest) = split_statements(&format!("{} select 1;", trigger));
+        assert_eq!(statements, [trigger, "select 1;"]);
+        assert!(rest.is_empty());
+
+        // 还在语句体中时等待更多输入
+        let (statements, rest) = split_statements(
+            "create trigger t after insert on cats begin select case when 1 then 2 end;",
+        );
+        assert!(statements.is_empty());
+        assert!(rest.starts_with("create trigger"));
+
+        // WHEN子句中的CASE在BEGIN之前结束
+        let (statements, _) = split_statements(
+            "create temp trigger t after insert on cats when case new.id when 1 then 1 end begin select 1; end; select 2;",
+        );
+        assert_eq!(statements.len(), 2);
+
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("create table cats (id integer primary key, name text, \"end\" text); create table legend (name text, \"end\" text)")
+            .unwrap();
+        conn.execute_batch(trigger).unwrap();
+    }
+
+    #[test]
+    fn shell_is_not_pending_after_trailing_comment() {
+        let mut shell = Shell::new(Connection::open_in_memory().unwrap());
+        let mut out = vec![];
+        assert_eq!(
+            shell.feed("select 1; -- note", &mut out).unwrap(),
+            Input::Done
+        );
+        assert!(!shell.is_pending());
+
+        assert_eq!(
+            shell.feed("select 2 -- note", &mut out).unwrap(),
+            Input::Pending
+        );
+        assert_eq!(shell.feed(";", &mut out).unwrap(), Input::Done);
+        assert!(!shell.is_pending());
+    }
+}
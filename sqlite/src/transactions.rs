@@ -0,0 +1,412 @@
+//! # 事务
+//! `with_transaction`在`conn.transaction()`的基础上增加了：
+//! - 遇到`SQLITE_BUSY`/`SQLITE_LOCKED`时按指数退避重新执行整个事务；
+//! - `Tx::savepoint`支持嵌套的保存点，内层失败只回滚到保存点；
+//! - 语句失败时错误中带有出错的SQL，而不只是SQLite的错误信息。
+//!
+//! 事务中的闭包可能被执行多次，所以是`FnMut`，不应该有事务之外的副作用。
+use crate::migrations;
+use rusqlite::{Connection, ErrorCode, Params, Row, TransactionBehavior};
+use std::error::Error;
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum TxError {
+    /// 某个语句执行失败
+    Statement {
+        sql: String,
+        source: rusqlite::Error,
+    },
+    /// 开始、提交事务或者保存点失败
+    Sqlite(rusqlite::Error),
+    /// 重试之后数据库仍然忙
+    Busy { attempts: u32, source: Box<TxError> },
+}
+
+impl TxError {
+    /// SQLite的错误码，例如`ConstraintViolation`
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        match self {
+            TxError::Statement {
+                source: rusqlite::Error::SqliteFailure(err, _),
+                ..
+            }
+            | TxError::Sqlite(rusqlite::Error::SqliteFailure(err, _)) => Some(err.code),
+            TxError::Busy { source, .. } => source.error_code(),
+            _ => None,
+        }
+    }
+
+    /// 数据库被其他连接锁住，重新执行事务可能会成功
+    pub fn is_busy(&self) -> bool {
+        matches!(
+            self.error_code(),
+            Some(ErrorCode::DatabaseBusy) | Some(ErrorCode::DatabaseLocked)
+        )
+    }
+
+    /// 失败的语句
+    pub fn statement(&self) -> Option<&str> {
+        match self {
+            TxError::Statement { sql, .. } => Some(sql),
+            TxError::Busy { source, .. } => source.statement(),
+            TxError::Sqlite(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxError::Statement { sql, source } => write!(f, "执行`{}`失败：{}", sql, source),
+            TxError::Sqlite(err) => write!(f, "事务错误：{}", err),
+            TxError::Busy { attempts, source } => {
+                write!(f, "尝试{}次后数据库仍然忙：{}", attempts, source)
+            }
+        }
+    }
+}
+
+impl Error for TxError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TxError::Statement { source, .. } | TxError::Sqlite(source) => Some(source),
+            TxError::Busy { source, .. } => Some(source.as_ref()),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for TxError {
+    fn from(err: rusqlite::Error) -> Self {
+        TxError::Sqlite(err)
+    }
+}
+
+/// 数据库忙时的重试策略
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// 第一次执行之后最多再重试的次数
+    pub max_retries: u32,
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 5,
+            base_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.base_backoff
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_backoff)
+    }
+}
+
+/// 事务中的连接，记录保存点的深度
+pub struct Tx<'c> {
+    conn: &'c Connection,
+    depth: usize,
+}
+
+impl Tx<'_> {
+    pub fn execute<P: Params>(&self, sql: &str, params: P) -> Result<usize, TxError> {
+        self.conn
+            .execute(sql, params)
+            .map_err(|source| TxError::Statement {
+                sql: sql.to_string(),
+                source,
+            })
+    }
+
+    pub fn query_row<T, P, F>(&self, sql: &str, params: P, f: F) -> Result<T, TxError>
+    where
+        P: Params,
+        F: FnOnce(&Row<'_>) -> rusqlite::Result<T>,
+    {
+        self.conn
+            .query_row(sql, params, f)
+            .map_err(|source| TxError::Statement {
+                sql: sql.to_string(),
+                source,
+            })
+    }
+
+    /// 在保存点中执行`f`：成功时释放保存点，失败时回滚到保存点并返回错误，外层事务可以继续
+    pub fn savepoint<T, F>(&mut self, f: F) -> Result<T, TxError>
+    where
+        F: FnOnce(&mut Tx) -> Result<T, TxError>,
+    {
+        let name = format!("sp_{}", self.depth + 1);
+        self.conn.execute_batch(&format!("savepoint {}", name))?;
+        let mut inner = Tx {
+            conn: self.conn,
+            depth: self.depth + 1,
+        };
+        match f(&mut inner) {
+            Ok(value) => {
+                self.conn.execute_batch(&format!("release {}", name))?;
+                Ok(value)
+            }
+            Err(err) => {
+                self.conn
+                    .execute_batch(&format!("rollback to {0}; release {0}", name))?;
+                Err(err)
+            }
+        }
+    }
+}
+
+/// 在`IMMEDIATE`事务中执行`f`并提交；`f`返回错误时回滚，数据库忙时按`policy`重试
+pub fn with_transaction<T, F>(
+    conn: &mut Connection,
+    policy: &RetryPolicy,
+    mut f: F,
+) -> Result<T, TxError>
+where
+    F: FnMut(&mut Tx) -> Result<T, TxError>,
+{
+    let mut attempt = 0;
+    loop {
+        let result = conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(TxError::from)
+            .and_then(|tx| {
+                let value = f(&mut Tx {
+                    conn: &tx,
+                    depth: 0,
+                })?;
+                tx.commit()?;
+                Ok(value)
+            });
+
+        match result {
+            Err(err) if err.is_busy() && attempt < policy.max_retries => {
+                thread::sleep(policy.backoff(attempt));
+                attempt += 1;
+            }
+            Err(err) if err.is_busy() => {
+                return Err(TxError::Busy {
+                    attempts: attempt + 1,
+                    source: Box::new(err),
+                })
+            }
+            result => return result,
+        }
+    }
+}
+
+/// 替换所有颜色并提交。
+/// 删除颜色时不检查`cats`中的引用，只能用于没有猫的数据库，例如内存数据库。
+pub fn successful_tx(conn: &mut Connection) -> Result<(), TxError> {
+    with_transaction(conn, &RetryPolicy::default(), |tx| {
+        tx.execute("delete from cat_colors", [])?;
+        tx.execute("insert into cat_colors (name) values(?1)", ["lavender"])?;
+        tx.execute("insert into cat_colors (name) values(?1)", ["blue"])?;
+        Ok(())
+    })
+}
+
+/// 第二次插入`lavender`违反UNIQUE约束，整个事务回滚，`cat_colors`保持不变
+pub fn rolled_back_tx(conn: &mut Connection) -> Result<(), TxError> {
+    with_transaction(conn, &RetryPolicy::default(), |tx| {
+        tx.execute("delete from cat_colors", [])?;
+        tx.execute("insert into cat_colors (name) values(?1)", ["lavender"])?;
+        tx.execute("insert into cat_colors (name) values(?1)", ["blue"])?;
+        tx.execute("insert into cat_colors (name) values(?1)", ["lavender"])?;
+        Ok(())
+    })
+}
+
+fn color_names(conn: &Connection) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare("select name from cat_colors order by id")?;
+    let names = stmt.query_map([], |row| row.get(0))?;
+    names.collect()
+}
+
+/// # 事务的提交、回滚、保存点和重试
+pub fn transaction_helpers() -> Result<(), Box<dyn Error>> {
+    let mut conn = Connection::open_in_memory()?;
+    migrations::cats().up(&mut conn, None)?;
+    conn.execute_batch("insert into cat_colors (name) values ('Blue'), ('Black')")?;
+
+    // 失败的事务不改变cat_colors，错误中带有出错的语句
+    let err = rolled_back_tx(&mut conn).unwrap_err();
+    println!("{}", err);
+    assert_eq!(
+        err.statement(),
+        Some("insert into cat_colors (name) values(?1)")
+    );
+    assert_eq!(err.error_code(), Some(ErrorCode::ConstraintViolation));
+    assert_eq!(color_names(&conn)?, ["Blue", "Black"]);
+
+    successful_tx(&mut conn)?;
+    assert_eq!(color_names(&conn)?, ["lavender", "blue"]);
+
+    // 内层保存点失败只回滚内层，外层的插入照常提交
+    let inner_failed = with_transaction(&mut conn, &RetryPolicy::default(), |tx| {
+        tx.execute("insert into cat_colors (name) values ('Tabby')", [])?;
+        let inner = tx.savepoint(|tx| {
+            tx.execute("insert into cat_colors (name) values ('Ginger')", [])?;
+            tx.savepoint(|tx| tx.execute("insert into cat_colors (name) values ('Calico')", []))?;
+            tx.execute("insert into cat_colors (name) values ('blue')", [])
+        });
+        Ok(inner.is_err())
+    })?;
+    assert!(inner_failed);
+    assert_eq!(color_names(&conn)?, ["lavender", "blue", "Tabby"]);
+
+    // 另一个连接持有写锁时，不等待的连接通过重试完成事务
+    // 临时目录在函数返回或者panic时删除
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("tx-busy.db");
+    let mut writer = Connection::open(&path)?;
+    migrations::cats().up(&mut writer, None)?;
+    let mut waiting = Connection::open(&path)?;
+    waiting.busy_timeout(Duration::ZERO)?;
+
+    writer.execute_batch("begin immediate; insert into cat_colors (name) values ('Grey')")?;
+    let holder = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        writer.execute_batch("commit")
+    });
+    // 写锁在BEGIN IMMEDIATE时就拿不到，所以闭包只执行一次，等待的时间说明发生了重试
+    let started = std::time::Instant::now();
+    with_transaction(&mut waiting, &RetryPolicy::default(), |tx| {
+        tx.execute("insert into cat_colors (name) values ('White')", [])
+    })?;
+    holder.join().expect("持有写锁的线程不应该panic")?;
+    println!("数据库忙，重试后用时{:?}完成", started.elapsed());
+    assert!(started.elapsed() >= Duration::from_millis(40));
+    assert_eq!(color_names(&waiting)?, ["Grey", "White"]);
+
+    // 不重试时直接报告数据库忙
+    waiting.execute_batch("begin immediate")?;
+    let mut other = Connection::open(&path)?;
+    other.busy_timeout(Duration::ZERO)?;
+    let no_retry = RetryPolicy {
+        max_retries: 0,
+        ..RetryPolicy::default()
+    };
+    match with_transaction(&mut other, &no_retry, |tx| {
+        tx.execute("delete from cats", [])
+    }) {
+        Err(TxError::Busy { attempts: 1, .. }) => {}
+        other => panic!("应该报告数据库忙：{:?}", other),
+    }
+    waiting.execute_batch("rollback")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cats_db() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::cats().up(&mut conn, None).unwrap();
+        conn.execute_batch("insert into cat_colors (name) values ('Blue'), ('Black')")
+            .unwrap();
+        conn
+    }
+
+    #[test]
+    fn rolled_back_tx_leaves_colors_unchanged() {
+        let mut conn = cats_db();
+        assert!(rolled_back_tx(&mut conn).is_err());
+        assert_eq!(color_names(&conn).unwrap(), ["Blue", "Black"]);
+    }
+
+    #[test]
+    fn error_reports_the_failing_statement() {
+        let mut conn = cats_db();
+        let err = with_transaction(&mut conn, &RetryPolicy::default(), |tx| {
+            tx.execute("insert into cat_colors (name) values ('Tabby')", [])?;
+            tx.execute("insert into cat_colors (name) values ('Blue')", [])
+        })
+        .unwrap_err();
+        assert_eq!(
+            err.statement(),
+            Some("insert into cat_colors (name) values ('Blue')")
+        );
+        assert_eq!(err.error_code(), Some(ErrorCode::ConstraintViolation));
+        assert!(err
+            .to_string()
+            .contains("insert into cat_colors (name) values ('Blue')"));
+        assert_eq!(color_names(&conn).unwrap(), ["Blue", "Black"]);
+    }
+
+    #[test]
+    fn nested_savepoint_rolls_back_without_undoing_outer() {
+        let mut conn = cats_db();
+        let inner = with_transaction(&mut conn, &RetryPolicy::default(), |tx| {
+            tx.execute("insert into cat_colors (name) values ('Tabby')", [])?;
+            let inner = tx.savepoint(|tx| {
+                tx.savepoint(|tx| {
+                    tx.execute("insert into cat_colors (name) values ('Calico')", [])
+                })?;
+                tx.execute("insert into cat_colors (name) values ('Black')", [])
+            });
+            // 保存点回滚之后外层事务还能继续执行语句
+            tx.execute("insert into cat_colors (name) values ('Ginger')", [])?;
+            Ok(inner)
+        })
+        .unwrap();
+        assert_eq!(
+            inner.unwrap_err().error_code(),
+            Some(ErrorCode::ConstraintViolation)
+        );
+        assert_eq!(
+            color_names(&conn).unwrap(),
+            ["Blue", "Black", "Tabby", "Ginger"]
+        );
+    }
+
+    #[test]
+    fn busy_database_is_retried_until_the_lock_is_released() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("busy.db");
+        let mut writer = Connection::open(&path).unwrap();
+        migrations::cats().up(&mut writer, None).unwrap();
+        let mut waiting = Connection::open(&path).unwrap();
+        waiting.busy_timeout(Duration::ZERO).unwrap();
+
+        writer
+            .execute_batch("begin immediate; insert into cat_colors (name) values ('Grey')")
+            .unwrap();
+        // 不重试时立即报告数据库忙
+        let no_retry = RetryPolicy {
+            max_retries: 0,
+            ..RetryPolicy::default()
+        };
+        let err = with_transaction(&mut waiting, &no_retry, |tx| {
+            tx.execute("insert into cat_colors (name) values ('White')", [])
+        })
+        .unwrap_err();
+        assert!(matches!(err, TxError::Busy { attempts: 1, .. }) && err.is_busy());
+
+        let holder = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            writer.execute_batch("commit")
+        });
+        let mut runs = 0;
+        with_transaction(&mut waiting, &RetryPolicy::default(), |tx| {
+            runs += 1;
+            tx.execute("insert into cat_colors (name) values ('White')", [])
+        })
+        .unwrap();
+        holder.join().unwrap().unwrap();
+        // 写锁在BEGIN IMMEDIATE时就拿不到，闭包只在拿到锁之后执行一次
+        assert_eq!(runs, 1);
+        assert_eq!(color_names(&waiting).unwrap(), ["Grey", "White"]);
+    }
+}
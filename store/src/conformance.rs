@@ -0,0 +1,172 @@
+//! # 存储的一致性检查
+//! `check`对一个空的存储执行同样的操作并检查结果，两个后端都必须通过；
+//! `tests/conformance.rs`对两个后端运行它。
+use crate::{transaction, Author, Backend, Book, PostgresStore, SqliteStore, Store, StoreError};
+use dbpostgres::config::Config;
+
+/// 对空的存储执行所有检查，结束时删除创建的数据；存储不是空的时候返回`Invalid`，不修改数据。
+/// 行为和约定不一致时panic，在测试中就是失败。
+pub fn check(store: &mut dyn Store) -> Result<(), StoreError> {
+    if !store.authors()?.is_empty() {
+        return Err(StoreError::Invalid(format!(
+            "{}中已经有作者，一致性检查需要空的存储",
+            store.backend()
+        )));
+    }
+
+    // 作者
+    let achebe = store.create_author("Chinua Achebe", "Nigeria")?;
+    let tagore = store.create_author("Rabindranath Tagore", "India")?;
+    assert_eq!(store.author(achebe.id)?, Some(achebe.clone()));
+    assert_eq!(store.authors()?, [achebe.clone(), tagore.clone()]);
+    assert!(matches!(
+        store.create_author("Chinua Achebe", "Elsewhere"),
+        Err(StoreError::Duplicate(_))
+    ));
+
+    let mut moved = achebe.clone();
+    moved.country = "United States".to_string();
+    store.update_author(&moved)?;
+    assert_eq!(store.author(achebe.id)?, Some(moved.clone()));
+    moved.name = tagore.name.clone();
+    assert!(matches!(
+        store.update_author(&moved),
+        Err(StoreError::Duplicate(_))
+    ));
+    let missing = tagore.id + 1000;
+    assert!(matches!(
+        store.update_author(&Author {
+            id: missing,
+            ..tagore.clone()
+        }),
+        Err(StoreError::NotFound { what: "作者", .. })
+    ));
+    assert_eq!(store.author(missing)?, None);
+
+    // 图书
+    let things = store.create_book("Things Fall Apart", achebe.id)?;
+    let arrow = store.create_book("Arrow of God", achebe.id)?;
+    let gitanjali = store.create_book("Gitanjali", tagore.id)?;
+    assert_eq!(store.book(things.id)?, Some(things.clone()));
+    assert_eq!(
+        store.books_by_author(achebe.id)?,
+        [arrow.clone(), things.clone()]
+    );
+    assert!(matches!(
+        store.create_book("Things Fall Apart", achebe.id),
+        Err(StoreError::Duplicate(_))
+    ));
+    // 不同作者可以有同名的书
+    let other = store.create_book("Things Fall Apart", tagore.id)?;
+    assert_eq!(
+        store.books_by_author(tagore.id)?,
+        [gitanjali, other.clone()]
+    );
+    assert!(matches!(
+        store.create_book("Nowhere", missing),
+        Err(StoreError::NotFound { what: "作者", .. })
+    ));
+
+    let renamed = Book {
+        title: "No Longer at Ease".to_string(),
+        ..arrow.clone()
+    };
+    store.update_book(&renamed)?;
+    assert_eq!(store.book(arrow.id)?, Some(renamed.clone()));
+    assert!(matches!(
+        store.update_book(&Book {
+            title: things.title.clone(),
+            ..renamed.clone()
+        }),
+        Err(StoreError::Duplicate(_))
+    ));
+    assert!(matches!(
+        store.update_book(&Book {
+            author_id: missing,
+            ..renamed.clone()
+        }),
+        Err(StoreError::NotFound { what: "作者", .. })
+    ));
+    store.delete_book(other.id)?;
+    assert!(matches!(
+        store.delete_book(other.id),
+        Err(StoreError::NotFound { what: "图书", .. })
+    ));
+    assert!(matches!(
+        store.delete_author(achebe.id),
+        Err(StoreError::HasBooks { .. })
+    ));
+
+    // 事务：成功时提交
+    let (soyinka, ake) = transaction(store, |store| {
+        let author = store.create_author("Wole Soyinka", "Nigeria")?;
+        let book = store.create_book("Aké", author.id)?;
+        Ok((author, book))
+    })?;
+    assert_eq!(
+        store.books_by_author(soyinka.id)?,
+        std::slice::from_ref(&ake)
+    );
+
+    // 失败时回滚，事务中出错之后的操作也被回滚
+    let before = store.authors()?;
+    let result = transaction(store, |store| {
+        let author = store.create_author("Anita Nair", "India")?;
+        store.create_book("Ladies Coupé", author.id)?;
+        store.create_author("Wole Soyinka", "Nigeria")?;
+        Ok(())
+    });
+    assert!(matches!(result, Err(StoreError::Duplicate(_))));
+    assert_eq!(store.authors()?, before);
+
+    // 出错的操作不影响同一个事务中之后的操作
+    store.begin()?;
+    assert!(store.create_author("Wole Soyinka", "Nigeria").is_err());
+    let nair = store.create_author("Anita Nair", "India")?;
+    store.commit()?;
+    assert_eq!(store.author(nair.id)?, Some(nair.clone()));
+
+    store.begin()?;
+    store.delete_book(ake.id)?;
+    store.rollback()?;
+    assert_eq!(store.book(ake.id)?, Some(ake.clone()));
+
+    // 清理
+    for author in store.authors()? {
+        for book in store.books_by_author(author.id)? {
+            store.delete_book(book.id)?;
+        }
+        store.delete_author(author.id)?;
+    }
+    assert!(store.authors()?.is_empty());
+    assert_eq!(store.book(things.id)?, None);
+    Ok(())
+}
+
+/// # 两个后端的一致性检查
+/// 内存中的SQLite和本地的Postgres执行同一组检查；Postgres使用单独的schema，最后删除。
+pub fn run_conformance(config: &Config) -> Result<(), StoreError> {
+    let mut sqlite = SqliteStore::open_in_memory()?;
+    check(&mut sqlite)?;
+    println!("{}通过一致性检查", sqlite.backend());
+
+    let schema = format!("store_conformance_{}", std::process::id());
+    let mut postgres = PostgresStore::connect(config, Some(&schema))?;
+    let result = check(&mut postgres);
+    config
+        .connect()?
+        .batch_execute(&format!("DROP SCHEMA {} CASCADE", schema))?;
+    result?;
+    println!("{}通过一致性检查", postgres.backend());
+    Ok(())
+}
+
+/// # 按配置选择存储
+/// 应用代码只使用`dyn Store`，后端由`STORE_URL`决定。
+pub fn open_configured_store() -> Result<(), StoreError> {
+    let backend = Backend::from_env()?;
+    let mut store = backend.open()?;
+    let authors = store.authors()?;
+    println!("{}中有{}个作者", store.backend(), authors.len());
+    Ok(())
+}
@@ -0,0 +1,16 @@
+use dbpostgres::config::Config;
+use store::conformance;
+
+fn main() {
+    match Config::from_env() {
+        Ok(config) => {
+            if let Err(err) = conformance::run_conformance(&config) {
+                println!("一致性检查错误：{}", err);
+            }
+        }
+        Err(err) => println!("加载连接配置错误：{}", err),
+    }
+    if let Err(err) = conformance::open_configured_store() {
+        println!("打开存储错误：{}", err);
+    }
+}
@@ -0,0 +1,197 @@
+//! # Postgres存储
+//! 使用`dbpostgres`的连接配置，表由`dbpostgres::migrations`创建。
+//! 重名和外键都先检查再修改，出错时不会让所在的事务失效。
+use crate::{Author, Book, Store, StoreError};
+use dbpostgres::config::Config;
+use dbpostgres::migrations;
+use postgres::{Client, Row};
+
+pub struct PostgresStore {
+    client: Client,
+}
+
+fn author(row: &Row) -> Author {
+    Author {
+        id: row.get("id"),
+        name: row.get("name"),
+        country: row.get("country"),
+    }
+}
+
+fn book(row: &Row) -> Book {
+    Book {
+        id: row.get("id"),
+        title: row.get("title"),
+        author_id: row.get("author_id"),
+    }
+}
+
+impl PostgresStore {
+    /// 连接并执行迁移；`schema`不为空时使用这个schema，不影响`public`中的表
+    pub fn connect(config: &Config, schema: Option<&str>) -> Result<Self, StoreError> {
+        let mut client = config.connect()?;
+        if let Some(schema) = schema {
+            client.batch_execute(&format!(
+                "CREATE SCHEMA IF NOT EXISTS {0}; SET search_path TO {0}",
+                schema
+            ))?;
+        }
+        migrations::library().up(&mut client, None)?;
+        Ok(PostgresStore { client })
+    }
+
+    fn exists(&mut self, table: &str, id: i64) -> Result<bool, StoreError> {
+        let sql = format!("SELECT count(*) FROM {} WHERE id = $1::bigint", table);
+        let count: i64 = self.client.query_one(sql.as_str(), &[&id])?.get(0);
+        Ok(count > 0)
+    }
+}
+
+impl Store for PostgresStore {
+    fn backend(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn create_author(&mut self, name: &str, country: &str) -> Result<Author, StoreError> {
+        let row = self.client.query_opt(
+            "INSERT INTO author (name, country) VALUES ($1, $2) ON CONFLICT DO NOTHING
+            RETURNING id::bigint AS id, name, country",
+            &[&name, &country],
+        )?;
+        row.as_ref()
+            .map(author)
+            .ok_or_else(|| StoreError::Duplicate(format!("作者{}", name)))
+    }
+
+    fn author(&mut self, id: i64) -> Result<Option<Author>, StoreError> {
+        let row = self.client.query_opt(
+            "SELECT id::bigint AS id, name, country FROM author WHERE id = $1::bigint",
+            &[&id],
+        )?;
+        Ok(row.as_ref().map(author))
+    }
+
+    fn authors(&mut self) -> Result<Vec<Author>, StoreError> {
+        let rows = self.client.query(
+            "SELECT id::bigint AS id, name, country FROM author ORDER BY id",
+            &[],
+        )?;
+        Ok(rows.iter().map(author).collect())
+    }
+
+    fn update_author(&mut self, author: &Author) -> Result<(), StoreError> {
+        let changed = self.client.execute(
+            "UPDATE author SET name = $2, country = $3 WHERE id = $1::bigint
+            AND NOT EXISTS (SELECT 1 FROM author WHERE name = $2 AND id <> $1::bigint)",
+            &[&author.id, &author.name, &author.country],
+        )?;
+        match (changed, self.exists("author", author.id)?) {
+            (0, true) => Err(StoreError::Duplicate(format!("作者{}", author.name))),
+            (0, false) => Err(StoreError::NotFound {
+                what: "作者",
+                id: author.id,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn delete_author(&mut self, id: i64) -> Result<(), StoreError> {
+        let books: i64 = self
+            .client
+            .query_one(
+                "SELECT count(*) FROM book WHERE author_id = $1::bigint",
+                &[&id],
+            )?
+            .get(0);
+        if books > 0 {
+            return Err(StoreError::HasBooks { author_id: id });
+        }
+        match self
+            .client
+            .execute("DELETE FROM author WHERE id = $1::bigint", &[&id])?
+        {
+            0 => Err(StoreError::NotFound { what: "作者", id }),
+            _ => Ok(()),
+        }
+    }
+
+    fn create_book(&mut self, title: &str, author_id: i64) -> Result<Book, StoreError> {
+        if !self.exists("author", author_id)? {
+            return Err(StoreError::NotFound {
+                what: "作者",
+                id: author_id,
+            });
+        }
+        let row = self.client.query_opt(
+            "INSERT INTO book (title, author_id) VALUES ($1, $2::bigint) ON CONFLICT DO NOTHING
+            RETURNING id::bigint AS id, title, author_id::bigint AS author_id",
+            &[&title, &author_id],
+        )?;
+        row.as_ref()
+            .map(book)
+            .ok_or_else(|| StoreError::Duplicate(format!("图书{}", title)))
+    }
+
+    fn book(&mut self, id: i64) -> Result<Option<Book>, StoreError> {
+        let row = self.client.query_opt(
+            "SELECT id::bigint AS id, title, author_id::bigint AS author_id
+            FROM book WHERE id = $1::bigint",
+            &[&id],
+        )?;
+        Ok(row.as_ref().map(book))
+    }
+
+    fn books_by_author(&mut self, author_id: i64) -> Result<Vec<Book>, StoreError> {
+        let rows = self.client.query(
+            "SELECT id::bigint AS id, title, author_id::bigint AS author_id
+            FROM book WHERE author_id = $1::bigint ORDER BY title",
+            &[&author_id],
+        )?;
+        Ok(rows.iter().map(book).collect())
+    }
+
+    fn update_book(&mut self, book: &Book) -> Result<(), StoreError> {
+        if !self.exists("author", book.author_id)? {
+            return Err(StoreError::NotFound {
+                what: "作者",
+                id: book.author_id,
+            });
+        }
+        let changed = self.client.execute(
+            "UPDATE book SET title = $2, author_id = $3::bigint WHERE id = $1::bigint
+            AND NOT EXISTS (SELECT 1 FROM book
+                WHERE title = $2 AND author_id = $3::bigint AND id <> $1::bigint)",
+            &[&book.id, &book.title, &book.author_id],
+        )?;
+        match (changed, self.exists("book", book.id)?) {
+            (0, true) => Err(StoreError::Duplicate(format!("图书{}", book.title))),
+            (0, false) => Err(StoreError::NotFound {
+                what: "图书",
+                id: book.id,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn delete_book(&mut self, id: i64) -> Result<(), StoreError> {
+        match self
+            .client
+            .execute("DELETE FROM book WHERE id = $1::bigint", &[&id])?
+        {
+            0 => Err(StoreError::NotFound { what: "图书", id }),
+            _ => Ok(()),
+        }
+    }
+
+    fn begin(&mut self) -> Result<(), StoreError> {
+        Ok(self.client.batch_execute("BEGIN")?)
+    }
+
+    fn commit(&mut self) -> Result<(), StoreError> {
+        Ok(self.client.batch_execute("COMMIT")?)
+    }
+
+    fn rollback(&mut self) -> Result<(), StoreError> {
+        Ok(self.client.batch_execute("ROLLBACK")?)
+    }
+}
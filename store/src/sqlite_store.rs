@@ -0,0 +1,205 @@
+//! # SQLite存储
+//! 表在打开数据库时创建，并且打开外键检查。
+use crate::{Author, Book, Store, StoreError};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::path::Path;
+
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+fn author(row: &Row) -> rusqlite::Result<Author> {
+    Ok(Author {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        country: row.get("country")?,
+    })
+}
+
+fn book(row: &Row) -> rusqlite::Result<Book> {
+    Ok(Book {
+        id: row.get("id")?,
+        title: row.get("title")?,
+        author_id: row.get("author_id")?,
+    })
+}
+
+impl SqliteStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+        SqliteStore::new(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, StoreError> {
+        SqliteStore::new(Connection::open_in_memory()?)
+    }
+
+    fn new(conn: Connection) -> Result<Self, StoreError> {
+        conn.execute_batch(
+            "PRAGMA foreign_keys = ON;
+            CREATE TABLE IF NOT EXISTS author (
+                id      INTEGER PRIMARY KEY,
+                name    TEXT NOT NULL UNIQUE,
+                country TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS book (
+                id          INTEGER PRIMARY KEY,
+                title       TEXT NOT NULL,
+                author_id   INTEGER NOT NULL REFERENCES author,
+                UNIQUE (author_id, title)
+            );",
+        )?;
+        Ok(SqliteStore { conn })
+    }
+
+    fn exists(&self, table: &str, id: i64) -> Result<bool, StoreError> {
+        let sql = format!("SELECT count(*) FROM {} WHERE id = ?", table);
+        let count: i64 = self.conn.query_row(&sql, [id], |row| row.get(0))?;
+        Ok(count > 0)
+    }
+}
+
+impl Store for SqliteStore {
+    fn backend(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn create_author(&mut self, name: &str, country: &str) -> Result<Author, StoreError> {
+        let inserted = self.conn.execute(
+            "INSERT OR IGNORE INTO author (name, country) VALUES (?, ?)",
+            params![name, country],
+        )?;
+        match inserted {
+            0 => Err(StoreError::Duplicate(format!("作者{}", name))),
+            _ => Ok(Author {
+                id: self.conn.last_insert_rowid(),
+                name: name.to_string(),
+                country: country.to_string(),
+            }),
+        }
+    }
+
+    fn author(&mut self, id: i64) -> Result<Option<Author>, StoreError> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT id, name, country FROM author WHERE id = ?")?;
+        Ok(stmt.query_row([id], author).optional()?)
+    }
+
+    fn authors(&mut self) -> Result<Vec<Author>, StoreError> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT id, name, country FROM author ORDER BY id")?;
+        let authors = stmt.query_map([], author)?.collect::<Result<_, _>>()?;
+        Ok(authors)
+    }
+
+    fn update_author(&mut self, author: &Author) -> Result<(), StoreError> {
+        let changed = self.conn.execute(
+            "UPDATE author SET name = ?2, country = ?3 WHERE id = ?1
+            AND NOT EXISTS (SELECT 1 FROM author WHERE name = ?2 AND id <> ?1)",
+            params![author.id, author.name, author.country],
+        )?;
+        match (changed, self.exists("author", author.id)?) {
+            (0, true) => Err(StoreError::Duplicate(format!("作者{}", author.name))),
+            (0, false) => Err(StoreError::NotFound {
+                what: "作者",
+                id: author.id,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn delete_author(&mut self, id: i64) -> Result<(), StoreError> {
+        let books: i64 = self.conn.query_row(
+            "SELECT count(*) FROM book WHERE author_id = ?",
+            [id],
+            |row| row.get(0),
+        )?;
+        if books > 0 {
+            return Err(StoreError::HasBooks { author_id: id });
+        }
+        match self.conn.execute("DELETE FROM author WHERE id = ?", [id])? {
+            0 => Err(StoreError::NotFound { what: "作者", id }),
+            _ => Ok(()),
+        }
+    }
+
+    fn create_book(&mut self, title: &str, author_id: i64) -> Result<Book, StoreError> {
+        if !self.exists("author", author_id)? {
+            return Err(StoreError::NotFound {
+                what: "作者",
+                id: author_id,
+            });
+        }
+        let inserted = self.conn.execute(
+            "INSERT OR IGNORE INTO book (title, author_id) VALUES (?, ?)",
+            params![title, author_id],
+        )?;
+        match inserted {
+            0 => Err(StoreError::Duplicate(format!("图书{}", title))),
+            _ => Ok(Book {
+                id: self.conn.last_insert_rowid(),
+                title: title.to_string(),
+                author_id,
+            }),
+        }
+    }
+
+    fn book(&mut self, id: i64) -> Result<Option<Book>, StoreError> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT id, title, author_id FROM book WHERE id = ?")?;
+        Ok(stmt.query_row([id], book).optional()?)
+    }
+
+    fn books_by_author(&mut self, author_id: i64) -> Result<Vec<Book>, StoreError> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id, title, author_id FROM book WHERE author_id = ? ORDER BY title",
+        )?;
+        let books = stmt
+            .query_map([author_id], book)?
+            .collect::<Result<_, _>>()?;
+        Ok(books)
+    }
+
+    fn update_book(&mut self, book: &Book) -> Result<(), StoreError> {
+        if !self.exists("author", book.author_id)? {
+            return Err(StoreError::NotFound {
+                what: "作者",
+                id: book.author_id,
+            });
+        }
+        let changed = self.conn.execute(
+            "UPDATE book SET title = ?2, author_id = ?3 WHERE id = ?1 AND NOT EXISTS
+            (SELECT 1 FROM book WHERE title = ?2 AND author_id = ?3 AND id <> ?1)",
+            params![book.id, book.title, book.author_id],
+        )?;
+        match (changed, self.exists("book", book.id)?) {
+            (0, true) => Err(StoreError::Duplicate(format!("图书{}", book.title))),
+            (0, false) => Err(StoreError::NotFound {
+                what: "图书",
+                id: book.id,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn delete_book(&mut self, id: i64) -> Result<(), StoreError> {
+        match self.conn.execute("DELETE FROM book WHERE id = ?", [id])? {
+            0 => Err(StoreError::NotFound { what: "图书", id }),
+            _ => Ok(()),
+        }
+    }
+
+    fn begin(&mut self) -> Result<(), StoreError> {
+        Ok(self.conn.execute_batch("BEGIN")?)
+    }
+
+    fn commit(&mut self) -> Result<(), StoreError> {
+        Ok(self.conn.execute_batch("COMMIT")?)
+    }
+
+    fn rollback(&mut self) -> Result<(), StoreError> {
+        Ok(self.conn.execute_batch("ROLLBACK")?)
+    }
+}
@@ -7,6 +7,7 @@ use select::document::Document;
 use select::predicate::Name;
 use std::collections::HashSet;
 use url::{Position, Url};
+use web::executor::{Executor, ExecutorConfig};
 
 error_chain! {
   foreign_links {
@@ -15,6 +16,14 @@ error_chain! {
       UrlParseError(url::ParseError);
       JoinError(tokio::task::JoinError);
   }
+
+  errors {
+      /// 服务器暂时无法处理：限流（429）或者服务器错误（5xx）
+      Unavailable(status: StatusCode) {
+          description("server unavailable")
+          display("server returned {}", status)
+      }
+  }
 }
 
 async fn get_base_url(url: &Url, doc: &Document) -> Result<Url> {
@@ -24,9 +33,23 @@ async fn get_base_url(url: &Url, doc: &Document) -> Result<Url> {
     Ok(base_url)
 }
 
-async fn check_link(url: &Url) -> Result<bool> {
-    let res = reqwest::get(url.as_ref()).await?;
-    Ok(res.status() != StatusCode::NOT_FOUND)
+/// 404表示链接失效；429和5xx不能说明链接的好坏，作为错误返回，由执行器重试
+async fn check_link(client: &reqwest::Client, url: &Url) -> Result<bool> {
+    let res = client.get(url.as_ref()).send().await?;
+    let status = res.status();
+    if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+        bail!(ErrorKind::Unavailable(status));
+    }
+    Ok(status != StatusCode::NOT_FOUND)
+}
+
+/// 连接失败、超时、限流和服务器错误可能是暂时的，值得重试；无效的URL、重定向过多等错误重试也没有用
+fn is_transient(err: &Error) -> bool {
+    match err.kind() {
+        ErrorKind::ReqError(err) => err.is_connect() || err.is_timeout(),
+        ErrorKind::Unavailable(_) => true,
+        _ => false,
+    }
 }
 
 #[tokio::main]
@@ -41,21 +64,32 @@ async fn main() -> Result<()> {
         .filter_map(|n| n.attr("href"))
         .filter_map(|link| base_parser.parse(link).ok())
         .collect();
-    let mut tasks = vec![];
-
-    for link in links {
-        tasks.push(tokio::spawn(async move {
-            if check_link(&link).await.unwrap() {
-                println!("{} is ok", link);
-            } else {
-                println!("{} is broken", link);
-            }
-        }));
-    }
 
-    for task in tasks {
-        task.await?
+    // 限制并发数量和每个主机的请求频率，只重试网络错误，最终失败的链接单独报告
+    let client = reqwest::Client::new();
+    let executor = Executor::new(ExecutorConfig::default());
+    let report = executor
+        .run_with_retry(
+            links,
+            move |link| {
+                let client = client.clone();
+                async move { check_link(&client, &link).await }
+            },
+            is_transient,
+        )
+        .await;
+
+    for result in &report.results {
+        match &result.outcome {
+            Ok(true) => println!("{} is ok", result.url),
+            Ok(false) => println!("{} is broken", result.url),
+            Err(err) => println!(
+                "{} could not be checked after {} attempts: {}",
+                result.url, result.attempts, err
+            ),
+        }
     }
+    println!("{}", report);
 
     Ok(())
 }
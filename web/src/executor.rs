@@ -0,0 +1,340 @@
+//! # 限流的异步任务执行器
+//! 对大量链接发起HTTP请求时，如果直接对每个任务调用`tokio::spawn`，会同时打开过多连接，
+//! 并且任何一个网络错误都会在`unwrap`时导致程序崩溃。
+//!
+//! `Executor`使用`tokio::sync::Semaphore`限制同时运行的任务数量，按主机限制请求间隔，
+//! 对失败或超时的任务使用带随机抖动的指数退避进行重试，最后把所有任务的结果汇总到`Report`中。
+use rand::Rng;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::time::{self, Instant};
+use url::Url;
+
+/// 执行器配置
+#[derive(Debug, Clone)]
+pub struct ExecutorConfig {
+    /// 同时运行的最大任务数
+    pub max_concurrency: usize,
+    /// 同一主机两次请求之间的最小间隔
+    pub per_host_interval: Duration,
+    /// 失败后的最大重试次数，不包括第一次请求
+    pub max_retries: u32,
+    /// 第一次重试前的等待时间，之后每次翻倍
+    pub base_backoff: Duration,
+    /// 重试等待时间的上限
+    pub max_backoff: Duration,
+    /// 单次请求的超时时间
+    pub timeout: Duration,
+}
+
+impl Default for ExecutorConfig {
+    fn default() -> Self {
+        ExecutorConfig {
+            max_concurrency: 8,
+            per_host_interval: Duration::from_millis(100),
+            max_retries: 2,
+            base_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// 单个任务失败的原因
+#[derive(Debug)]
+pub enum TaskError<E> {
+    /// 最后一次请求超过了配置的超时时间
+    Timeout(Duration),
+    /// 任务本身返回的错误
+    Failed(E),
+    /// 任务在执行过程中发生了panic
+    Panicked(String),
+}
+
+impl<E: fmt::Display> fmt::Display for TaskError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskError::Timeout(timeout) => write!(f, "请求超时（{:?}）", timeout),
+            TaskError::Failed(err) => write!(f, "{}", err),
+            TaskError::Panicked(msg) => write!(f, "任务异常终止：{}", msg),
+        }
+    }
+}
+
+/// 单个任务的执行结果
+#[derive(Debug)]
+pub struct TaskResult<T, E> {
+    pub url: Url,
+    /// 实际发起请求的次数
+    pub attempts: u32,
+    /// 从开始执行到得到结果的总耗时，包括重试等待
+    pub elapsed: Duration,
+    pub outcome: Result<T, TaskError<E>>,
+}
+
+/// 所有任务结果的汇总，结果顺序和输入顺序一致
+#[derive(Debug)]
+pub struct Report<T, E> {
+    pub results: Vec<TaskResult<T, E>>,
+}
+
+impl<T, E> Report<T, E> {
+    pub fn successes(&self) -> impl Iterator<Item = &TaskResult<T, E>> {
+        self.results.iter().filter(|r| r.outcome.is_ok())
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &TaskResult<T, E>> {
+        self.results.iter().filter(|r| r.outcome.is_err())
+    }
+}
+
+impl<T, E> fmt::Display for Report<T, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let retried = self.results.iter().filter(|r| r.attempts > 1).count();
+        write!(
+            f,
+            "共{}个任务，成功{}个，失败{}个，其中{}个经过重试",
+            self.results.len(),
+            self.successes().count(),
+            self.failures().count(),
+            retried
+        )
+    }
+}
+
+/// # 限流执行器
+/// 可以被克隆，克隆出的执行器共享同一个并发限制和主机限流状态。
+#[derive(Clone)]
+pub struct Executor {
+    config: Arc<ExecutorConfig>,
+    semaphore: Arc<Semaphore>,
+    next_slot: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl Executor {
+    pub fn new(config: ExecutorConfig) -> Self {
+        Executor {
+            semaphore: Arc::new(Semaphore::new(config.max_concurrency.max(1))),
+            config: Arc::new(config),
+            next_slot: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// 对每个`url`执行`job`，所有任务结束后返回汇总结果。
+    /// 单个任务的失败不会影响其他任务，任务返回的所有错误都会重试。
+    pub async fn run<I, F, Fut, T, E>(&self, urls: I, job: F) -> Report<T, E>
+    where
+        I: IntoIterator<Item = Url>,
+        F: Fn(Url) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<T, E>> + Send + 'static,
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        self.run_with_retry(urls, job, |_: &E| true).await
+    }
+
+    /// 和`run`相同，但只重试`retryable`返回`true`的错误，例如网络错误；
+    /// 404、无效的URL这类永久性的错误重试也不会成功。超时总是会重试。
+    pub async fn run_with_retry<I, F, Fut, T, E, R>(
+        &self,
+        urls: I,
+        job: F,
+        retryable: R,
+    ) -> Report<T, E>
+    where
+        I: IntoIterator<Item = Url>,
+        F: Fn(Url) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<T, E>> + Send + 'static,
+        T: Send + 'static,
+        E: Send + 'static,
+        R: Fn(&E) -> bool + Send + Sync + 'static,
+    {
+        let job = Arc::new(job);
+        let retryable = Arc::new(retryable);
+        let mut tasks = vec![];
+
+        for url in urls {
+            let executor = self.clone();
+            let job = job.clone();
+            let retryable = retryable.clone();
+            let task_url = url.clone();
+            let task = tokio::spawn(async move {
+                executor
+                    .run_one(task_url, job.as_ref(), retryable.as_ref())
+                    .await
+            });
+            tasks.push((url, task));
+        }
+
+        let mut results = Vec::with_capacity(tasks.len());
+        for (url, task) in tasks {
+            let result = task.await.unwrap_or_else(|err| TaskResult {
+                url,
+                attempts: 0,
+                elapsed: Duration::ZERO,
+                outcome: Err(TaskError::Panicked(err.to_string())),
+            });
+            results.push(result);
+        }
+
+        Report { results }
+    }
+
+    async fn run_one<F, Fut, T, E, R>(&self, url: Url, job: &F, retryable: &R) -> TaskResult<T, E>
+    where
+        F: Fn(Url) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        R: Fn(&E) -> bool,
+    {
+        let start = Instant::now();
+        let mut attempts = 0;
+
+        loop {
+            attempts += 1;
+            let outcome = {
+                // 取得并发名额之后才预约主机的请求时间，预约的时间就是实际发出请求的时间；
+                // 还没到时间就先归还名额再等待，等待慢主机时不影响其他主机的请求
+                let _permit = loop {
+                    let permit = self
+                        .semaphore
+                        .acquire()
+                        .await
+                        .expect("执行器的信号量不会被关闭");
+                    match self.reserve_host(&url) {
+                        Ok(()) => break permit,
+                        Err(slot) => {
+                            drop(permit);
+                            time::sleep_until(slot).await;
+                        }
+                    }
+                };
+
+                match time::timeout(self.config.timeout, job(url.clone())).await {
+                    Ok(Ok(value)) => Ok(value),
+                    Ok(Err(err)) => Err(TaskError::Failed(err)),
+                    Err(_) => Err(TaskError::Timeout(self.config.timeout)),
+                }
+            };
+
+            let retry = match &outcome {
+                Ok(_) => false,
+                Err(TaskError::Failed(err)) => retryable(err),
+                Err(_) => true,
+            };
+            if !retry || attempts > self.config.max_retries {
+                return TaskResult {
+                    url,
+                    attempts,
+                    elapsed: start.elapsed(),
+                    outcome,
+                };
+            }
+
+            time::sleep(self.backoff(attempts)).await;
+        }
+    }
+
+    /// 如果`url`所在的主机现在可以请求，预约下一次的时间；否则返回可以请求的时间。
+    fn reserve_host(&self, url: &Url) -> Result<(), Instant> {
+        let host = match url.host_str() {
+            Some(host) => host.to_string(),
+            None => return Ok(()),
+        };
+
+        let mut next_slot = self.next_slot.lock().unwrap();
+        let now = Instant::now();
+        match next_slot.get(&host) {
+            Some(&next) if next > now => Err(next),
+            _ => {
+                next_slot.insert(host, now + self.config.per_host_interval);
+                Ok(())
+            }
+        }
+    }
+
+    /// 第`attempt`次失败后的等待时间：指数增长并以`max_backoff`为上限，
+    /// 其中一半是固定的，另一半是随机抖动，避免大量任务在同一时刻重试。
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .config
+            .base_backoff
+            .saturating_mul(1 << (attempt - 1).min(16));
+        let capped = exponential.min(self.config.max_backoff);
+        let half = capped / 2;
+        let jitter = rand::thread_rng().gen_range(0..=half.as_millis() as u64);
+
+        half + Duration::from_millis(jitter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn per_host_interval_is_kept_when_requests_are_sent() {
+        // 两个慢主机先占满名额，同一主机的请求排队等待名额之后也不能同时发出
+        let config = ExecutorConfig {
+            max_concurrency: 2,
+            per_host_interval: Duration::from_millis(40),
+            ..ExecutorConfig::default()
+        };
+        let sent = Arc::new(Mutex::new(vec![]));
+        let record = sent.clone();
+        let urls = ["http://slow1.test/", "http://slow2.test/"]
+            .iter()
+            .map(|url| url.to_string())
+            .chain((0..4).map(|i| format!("http://example.com/{}", i)))
+            .map(|url| Url::parse(&url).unwrap());
+        let report = Executor::new(config)
+            .run(urls, move |url| {
+                let record = record.clone();
+                async move {
+                    let host = url.host_str().unwrap().to_string();
+                    record.lock().unwrap().push((host.clone(), Instant::now()));
+                    let work = if host.starts_with("slow") { 200 } else { 5 };
+                    time::sleep(Duration::from_millis(work)).await;
+                    Ok::<_, ()>(url)
+                }
+            })
+            .await;
+        assert_eq!(report.successes().count(), 6);
+
+        let mut sent: Vec<Instant> = sent
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(host, _)| host == "example.com")
+            .map(|(_, at)| *at)
+            .collect();
+        sent.sort();
+        for pair in sent.windows(2) {
+            assert!(pair[1] - pair[0] >= Duration::from_millis(40), "{:?}", sent);
+        }
+    }
+
+    #[tokio::test]
+    async fn only_retryable_errors_are_retried() {
+        let config = ExecutorConfig {
+            per_host_interval: Duration::ZERO,
+            base_backoff: Duration::from_millis(1),
+            ..ExecutorConfig::default()
+        };
+        let urls = ["http://a.test/permanent", "http://a.test/transient"]
+            .iter()
+            .map(|url| Url::parse(url).unwrap());
+        let report = Executor::new(config)
+            .run_with_retry(
+                urls,
+                |url| async move { Err::<(), _>(url.path().to_string()) },
+                |err: &String| err == "/transient",
+            )
+            .await;
+        let attempts: Vec<u32> = report.results.iter().map(|r| r.attempts).collect();
+        assert_eq!(attempts, [1, 3]);
+    }
+}
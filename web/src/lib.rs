@@ -0,0 +1,4 @@
+//! # Web编程公共模块
+//! 供`src/bin`下的各个例子共享的代码。
+
+pub mod executor;